@@ -0,0 +1,144 @@
+use std::fmt::Write as _;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Error};
+use serde::Serialize;
+use tempfile::tempdir;
+
+use crate::exec::toolchain_command;
+
+/// How the results of `bench` are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => bail!("unknown format `{}`, expected `csv` or `json`", s),
+        }
+    }
+}
+
+/// The time a compiler pass took, as reported by `-Ztime-passes`.
+#[derive(Serialize, Debug)]
+pub struct Pass {
+    pub name: String,
+    pub seconds: f64,
+}
+
+/// The measurements of building the crate with one toolchain.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct BenchResult {
+    pub commit: String,
+    pub toolchain: String,
+    pub success: bool,
+    pub wall_time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passes: Option<Vec<Pass>>,
+}
+
+/// Builds the crate at `path` from scratch with the toolchain `name`,
+/// measuring the wall time. With `time_passes`, the crate itself is built
+/// with `-Ztime-passes` and the time of each pass is recorded as well.
+pub fn bench_crate(
+    path: &Path,
+    time_passes: bool,
+    toolchains_path: &Path,
+    commit: &str,
+    name: &str,
+) -> Result<BenchResult, Error> {
+    eprintln!("building `{}` with toolchain `{}`...", path.display(), name);
+    // a fresh target directory, so nothing is reused from previous builds.
+    let target_dir = tempdir().context("failed to create the target directory")?;
+    let mut command = toolchain_command("cargo", toolchains_path, name)?;
+    if time_passes {
+        command.args(["rustc", "--", "-Ztime-passes"]);
+    } else {
+        command.arg("build");
+    }
+    let start = Instant::now();
+    let output = command
+        .env("CARGO_TARGET_DIR", target_dir.path())
+        .current_dir(path)
+        .output()
+        .context("failed to run cargo")?;
+    let wall_time = start.elapsed().as_secs_f64();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        eprint!("{}", stderr);
+    }
+    Ok(BenchResult {
+        commit: commit.to_owned(),
+        toolchain: name.to_owned(),
+        success: output.status.success(),
+        wall_time,
+        passes: if time_passes {
+            Some(stderr.lines().filter_map(parse_time_passes_line).collect())
+        } else {
+            None
+        },
+    })
+}
+
+/// Parses a line like `time: 0.012; rss: 35MB -> 36MB (+1MB)` followed by a
+/// tab and the name of the pass.
+fn parse_time_passes_line(line: &str) -> Option<Pass> {
+    let rest = line.trim_start().strip_prefix("time:")?;
+    let seconds = rest.split(';').next()?.trim().parse().ok()?;
+    let name = rest.rsplit('\t').next()?.trim();
+    Some(Pass {
+        name: name.to_owned(),
+        seconds,
+    })
+}
+
+/// Formats the results as a table with one row per measurement.
+pub fn format_results(results: &[BenchResult], format: Format) -> Result<String, Error> {
+    match format {
+        Format::Json => {
+            let mut json = serde_json::to_string_pretty(results)?;
+            json.push('\n');
+            Ok(json)
+        }
+        Format::Csv => {
+            let mut csv = String::from("commit,toolchain,success,metric,seconds\n");
+            for result in results {
+                let mut row = |metric: &str, seconds: f64| {
+                    writeln!(
+                        csv,
+                        "{},{},{},{},{}",
+                        result.commit,
+                        csv_field(&result.toolchain),
+                        result.success,
+                        csv_field(metric),
+                        seconds
+                    )
+                };
+                row("wall-time", result.wall_time)?;
+                for pass in result.passes.iter().flatten() {
+                    row(&format!("pass:{}", pass.name), pass.seconds)?;
+                }
+            }
+            Ok(csv)
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Error};
+
+use crate::exec::toolchain_command;
+
+/// Runs `test_command` with the toolchain `name`, returning whether it failed.
+fn is_bad(test_command: &[String], toolchains_path: &Path, name: &str) -> Result<bool, Error> {
+    let status = toolchain_command(&test_command[0], toolchains_path, name)?
+        .args(&test_command[1..])
+        .status()
+        .with_context(|| format!("failed to run `{}`", test_command[0]))?;
+    Ok(!status.success())
+}
+
+/// Binary-searches `commits` for the first one for which `test_command`
+/// fails, assuming every commit after a bad commit is bad as well.
+///
+/// `install` installs the toolchain of a commit and returns its name.
+pub fn bisect(
+    commits: &[String],
+    test_command: &[String],
+    toolchains_path: &Path,
+    mut install: impl FnMut(&str) -> Result<String, Error>,
+) -> Result<String, Error> {
+    ensure!(
+        commits.len() >= 2,
+        "there are no merge commits to bisect between the start and the end"
+    );
+    let mut test = |commit: &str| -> Result<bool, Error> {
+        let name = install(commit)?;
+        let bad = is_bad(test_command, toolchains_path, &name)?;
+        eprintln!(
+            "commit `{}` is {}",
+            commit,
+            if bad { "bad" } else { "good" }
+        );
+        Ok(bad)
+    };
+
+    // `good` is always a good commit and `bad` always a bad one.
+    let (mut good, mut bad) = (0, commits.len() - 1);
+    if test(&commits[good])? {
+        bail!("the start commit `{}` is already bad", commits[good]);
+    }
+    if !test(&commits[bad])? {
+        bail!("the end commit `{}` is not bad", commits[bad]);
+    }
+    while bad - good > 1 {
+        eprintln!(
+            "bisecting {} commits, about {} steps left",
+            bad - good - 1,
+            usize::BITS - (bad - good - 1).leading_zeros()
+        );
+        let mid = good + (bad - good) / 2;
+        if test(&commits[mid])? {
+            bad = mid;
+        } else {
+            good = mid;
+        }
+    }
+    Ok(commits[bad].clone())
+}
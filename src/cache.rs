@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Error};
+use reqwest::Url;
+use tempfile::NamedTempFile;
+
+use crate::channel::SUPPORTED_CHANNELS;
+use crate::lock::{lock_file, try_lock_file, FileLock};
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// Bounds the downloads kept in the cache, see [`Cache::prune`].
+#[derive(Debug, Default)]
+pub struct CacheLimits {
+    /// The downloads are removed, least recently used first, until they take
+    /// up no more than this number of bytes.
+    pub max_size: Option<u64>,
+    /// Downloads not used for longer than this are removed.
+    pub max_age: Option<Duration>,
+}
+
+impl CacheLimits {
+    pub fn is_empty(&self) -> bool {
+        self.max_size.is_none() && self.max_age.is_none()
+    }
+}
+
+/// The directory storing downloaded artifacts and metadata across runs.
+///
+/// Files are always written to a temporary file first and then atomically
+/// renamed into place, so concurrent invocations sharing the cache never
+/// observe a partially written file, and an artifact being downloaded is
+/// locked so they do not download it twice.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    root: PathBuf,
+    keep_downloads: bool,
+    offline: bool,
+    /// The channels detected or read from the cache during this run, shared
+    /// by every clone.
+    channels: Arc<Mutex<BTreeMap<String, &'static str>>>,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf, keep_downloads: bool) -> Self {
+        Self {
+            root,
+            keep_downloads,
+            offline: false,
+            channels: Arc::default(),
+        }
+    }
+
+    /// Makes the installs use only the cached artifacts and local files,
+    /// failing with [`crate::Error::Offline`] for anything needing the network.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the platform cache directory, e.g. `~/.cache` on Linux or
+    /// `%LOCALAPPDATA%` on Windows.
+    pub fn default_dir() -> Option<PathBuf> {
+        Some(dirs::cache_dir()?.join("rustup-toolchain-install-master"))
+    }
+
+    /// Returns the path of the installation journal.
+    pub fn history_path(&self) -> PathBuf {
+        self.root.join("history.jsonl")
+    }
+
+    /// Returns where the result of the last check for a newer release of this
+    /// tool is stored.
+    pub fn update_check_path(&self) -> PathBuf {
+        self.root.join("update-check.json")
+    }
+
+    /// Returns the channel which `commit` was found to belong to, by this run
+    /// or a previous one.
+    pub fn channel(&self, commit: &str) -> Option<&'static str> {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get(commit) {
+            return Some(channel);
+        }
+        let content = read_to_string(self.channel_path(commit)?).ok()?;
+        let channel = SUPPORTED_CHANNELS
+            .iter()
+            .copied()
+            .find(|channel| *channel == content.trim())?;
+        channels.insert(commit.to_owned(), channel);
+        Some(channel)
+    }
+
+    /// Remembers that `commit` belongs to `channel`, so its channel is not
+    /// detected again.
+    pub fn store_channel(&self, commit: &str, channel: &'static str) {
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(commit.to_owned(), channel);
+        if let Some(path) = self.channel_path(commit) {
+            // failing to persist it only means detecting it again next time.
+            let _ = write_atomically(&path, channel.as_bytes());
+        }
+    }
+
+    /// Returns where the channel of `commit` is stored, unless the commit is
+    /// not a plain hash which can be used as a file name.
+    fn channel_path(&self, commit: &str) -> Option<PathBuf> {
+        if commit.is_empty() || !commit.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return None;
+        }
+        Some(self.root.join("channels").join(commit))
+    }
+
+    /// Maps a URL to its location in the cache, e.g.
+    /// `downloads/ci-artifacts.rust-lang.org/rustc-builds/<commit>/<file>`.
+    fn download_path(&self, url: &str) -> Option<PathBuf> {
+        let url = Url::parse(url).ok()?;
+        let mut path = self.root.join("downloads");
+        path.push(match url.port() {
+            Some(port) => format!("{}_{}", url.host_str()?, port),
+            None => url.host_str()?.to_owned(),
+        });
+        for segment in url.path_segments()? {
+            if segment.is_empty() || segment == "." || segment == ".." {
+                return None;
+            }
+            path.push(segment);
+        }
+        Some(path)
+    }
+
+    /// Locks the cached artifact of `url` while it is downloaded, so a
+    /// concurrent invocation waits and then reuses it instead of downloading
+    /// it again. Returns `None` if downloads are not kept.
+    pub(crate) fn lock_download(
+        &self,
+        url: &str,
+        observer: &dyn ProgressObserver,
+    ) -> Result<Option<FileLock>, crate::Error> {
+        if !self.keep_downloads {
+            return Ok(None);
+        }
+        match self.download_path(url) {
+            Some(mut path) => {
+                let mut file_name = path.file_name().unwrap_or_default().to_owned();
+                file_name.push(".lock");
+                path.set_file_name(file_name);
+                lock_file(&path, observer).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the path of the cached artifact downloaded from `url`, if any,
+    /// marking it as used for [`Cache::prune`].
+    pub fn get(&self, url: &str) -> Option<PathBuf> {
+        let path = self.download_path(url).filter(|path| path.is_file())?;
+        // the access time is not updated on many file systems, so the
+        // modification time records when the artifact was last used.
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(path)
+    }
+
+    /// Returns the SHA-256 hash recorded when the artifact of `url` was
+    /// downloaded into the cache, if any.
+    pub fn recorded_sha256(&self, url: &str) -> Option<String> {
+        let content = read_to_string(hash_path(&self.download_path(url)?)).ok()?;
+        Some(content.trim().to_owned())
+    }
+
+    /// Removes the cached artifact of `url` and its recorded hash, e.g. when
+    /// it turns out to be corrupted.
+    pub fn evict(&self, url: &str) -> Result<(), Error> {
+        if let Some(path) = self.download_path(url) {
+            for path in [hash_path(&path), path] {
+                match remove_file(&path) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => {
+                        return Err(Error::from(err)
+                            .context(format!("failed to remove `{}`", path.display())))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the cached downloads exceeding the `limits`, least recently used
+    /// first, and returns the number of bytes freed.
+    pub fn prune(
+        &self,
+        limits: &CacheLimits,
+        dry_run: bool,
+        observer: &dyn ProgressObserver,
+    ) -> Result<u64, crate::Error> {
+        let downloads_dir = self.root.join("downloads");
+        let mut downloads = Vec::new();
+        list_downloads(&downloads_dir, &mut downloads)?;
+        // least recently used first
+        downloads.sort_by_key(|(_, _, used_at)| *used_at);
+
+        let now = SystemTime::now();
+        let mut total = downloads.iter().map(|(_, size, _)| size).sum::<u64>();
+        let mut freed = 0;
+        for (path, size, used_at) in downloads {
+            check_cancelled(observer)?;
+            let age = now.duration_since(used_at).unwrap_or_default();
+            let reason = if limits.max_age.is_some_and(|max_age| age > max_age) {
+                format!("unused for {} days", age.as_secs() / 86400)
+            } else if limits.max_size.is_some_and(|max| total > max) {
+                "the cache is too large".to_owned()
+            } else {
+                continue;
+            };
+            let lock_path = {
+                let mut file_name = path.file_name().unwrap_or_default().to_owned();
+                file_name.push(".lock");
+                path.with_file_name(file_name)
+            };
+            // an artifact being downloaded again by another run is skipped.
+            let _lock = if dry_run {
+                None
+            } else {
+                match try_lock_file(&lock_path)? {
+                    Some(lock) => Some(lock),
+                    None => continue,
+                }
+            };
+            observer.phase(Phase::EvictingDownload {
+                path: &path,
+                reason: &reason,
+                dry_run,
+            });
+            if !dry_run {
+                // the lock file and thus the directory stay, since another
+                // run may have opened it already and would lock the removed
+                // file while a third run locks a new one.
+                for path in [hash_path(&path), path.clone()] {
+                    match remove_file(&path) {
+                        Err(err) if err.kind() != ErrorKind::NotFound => {
+                            return Err(Error::from(err)
+                                .context(format!("failed to remove `{}`", path.display()))
+                                .into())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            total -= size;
+            freed += size;
+        }
+        Ok(freed)
+    }
+
+    /// Prepares to store the artifact downloaded from `url`. Returns `None` if
+    /// downloads should not be kept in the cache.
+    pub fn begin_download(&self, url: &str) -> Result<Option<PendingDownload>, Error> {
+        if !self.keep_downloads {
+            return Ok(None);
+        }
+        let path = match self.download_path(url) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let parent = path.parent().unwrap_or(&self.root);
+        create_dir_all(parent)
+            .with_context(|| format!("failed to create cache directory `{}`", parent.display()))?;
+        let file = NamedTempFile::new_in(parent)?;
+        Ok(Some(PendingDownload { file, path }))
+    }
+}
+
+/// Collects the path, size and last use of every cached artifact under `dir`,
+/// skipping the recorded hashes, locks and partial downloads.
+fn list_downloads(
+    dir: &Path,
+    downloads: &mut Vec<(PathBuf, u64, SystemTime)>,
+) -> Result<(), Error> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(Error::from(err).context(format!("failed to read `{}`", dir.display())))
+        }
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            list_downloads(&path, downloads)?;
+            continue;
+        }
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with(".tmp")
+            || file_name.ends_with(".sha256")
+            || file_name.ends_with(".lock")
+        {
+            continue;
+        }
+        downloads.push((path, metadata.len(), metadata.modified()?));
+    }
+    Ok(())
+}
+
+/// Returns where the hash of the cached artifact at `path` is recorded.
+fn hash_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".sha256");
+    path.with_file_name(file_name)
+}
+
+/// Writes `content` to a temporary file next to `path` and renames it into
+/// place.
+fn write_atomically(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let parent = path.parent().context("the path has no parent")?;
+    create_dir_all(parent)?;
+    let mut file = NamedTempFile::new_in(parent)?;
+    file.write_all(content)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// An artifact being downloaded into the cache.
+pub struct PendingDownload {
+    file: NamedTempFile,
+    path: PathBuf,
+}
+
+impl PendingDownload {
+    pub fn file_mut(&mut self) -> &mut impl Write {
+        self.file.as_file_mut()
+    }
+
+    /// Moves the completely downloaded file into the cache, recording its
+    /// `sha256` hash to verify it whenever it is used.
+    pub fn finish(self, sha256: &str) -> Result<PathBuf, Error> {
+        let Self { mut file, path } = self;
+        file.as_file_mut().flush()?;
+        write_atomically(&hash_path(&path), sha256.as_bytes())?;
+        file.persist(&path)
+            .with_context(|| format!("failed to store `{}` in the cache", path.display()))?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::ErrorKind;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::Client;
+use reqwest::header::LAST_MODIFIED;
+use reqwest::StatusCode;
+
+use crate::download::{local_path, response_url};
+use crate::error::Error;
+use crate::http::send;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+use crate::url::component_urls;
+
+/// The channels which a CI build can belong to, in the order to probe them.
+pub static SUPPORTED_CHANNELS: &[&str] = &["nightly", "beta", "stable"];
+
+/// How many days CI artifacts are kept after they are uploaded.
+pub const ARTIFACT_RETENTION_DAYS: i64 = 168;
+
+/// Detects the channel of the toolchain built from `commit` by probing which
+/// `rust-src` artifact exists under `prefix`.
+pub fn detect_channel(
+    client: &Client,
+    prefix: &str,
+    commit: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<&'static str, Error> {
+    observer.phase(Phase::DetectingChannel { commit });
+    probe_channel(client, prefix, commit, observer)?.ok_or_else(|| Error::ArtifactExpired {
+        commit: commit.to_owned(),
+    })
+}
+
+/// Probes the channel of `commit` like [`detect_channel`] without reporting
+/// it, returning `None` if the artifacts of `commit` do not exist (anymore).
+pub fn probe_channel(
+    client: &Client,
+    prefix: &str,
+    commit: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<&'static str>, Error> {
+    for channel in SUPPORTED_CHANNELS {
+        for url in component_urls(prefix, commit, channel, "rust-src", "") {
+            check_cancelled(observer)?;
+            if let Some(path) = local_path(&url) {
+                if path.is_file() {
+                    return Ok(Some(channel));
+                }
+                continue;
+            }
+            let resp = send(client, client.head(&url), observer)?;
+
+            match resp.status() {
+                StatusCode::OK => return Ok(Some(channel)),
+                StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {}
+                status => {
+                    return Err(Error::UnexpectedStatus {
+                        status,
+                        url: response_url(&url, &resp),
+                    })
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns when the artifacts of `commit` in `channel` were uploaded under
+/// `prefix`, according to the `Last-Modified` header of `rust-src`, or `None`
+/// if they do not exist. They are deleted [`ARTIFACT_RETENTION_DAYS`] later.
+pub fn upload_time(
+    client: &Client,
+    prefix: &str,
+    commit: &str,
+    channel: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let mut found = None;
+    for url in component_urls(prefix, commit, channel, "rust-src", "") {
+        check_cancelled(observer)?;
+        if let Some(path) = local_path(&url) {
+            // a mirror keeps the modification times of the uploads, if anything.
+            match path.metadata() {
+                Ok(metadata) => return Ok(Some(metadata.modified()?.into())),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let resp = send(client, client.head(&url), observer)?;
+        match resp.status() {
+            StatusCode::OK => {
+                found = Some((url, resp));
+                break;
+            }
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {}
+            status => {
+                return Err(Error::UnexpectedStatus {
+                    status,
+                    url: response_url(&url, &resp),
+                })
+            }
+        }
+    }
+    let (url, resp) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let last_modified = resp
+        .headers()
+        .get(LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .ok_or_else(|| anyhow!("`{}` has no valid Last-Modified header", url))?;
+    Ok(Some(last_modified.with_timezone(&Utc)))
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+use std::env::consts::EXE_SUFFIX;
+use std::fs::read_dir;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{ensure, Context, Error};
+use rustup_toolchain_install_master::InstallMetadata;
+
+/// What is compared between two toolchains.
+struct Summary {
+    version: Vec<String>,
+    /// The components and targets, if the toolchain was installed by this
+    /// tool.
+    components: Option<BTreeSet<String>>,
+    targets: Option<BTreeSet<String>>,
+    size: u64,
+}
+
+fn summarize(toolchains_path: &Path, name: &str) -> Result<Summary, Error> {
+    let path = toolchains_path.join(name);
+    let rustc = path.join("bin").join(format!("rustc{}", EXE_SUFFIX));
+    let output = Command::new(&rustc)
+        .arg("-vV")
+        .output()
+        .with_context(|| format!("failed to run `{}`", rustc.display()))?;
+    ensure!(
+        output.status.success(),
+        "`rustc -vV` of toolchain `{}` exited with {}",
+        name,
+        output.status
+    );
+    let version = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+
+    let metadata = InstallMetadata::read(&path)?;
+    let components = metadata.as_ref().map(|m| {
+        ["rustc", "rust-std"]
+            .iter()
+            .map(|&c| c.to_owned())
+            .chain(m.components.iter().cloned())
+            .collect()
+    });
+    let targets = metadata.map(|m| m.targets.into_iter().collect());
+
+    let size = dir_size(&path)
+        .with_context(|| format!("failed to measure the size of `{}`", path.display()))?;
+    Ok(Summary {
+        version,
+        components,
+        targets,
+        size,
+    })
+}
+
+/// Returns the total size of the files in `path`, not following symlinks.
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Prints the lines of `a` and `b`, marking those only in `a` with `-` and
+/// those only in `b` with `+`.
+fn print_set_diff(title: &str, a: &Option<BTreeSet<String>>, b: &Option<BTreeSet<String>>) {
+    println!("{}:", title);
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            for item in a.union(b) {
+                let marker = match (a.contains(item), b.contains(item)) {
+                    (true, false) => '-',
+                    (false, true) => '+',
+                    _ => ' ',
+                };
+                println!("{} {}", marker, item);
+            }
+        }
+        _ => println!("  unknown, the toolchains were not both installed by this tool"),
+    }
+}
+
+pub fn format_size(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))
+}
+
+/// Prints how the toolchains `a` and `b` differ in `rustc -vV`, their
+/// components, targets and sizes.
+pub fn compare(toolchains_path: &Path, a: &str, b: &str) -> Result<(), Error> {
+    let sa = summarize(toolchains_path, a)?;
+    let sb = summarize(toolchains_path, b)?;
+
+    println!("--- {}", a);
+    println!("+++ {}", b);
+
+    // `rustc -vV` prints the same keys in the same order on every version.
+    println!("rustc -vV:");
+    for i in 0..sa.version.len().max(sb.version.len()) {
+        match (sa.version.get(i), sb.version.get(i)) {
+            (Some(la), Some(lb)) if la == lb => println!("  {}", la),
+            (la, lb) => {
+                if let Some(la) = la {
+                    println!("- {}", la);
+                }
+                if let Some(lb) = lb {
+                    println!("+ {}", lb);
+                }
+            }
+        }
+    }
+
+    print_set_diff("components", &sa.components, &sb.components);
+    print_set_diff("targets", &sa.targets, &sb.targets);
+
+    let delta = sb.size as i64 - sa.size as i64;
+    println!(
+        "size: {} -> {} ({}{})",
+        format_size(sa.size),
+        format_size(sb.size),
+        if delta < 0 { "-" } else { "+" },
+        format_size(delta.unsigned_abs())
+    );
+    Ok(())
+}
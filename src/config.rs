@@ -0,0 +1,699 @@
+use std::collections::BTreeMap;
+use std::env::{current_dir, var, var_os};
+use std::fs::{create_dir_all, read_to_string, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Error};
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+use rustup_toolchain_install_master::{FileModes, SUPPORTED_CHANNELS};
+
+use crate::credentials::TokenSource;
+
+static PROJECT_CONFIG_FILE_NAME: &str = ".rtim.toml";
+/// The environment variable of `--config`.
+pub static CONFIG_VAR: &str = "RTIM_CONFIG";
+/// The environment variable of `--profile-name`.
+pub static PROFILE_VAR: &str = "RTIM_PROFILE";
+
+/// Persistent defaults read from the configuration file.
+///
+/// Every field mirrors a command line flag. Flags given on the command line
+/// always take precedence over the values stored here. The switches are
+/// optional so that a lower layer, e.g. the config file, can be turned off
+/// again in a higher one like `RTIM_FORCE=0`.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub server: Option<String>,
+    pub url_template: Option<String>,
+    pub prefix: Option<String>,
+    pub dist_server: Option<String>,
+    #[serde(deserialize_with = "string_or_list")]
+    pub host: Vec<String>,
+    pub name_template: Option<String>,
+    pub targets: Vec<String>,
+    pub components: Vec<String>,
+    pub exclude: Vec<String>,
+    pub channel: Option<String>,
+    pub proxy: Option<String>,
+    pub github_token: Option<String>,
+    pub github_token_from: Option<TokenSource>,
+    pub alt: Option<bool>,
+    pub wait_for_rate_limit: Option<bool>,
+    pub force: Option<bool>,
+    pub dry_run: Option<bool>,
+    pub keep_going: Option<bool>,
+    pub no_git: Option<bool>,
+    pub no_update_check: Option<bool>,
+    pub offline: Option<bool>,
+    pub verbose: Option<bool>,
+    pub debug_http: Option<bool>,
+    pub from_toolchain_file: Option<bool>,
+    pub detect_targets: Option<bool>,
+    pub no_std: Option<bool>,
+    pub show_commit: Option<bool>,
+    pub show_pr: Option<bool>,
+    pub rust_analyzer: Option<bool>,
+    pub both_abis: Option<bool>,
+    pub wasm: Option<bool>,
+    pub embedded: Option<bool>,
+    pub retention: Option<String>,
+    pub max_toolchains: Option<usize>,
+    pub jobs: Option<usize>,
+    pub max_redirects: Option<usize>,
+    pub max_download_size: Option<String>,
+    pub pool_idle_timeout: Option<String>,
+    pub pool_max_idle: Option<usize>,
+    pub cache_dir: Option<PathBuf>,
+    pub rustup_home: Option<PathBuf>,
+    pub system: Option<PathBuf>,
+    pub chmod: Option<FileModes>,
+    pub group_writable: Option<bool>,
+    pub cache_downloads: Option<bool>,
+    pub cache_max_size: Option<String>,
+    pub cache_max_age: Option<String>,
+    pub hooks: Hooks,
+    pub profile: BTreeMap<String, Config>,
+}
+
+/// The shell commands run around installing and removing toolchains.
+///
+/// They can only be set in the user config, so cloning a project does not
+/// make this tool run its commands.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hooks {
+    /// Run before downloading a toolchain which is not installed yet.
+    pub pre_install: Option<String>,
+    /// Run after a toolchain is installed.
+    pub post_install: Option<String>,
+    /// Run after a toolchain is removed.
+    pub post_uninstall: Option<String>,
+}
+
+impl Config {
+    /// Reads the options from the `RTIM_*` environment variables named after
+    /// the config keys, see [`env_var`].
+    pub fn from_env() -> Result<Self, Error> {
+        let mut doc = DocumentMut::new();
+        for (key, _) in KEYS {
+            let name = env_var(key);
+            let value = var(&name).unwrap_or_default();
+            if value.is_empty() {
+                continue;
+            }
+            let value = parse_value(key, &value)
+                .with_context(|| format!("invalid environment variable {}", name))?;
+            doc.insert(key, Item::Value(value));
+        }
+        toml::from_str(&doc.to_string()).context("invalid `RTIM_*` environment variables")
+    }
+
+    /// Loads the configuration from `path`, or from the default location if
+    /// no path is given. A missing file at the default location is not an
+    /// error and yields the empty configuration.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let (path, required) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound && !required => return Ok(Self::default()),
+            Err(e) => {
+                return Err(Error::new(e)
+                    .context(format!("failed to read config file `{}`", path.display())))
+            }
+        };
+        toml::from_str(&content)
+            .with_context(|| format!("failed to parse config file `{}`", path.display()))
+    }
+
+    /// Loads every applicable configuration, ordered from the highest to the
+    /// lowest precedence: the project-local `.rtim.toml`, then the user config
+    /// (from `path` or the default location). If `profile_name` is given, the
+    /// profile found in these files is placed before the top-level values.
+    pub fn load_all(path: Option<&Path>, profile_name: Option<&str>) -> Result<Vec<Self>, Error> {
+        let mut configs = Vec::with_capacity(4);
+        if let Some(project) = Self::load_project()? {
+            configs.push(project);
+        }
+        configs.push(Self::load(path)?);
+
+        if let Some(name) = profile_name {
+            let profiles = configs
+                .iter_mut()
+                .filter_map(|config| config.profile.remove(name))
+                .collect::<Vec<_>>();
+            ensure!(
+                !profiles.is_empty(),
+                "profile `{}` is not defined in the config file",
+                name
+            );
+            ensure!(
+                profiles.iter().all(|p| p.profile.is_empty()),
+                "profile `{}` cannot contain nested profiles",
+                name
+            );
+            configs.splice(..0, profiles);
+        }
+        Ok(configs)
+    }
+
+    /// Searches upward from the current directory for a `.rtim.toml` file.
+    fn load_project() -> Result<Option<Self>, Error> {
+        let cwd = current_dir()?;
+        for dir in cwd.ancestors() {
+            let path = dir.join(PROJECT_CONFIG_FILE_NAME);
+            if path.is_file() {
+                return ProjectConfig::load(&path).map(|config| Some(config.into()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The options a repository can pin in its `.rtim.toml`.
+///
+/// These only choose what is installed and how it is named, so cloning a
+/// project cannot redirect the downloads, run commands or touch files outside
+/// the toolchains.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ProjectConfig {
+    components: Vec<String>,
+    targets: Vec<String>,
+    exclude: Vec<String>,
+    name_template: Option<String>,
+    channel: Option<String>,
+    profile: BTreeMap<String, ProjectConfig>,
+}
+
+static PROJECT_CONFIG_KEYS: &[&str] = &[
+    "components",
+    "targets",
+    "exclude",
+    "name-template",
+    "channel",
+];
+
+impl ProjectConfig {
+    fn load(path: &Path) -> Result<Self, Error> {
+        let content = read_to_string(path)
+            .with_context(|| format!("failed to read config file `{}`", path.display()))?;
+        let value = toml::from_str::<toml::Value>(&content)
+            .with_context(|| format!("failed to parse config file `{}`", path.display()))?;
+        let profiles = value.get("profile").and_then(toml::Value::as_table);
+        let tables = value.as_table().into_iter().chain(
+            profiles
+                .into_iter()
+                .flat_map(|p| p.values().filter_map(toml::Value::as_table)),
+        );
+        for key in tables.flat_map(|t| t.keys()) {
+            ensure!(
+                PROJECT_CONFIG_KEYS.contains(&&**key) || (key == "profile" && profiles.is_some()),
+                "`{}` cannot be set in the project config file `{}`, move it to the user \
+                 config file (only `components`, `targets`, `exclude`, `name-template` and \
+                 `channel` are allowed)",
+                key,
+                path.display()
+            );
+        }
+        value
+            .try_into()
+            .with_context(|| format!("failed to parse config file `{}`", path.display()))
+    }
+}
+
+impl From<ProjectConfig> for Config {
+    fn from(config: ProjectConfig) -> Self {
+        Self {
+            components: config.components,
+            targets: config.targets,
+            exclude: config.exclude,
+            name_template: config.name_template,
+            channel: config.channel,
+            profile: config
+                .profile
+                .into_iter()
+                .map(|(name, profile)| (name, profile.into()))
+                .collect(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Deserializes a list which may also be written as a single string, e.g.
+/// `host = "x86_64-pc-windows-msvc"`.
+fn string_or_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+
+    Ok(match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => vec![s],
+        StringOrList::List(list) => list,
+    })
+}
+
+/// Returns the environment variable of the config `key`, e.g. `RTIM_CACHE_DIR`
+/// for `cache-dir`.
+pub fn env_var(key: &str) -> String {
+    format!("RTIM_{}", key.to_uppercase().replace('-', "_"))
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "" | "0" | "false" | "no" => Some(false),
+        "1" | "true" | "yes" => Some(true),
+        _ => None,
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME/rustup-toolchain-install-master/config.toml`,
+/// falling back to `~/.config` when `$XDG_CONFIG_HOME` is unset.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = match var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home::home_dir()?.join(".config"),
+    };
+    Some(
+        config_home
+            .join("rustup-toolchain-install-master")
+            .join("config.toml"),
+    )
+}
+
+/// The type of the value stored under a config key.
+#[derive(Clone, Copy)]
+pub enum ValueKind {
+    String,
+    List,
+    Flag,
+    Integer,
+}
+
+/// The keys of the options which can be stored in the config file or given by
+/// the environment, named after their long flags.
+pub static KEYS: &[(&str, ValueKind)] = &[
+    ("server", ValueKind::String),
+    ("url-template", ValueKind::String),
+    ("prefix", ValueKind::String),
+    ("dist-server", ValueKind::String),
+    ("host", ValueKind::List),
+    ("name-template", ValueKind::String),
+    ("targets", ValueKind::List),
+    ("components", ValueKind::List),
+    ("exclude", ValueKind::List),
+    ("channel", ValueKind::String),
+    ("proxy", ValueKind::String),
+    ("github-token", ValueKind::String),
+    ("github-token-from", ValueKind::String),
+    ("alt", ValueKind::Flag),
+    ("wait-for-rate-limit", ValueKind::Flag),
+    ("force", ValueKind::Flag),
+    ("dry-run", ValueKind::Flag),
+    ("keep-going", ValueKind::Flag),
+    ("no-git", ValueKind::Flag),
+    ("no-update-check", ValueKind::Flag),
+    ("offline", ValueKind::Flag),
+    ("verbose", ValueKind::Flag),
+    ("debug-http", ValueKind::Flag),
+    ("from-toolchain-file", ValueKind::Flag),
+    ("detect-targets", ValueKind::Flag),
+    ("no-std", ValueKind::Flag),
+    ("show-commit", ValueKind::Flag),
+    ("show-pr", ValueKind::Flag),
+    ("rust-analyzer", ValueKind::Flag),
+    ("both-abis", ValueKind::Flag),
+    ("wasm", ValueKind::Flag),
+    ("embedded", ValueKind::Flag),
+    ("retention", ValueKind::String),
+    ("max-toolchains", ValueKind::Integer),
+    ("jobs", ValueKind::Integer),
+    ("max-redirects", ValueKind::Integer),
+    ("max-download-size", ValueKind::String),
+    ("pool-idle-timeout", ValueKind::String),
+    ("pool-max-idle", ValueKind::Integer),
+    ("cache-dir", ValueKind::String),
+    ("rustup-home", ValueKind::String),
+    ("system", ValueKind::String),
+    ("chmod", ValueKind::String),
+    ("group-writable", ValueKind::Flag),
+    ("cache-downloads", ValueKind::Flag),
+    ("cache-max-size", ValueKind::String),
+    ("cache-max-age", ValueKind::String),
+];
+
+fn value_kind(key: &str) -> Option<ValueKind> {
+    KEYS.iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, kind)| *kind)
+}
+
+/// Checks that the URL template of `--url-template` only uses the known
+/// placeholders and tells the artifacts apart.
+pub fn check_url_template(template: &str) -> Result<(), Error> {
+    let unknown = [
+        "{server}",
+        "{prefix}",
+        "{commit}",
+        "{component}",
+        "{channel}",
+        "{target}",
+        "{ext}",
+    ]
+    .iter()
+    .fold(template.to_owned(), |rest, placeholder| {
+        rest.replace(placeholder, "")
+    });
+    ensure!(
+        !unknown.contains(['{', '}']),
+        "unknown placeholder in the URL template `{}`",
+        template
+    );
+    ensure!(
+        template.contains("{component}"),
+        "the URL template `{}` must contain `{{component}}`",
+        template
+    );
+    Ok(())
+}
+
+/// Parses a size like `500MB` or `2GiB` into bytes. A plain number is a number
+/// of bytes.
+pub fn parse_size(size: &str) -> Result<u64, Error> {
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let scale = match unit.trim() {
+        "" | "B" => 1,
+        "KB" | "kB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        unit => bail!("unknown unit `{}` in size `{}`", unit, size),
+    };
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("invalid size `{}`", size))?;
+    Ok((number * scale as f64) as u64)
+}
+
+/// Splits a dotted key like `profile.miri.components` into the profile name
+/// and the option name.
+fn split_key(key: &str) -> Result<(Option<&str>, &str), Error> {
+    let parts = key.split('.').collect::<Vec<_>>();
+    let (profile, name) = match parts[..] {
+        [name] => (None, name),
+        ["profile", profile, name] => (Some(profile), name),
+        _ => bail!("invalid config key `{}`", key),
+    };
+    ensure!(value_kind(name).is_some(), "unknown config key `{}`", key);
+    Ok((profile, name))
+}
+
+/// Parses and validates a value given on the command line for the option
+/// `name`.
+fn parse_value(name: &str, value: &str) -> Result<Value, Error> {
+    Ok(match value_kind(name) {
+        Some(ValueKind::String) => {
+            match name {
+                "channel" => ensure!(
+                    SUPPORTED_CHANNELS.contains(&value),
+                    "channel must be one of {}",
+                    SUPPORTED_CHANNELS.join(", ")
+                ),
+                "server" | "dist-server" | "proxy" => {
+                    Url::parse(value).with_context(|| format!("invalid URL `{}`", value))?;
+                }
+                "url-template" => check_url_template(value)?,
+                "github-token-from" => {
+                    value.parse::<TokenSource>()?;
+                }
+                "retention" | "cache-max-age" => {
+                    humantime::parse_duration(value)
+                        .with_context(|| format!("invalid duration `{}`", value))?;
+                }
+                "max-download-size" | "cache-max-size" => {
+                    parse_size(value)?;
+                }
+                _ => {}
+            }
+            value.into()
+        }
+        Some(ValueKind::List) => value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .collect::<Array>()
+            .into(),
+        Some(ValueKind::Flag) => parse_flag(value)
+            .with_context(|| format!("`{}` expects true or false, not `{}`", name, value))?
+            .into(),
+        Some(ValueKind::Integer) => {
+            let number = value
+                .parse::<u32>()
+                .with_context(|| format!("`{}` expects a number, not `{}`", name, value))?;
+            ensure!(
+                number > 0 || name != "jobs",
+                "the number of jobs must be positive"
+            );
+            i64::from(number).into()
+        }
+        None => bail!("unknown config key `{}`", name),
+    })
+}
+
+/// Formats a value for display, printing strings without quotes and lists as
+/// comma-separated items.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.value().clone(),
+        Value::Array(a) => a.iter().map(display_value).collect::<Vec<_>>().join(","),
+        v => v.to_string().trim().to_owned(),
+    }
+}
+
+/// An editable view of the user config file which preserves its formatting.
+pub struct ConfigFile {
+    path: PathBuf,
+    doc: DocumentMut,
+}
+
+impl ConfigFile {
+    /// Opens the config file at `path` or at the default location. A missing
+    /// file is treated as empty.
+    pub fn open(path: Option<&Path>) -> Result<Self, Error> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => default_config_path().context("cannot determine the config directory")?,
+        };
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(Error::new(e)
+                    .context(format!("failed to read config file `{}`", path.display())))
+            }
+        };
+        let doc = content
+            .parse()
+            .with_context(|| format!("failed to parse config file `{}`", path.display()))?;
+        Ok(Self { path, doc })
+    }
+
+    fn table(&self, profile: Option<&str>) -> Option<&Table> {
+        match profile {
+            None => Some(self.doc.as_table()),
+            Some(profile) => self.doc.get("profile")?.get(profile)?.as_table(),
+        }
+    }
+
+    /// Returns the value of `key` in display form.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let (profile, name) = split_key(key)?;
+        Ok(self
+            .table(profile)
+            .and_then(|t| t.get(name))
+            .and_then(Item::as_value)
+            .map(display_value))
+    }
+
+    /// Returns every key and value stored in the file, with the GitHub token
+    /// masked.
+    pub fn list(&self) -> Vec<(String, String)> {
+        fn collect(prefix: &str, table: &Table, out: &mut Vec<(String, String)>) {
+            for (key, item) in table {
+                if let Some(value) = item.as_value() {
+                    let display = if key == "github-token" {
+                        "********".to_owned()
+                    } else {
+                        display_value(value)
+                    };
+                    out.push((format!("{}{}", prefix, key), display));
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        collect("", self.doc.as_table(), &mut out);
+        if let Some(profiles) = self.doc.get("profile").and_then(Item::as_table) {
+            for (name, profile) in profiles {
+                if let Some(profile) = profile.as_table() {
+                    collect(&format!("profile.{}.", name), profile, &mut out);
+                }
+            }
+        }
+        out
+    }
+
+    /// Validates and stores `value` under `key`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        let (profile, name) = split_key(key)?;
+        let value = parse_value(name, value)?;
+        let table = match profile {
+            None => self.doc.as_table_mut(),
+            Some(profile) => {
+                let profiles = self.doc.entry("profile").or_insert_with(|| {
+                    let mut table = Table::new();
+                    table.set_implicit(true);
+                    table.into()
+                });
+                profiles
+                    .as_table_mut()
+                    .context("`profile` in the config file is not a table")?
+                    .entry(profile)
+                    .or_insert_with(|| Table::new().into())
+                    .as_table_mut()
+                    .with_context(|| format!("`profile.{}` is not a table", profile))?
+            }
+        };
+        table.insert(name, Item::Value(value));
+        Ok(())
+    }
+
+    /// Removes `key`, returning whether it existed.
+    pub fn unset(&mut self, key: &str) -> Result<bool, Error> {
+        let (profile, name) = split_key(key)?;
+        let table = match profile {
+            None => Some(self.doc.as_table_mut()),
+            Some(profile) => self
+                .doc
+                .get_mut("profile")
+                .and_then(|p| p.get_mut(profile))
+                .and_then(Item::as_table_mut),
+        };
+        Ok(table.and_then(|t| t.remove(name)).is_some())
+    }
+
+    /// Writes the file back to disk, after checking it is still a valid
+    /// config.
+    pub fn save(&self) -> Result<(), Error> {
+        let content = self.doc.to_string();
+        toml::from_str::<Config>(&content).context("the edited config file is invalid")?;
+        if let Some(parent) = self.path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(&self.path, content)
+            .with_context(|| format!("failed to write config file `{}`", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("123").unwrap(), 123);
+        assert_eq!(parse_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_size("2 GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5kB").unwrap(), 1_500);
+        assert!(parse_size("5TB").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn flags() {
+        for value in ["", "0", "false", "no"] {
+            assert_eq!(parse_flag(value), Some(false));
+        }
+        for value in ["1", "true", "yes"] {
+            assert_eq!(parse_flag(value), Some(true));
+        }
+        assert_eq!(parse_flag("on"), None);
+        assert_eq!(parse_flag("TRUE"), None);
+    }
+
+    #[test]
+    fn hosts() {
+        let config = toml::from_str::<Config>("host = \"x86_64-pc-windows-msvc\"").unwrap();
+        assert_eq!(config.host, ["x86_64-pc-windows-msvc"]);
+        let config = toml::from_str::<Config>(
+            "host = [\"x86_64-pc-windows-msvc\", \"x86_64-pc-windows-gnu\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            config.host,
+            ["x86_64-pc-windows-msvc", "x86_64-pc-windows-gnu"]
+        );
+        assert!(toml::from_str::<Config>("").unwrap().host.is_empty());
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(parse_value("jobs", "4").unwrap().as_integer(), Some(4));
+        assert_eq!(
+            parse_value("max-redirects", "0").unwrap().as_integer(),
+            Some(0)
+        );
+        assert!(parse_value("jobs", "0").is_err());
+        assert!(parse_value("jobs", "-1").is_err());
+    }
+
+    #[test]
+    fn url_templates() {
+        assert!(
+            check_url_template("{prefix}/{commit}/{component}-{channel}-{target}.{ext}").is_ok()
+        );
+        assert!(check_url_template("{server}/{component}").is_ok());
+        assert!(check_url_template("{server}/{commit}.tar.xz").is_err());
+        assert!(check_url_template("{server}/{component}-{arch}").is_err());
+        assert!(check_url_template("{server}/{component}-{target").is_err());
+    }
+
+    #[test]
+    fn project_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROJECT_CONFIG_FILE_NAME);
+        write(
+            &path,
+            "channel = \"beta\"\n[profile.miri]\ncomponents = [\"miri\"]\n",
+        )
+        .unwrap();
+        let config = ProjectConfig::load(&path).unwrap();
+        assert_eq!(config.channel.as_deref(), Some("beta"));
+        assert_eq!(config.profile["miri"].components, ["miri"]);
+        for content in [
+            "prefix = \"rustc-builds-try\"",
+            "[profile.miri]\nserver = \"x\"",
+        ] {
+            write(&path, content).unwrap();
+            assert!(ProjectConfig::load(&path).is_err());
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Packs an installed toolchain for container images, to distribute CI
+//! compilers to machines without rustup.
+
+use std::fs::{create_dir_all, read_dir, write, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use rustup_toolchain_install_master::METADATA_FILE_NAME;
+use tar::{Builder, HeaderMode};
+
+/// Where the toolchain is placed in the image.
+static IMAGE_PREFIX: &str = "usr/local";
+
+/// The name of the layer in the Dockerfile context.
+static LAYER_FILE_NAME: &str = "toolchain.tar";
+
+/// Writes the toolchain at `toolchain_path` placed under `/usr/local` as an
+/// OCI layer, i.e. an uncompressed tar, into `output`.
+///
+/// The timestamps and owners are cleared, so packing the same toolchain again
+/// yields the same layer.
+pub fn write_layer(toolchain_path: &Path, output: &Path) -> Result<(), Error> {
+    let file =
+        File::create(output).with_context(|| format!("failed to create `{}`", output.display()))?;
+    let mut builder = Builder::new(BufWriter::new(file));
+    builder.mode(HeaderMode::Deterministic);
+    builder.follow_symlinks(false);
+    append_dir(&mut builder, toolchain_path, Path::new(IMAGE_PREFIX), true)
+        .with_context(|| format!("failed to write `{}`", output.display()))?;
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Writes a Dockerfile context into the directory `output`, building an image
+/// from `base_image` with the toolchain added by [`write_layer`].
+pub fn write_context(toolchain_path: &Path, output: &Path, base_image: &str) -> Result<(), Error> {
+    create_dir_all(output).with_context(|| format!("failed to create `{}`", output.display()))?;
+    write_layer(toolchain_path, &output.join(LAYER_FILE_NAME))?;
+    let dockerfile = format!(
+        "FROM {}\nADD {} /\nENV PATH=/{}/bin:$PATH\n",
+        base_image, LAYER_FILE_NAME, IMAGE_PREFIX
+    );
+    let path = output.join("Dockerfile");
+    write(&path, dockerfile).with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+fn append_dir<W: Write>(
+    builder: &mut Builder<W>,
+    dir: &Path,
+    name: &Path,
+    is_root: bool,
+) -> Result<(), Error> {
+    let mut entries = read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    // sorted for a reproducible layer.
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_name = entry.file_name();
+        // the metadata only describes the toolchain in the rustup home.
+        if is_root && file_name == METADATA_FILE_NAME {
+            continue;
+        }
+        let path = entry.path();
+        let name = name.join(&file_name);
+        builder.append_path_with_name(&path, &name)?;
+        if entry.file_type()?.is_dir() {
+            append_dir(builder, &path, &name, false)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use serde::Deserialize;
+
+/// Where to look for the GitHub token when it is not given explicitly.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenSource {
+    /// The `GITHUB_TOKEN`/`GH_TOKEN` environment variables or the GitHub CLI.
+    Auto,
+    /// The platform credential store.
+    Keyring,
+}
+
+impl FromStr for TokenSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "keyring" => Ok(Self::Keyring),
+            _ => bail!("unknown token source `{}`, expected `auto` or `keyring`", s),
+        }
+    }
+}
+
+#[cfg(feature = "keyring")]
+mod imp {
+    use anyhow::{Context, Error};
+    use keyring::Entry;
+
+    static SERVICE: &str = "rustup-toolchain-install-master";
+    static USER: &str = "github-token";
+
+    fn entry() -> Result<Entry, Error> {
+        Entry::new(SERVICE, USER).context("failed to access the keyring")
+    }
+
+    pub fn load_github_token() -> Result<Option<String>, Error> {
+        match entry()?.get_password() {
+            Ok(token) => Ok(Some(token)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => {
+                Err(Error::new(e).context("failed to read the GitHub token from the keyring"))
+            }
+        }
+    }
+
+    pub fn store_github_token(token: &str) -> Result<(), Error> {
+        entry()?
+            .set_password(token)
+            .context("failed to store the GitHub token in the keyring")
+    }
+
+    pub fn delete_github_token() -> Result<bool, Error> {
+        match entry()?.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => {
+                Err(Error::new(e).context("failed to delete the GitHub token from the keyring"))
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod imp {
+    use anyhow::{bail, Error};
+
+    static UNSUPPORTED: &str =
+        "rustup-toolchain-install-master was compiled without the `keyring` feature";
+
+    pub fn load_github_token() -> Result<Option<String>, Error> {
+        bail!(UNSUPPORTED)
+    }
+
+    pub fn store_github_token(_: &str) -> Result<(), Error> {
+        bail!(UNSUPPORTED)
+    }
+
+    pub fn delete_github_token() -> Result<bool, Error> {
+        bail!(UNSUPPORTED)
+    }
+}
+
+pub use self::imp::{delete_github_token, load_github_token, store_github_token};
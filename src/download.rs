@@ -0,0 +1,547 @@
+use std::fs::{create_dir_all, File};
+use std::io::{self, copy, sink, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{AsHeaderName, CONTENT_LENGTH};
+use reqwest::{StatusCode, Url};
+use ring::digest;
+use tar::Archive;
+
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::http::send;
+use crate::progress::{check_cancelled, Phase, ProgressObserver, ProgressReader};
+use crate::xz;
+
+/// Identifies a single artifact of a toolchain, for error messages.
+#[derive(Debug)]
+pub struct Artifact<'a> {
+    pub commit: &'a str,
+    pub component: &'a str,
+    pub channel: &'a str,
+    pub target: &'a str,
+}
+
+/// Downloads the `.tar.xz`, `.tar.zst` or `.tar.gz` archive at `url` and
+/// extracts it into `dest`, reusing the cached copy if there is one.
+///
+/// Returns the SHA-256 hash of the archive. If `client` is `None`, only the URL
+/// is logged (the dry-run mode) and `None` is returned.
+pub fn download_tarball(
+    client: Option<&Client>,
+    cache: &Cache,
+    url: &str,
+    dest: &Path,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<String>, Error> {
+    fetch_tarball(client, cache, url, None, dest, artifact, observer)
+}
+
+/// Same as [`download_tarball`], but fails with [`Error::ChecksumMismatch`]
+/// unless the SHA-256 hash of the archive is `sha256`, e.g. as listed in a
+/// release manifest. A corrupted archive is never stored in the cache.
+pub fn download_verified_tarball(
+    client: Option<&Client>,
+    cache: &Cache,
+    url: &str,
+    sha256: &str,
+    dest: &Path,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<String>, Error> {
+    fetch_tarball(client, cache, url, Some(sha256), dest, artifact, observer)
+}
+
+fn fetch_tarball(
+    client: Option<&Client>,
+    cache: &Cache,
+    url: &str,
+    sha256: Option<&str>,
+    dest: &Path,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<String>, Error> {
+    check_cancelled(observer)?;
+    if let Some(path) = local_path(url) {
+        observer.phase(Phase::Downloading { url });
+        if client.is_some() {
+            let file = open_local(&path, url, artifact)?;
+            let length = file.metadata()?.len();
+            let mut reader =
+                Sha256Reader::new(ProgressReader::new(file, Some(length), observer), sha256);
+            unpack_tarball(&mut reader, dest, observer)?;
+            observer.download_finished();
+            return reader.verify(url).map(Some);
+        }
+        return Ok(None);
+    }
+    let _lock = match client {
+        Some(_) => cache.lock_download(url, observer)?,
+        None => None,
+    };
+    let cached = match client {
+        Some(_) => intact_cached(cache, url, sha256, observer)?,
+        None => cache.get(url),
+    };
+    if let Some(path) = cached {
+        observer.phase(Phase::UsingCache { url });
+        if client.is_some() {
+            let file = File::open(&path)?;
+            let length = file.metadata()?.len();
+            let mut reader =
+                Sha256Reader::new(ProgressReader::new(file, Some(length), observer), sha256);
+            unpack_tarball(&mut reader, dest, observer)?;
+            observer.download_finished();
+            return reader.verify(url).map(Some);
+        }
+        return Ok(None);
+    }
+
+    if cache.is_offline() {
+        return Err(Error::Offline(format!("<{}> is not in the cache", url)));
+    }
+    observer.phase(Phase::Downloading { url });
+    if let Some(client) = client {
+        let response = get_artifact(client, url, artifact, observer)?;
+        let length = parse_header(&response, CONTENT_LENGTH);
+        let mut reader = Sha256Reader::new(ProgressReader::new(response, length, observer), sha256);
+
+        let sha256 = if let Some(mut pending) = cache.begin_download(url)? {
+            copy(&mut reader, pending.file_mut()).map_err(|err| or_cancelled(observer, err))?;
+            observer.download_finished();
+            let sha256 = reader.verify(url)?;
+            let path = pending.finish(&sha256)?;
+            unpack_tarball(File::open(path)?, dest, observer)?;
+            sha256
+        } else {
+            unpack_tarball(&mut reader, dest, observer)?;
+            observer.download_finished();
+            reader.verify(url)?
+        };
+        return Ok(Some(sha256));
+    }
+
+    Ok(None)
+}
+
+/// Returns the cached artifact of `url` unless its hash does not match
+/// `sha256` or the hash recorded when it was downloaded, in which case it is
+/// removed to be downloaded again.
+fn intact_cached(
+    cache: &Cache,
+    url: &str,
+    sha256: Option<&str>,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<PathBuf>, Error> {
+    let path = match cache.get(url) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let expected = match sha256
+        .map(str::to_owned)
+        .or_else(|| cache.recorded_sha256(url))
+    {
+        Some(expected) => expected,
+        // cached by an older version, which did not record the hashes.
+        None => return Ok(Some(path)),
+    };
+    let actual = Sha256Reader::new(File::open(&path)?, None).verify(url)?;
+    if actual.eq_ignore_ascii_case(&expected) {
+        return Ok(Some(path));
+    }
+    observer.phase(Phase::DiscardingCache { url });
+    cache.evict(url)?;
+    Ok(None)
+}
+
+/// Returns the path of a `file://` URL, which is read directly instead of
+/// downloaded.
+pub(crate) fn local_path(url: &str) -> Option<PathBuf> {
+    Url::parse(url)
+        .ok()
+        .filter(|url| url.scheme() == "file")?
+        .to_file_path()
+        .ok()
+}
+
+/// Opens the local file at `path` of the `file://` URL `url`, turning a missing
+/// file into [`Error::ArtifactNotFound`] like a 404 from a server.
+fn open_local(path: &Path, url: &str, artifact: &Artifact<'_>) -> Result<File, Error> {
+    match File::open(path) {
+        Ok(file) => Ok(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(Error::ArtifactNotFound {
+            commit: artifact.commit.to_owned(),
+            component: artifact.component.to_owned(),
+            channel: artifact.channel.to_owned(),
+            target: artifact.target.to_owned(),
+            url: Some(url.to_owned()),
+        }),
+        Err(err) => Err(Error::Other(
+            anyhow::Error::new(err).context(format!("failed to open `{}`", path.display())),
+        )),
+    }
+}
+
+/// Picks the first of the `urls` of an artifact, one for each of the archive
+/// extensions, which is a local file, is cached or exists on the server
+/// according to a HEAD request. Returns the first URL if none of them exists,
+/// so the download fails with [`Error::ArtifactNotFound`] as usual.
+pub(crate) fn probe_archive(
+    client: &Client,
+    cache: &Cache,
+    urls: Vec<String>,
+    observer: &dyn ProgressObserver,
+) -> Result<String, Error> {
+    if let [url] = &urls[..] {
+        return Ok(url.clone());
+    }
+    let found = urls.iter().find(|url| match local_path(url) {
+        Some(path) => path.is_file(),
+        None => cache.get(url).is_some(),
+    });
+    if let Some(url) = found {
+        return Ok(url.clone());
+    }
+    if cache.is_offline() {
+        return Ok(urls[0].clone());
+    }
+    for url in &urls {
+        check_cancelled(observer)?;
+        if local_path(url).is_some() {
+            continue;
+        }
+        let response = send(client, client.head(url), observer)?;
+        match response.status() {
+            StatusCode::OK => return Ok(url.clone()),
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {}
+            status => {
+                return Err(Error::UnexpectedStatus {
+                    status,
+                    url: response_url(url, &response),
+                })
+            }
+        }
+    }
+    Ok(urls[0].clone())
+}
+
+/// A reader which computes the SHA-256 hash of the content, to verify it or
+/// to record it.
+struct Sha256Reader<'a, R> {
+    inner: R,
+    expected: Option<&'a str>,
+    context: digest::Context,
+}
+
+impl<'a, R: Read> Sha256Reader<'a, R> {
+    fn new(inner: R, expected: Option<&'a str>) -> Self {
+        Self {
+            inner,
+            expected,
+            context: digest::Context::new(&digest::SHA256),
+        }
+    }
+
+    /// Reads the rest of the content, and returns the hash of the whole
+    /// content of `url` after checking it against the expected one, if any.
+    fn verify(mut self, url: &str) -> Result<String, Error> {
+        // the archive may end before the padding at the end of the file, which
+        // is read anyway so the connection can be reused for the next request.
+        copy(&mut self, &mut sink())?;
+        let actual = self
+            .context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        match self.expected {
+            Some(expected) if !actual.eq_ignore_ascii_case(expected) => {
+                Err(Error::ChecksumMismatch {
+                    url: url.to_owned(),
+                    expected: expected.to_owned(),
+                    actual,
+                })
+            }
+            _ => Ok(actual),
+        }
+    }
+}
+
+impl<R: Read> Read for Sha256Reader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.context.update(&buf[..amount]);
+        Ok(amount)
+    }
+}
+
+/// Downloads the artifact at `url` into the cache without extracting it,
+/// unless it is cached already.
+///
+/// Fails if `cache` does not keep downloads.
+pub fn prefetch_artifact(
+    client: &Client,
+    cache: &Cache,
+    url: &str,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    check_cancelled(observer)?;
+    // local files are read in place when installing.
+    if local_path(url).is_some() {
+        return Ok(());
+    }
+    let _lock = cache.lock_download(url, observer)?;
+    if intact_cached(cache, url, None, observer)?.is_some() {
+        observer.phase(Phase::UsingCache { url });
+        return Ok(());
+    }
+    if cache.is_offline() {
+        return Err(Error::Offline(format!("<{}> is not in the cache", url)));
+    }
+    let mut pending = cache
+        .begin_download(url)?
+        .ok_or_else(|| anyhow::anyhow!("the cache does not keep downloads"))?;
+
+    observer.phase(Phase::Downloading { url });
+    let response = get_artifact(client, url, artifact, observer)?;
+    let length = parse_header(&response, CONTENT_LENGTH);
+    let mut reader = Sha256Reader::new(ProgressReader::new(response, length, observer), None);
+    copy(&mut reader, pending.file_mut()).map_err(|err| or_cancelled(observer, err))?;
+    observer.download_finished();
+    let sha256 = reader.verify(url)?;
+    pending.finish(&sha256)?;
+    Ok(())
+}
+
+/// Returns the size of the artifact at `url` without downloading it, and
+/// whether it would be downloaded: a local or cached archive is measured,
+/// otherwise the server is asked with a HEAD request.
+pub fn artifact_size(
+    client: &Client,
+    cache: &Cache,
+    url: &str,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<(u64, bool), Error> {
+    check_cancelled(observer)?;
+    if let Some(path) = local_path(url) {
+        return Ok((open_local(&path, url, artifact)?.metadata()?.len(), false));
+    }
+    if let Some(path) = cache.get(url) {
+        let metadata = path
+            .metadata()
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        return Ok((metadata.len(), false));
+    }
+    if cache.is_offline() {
+        return Err(Error::Offline(format!("<{}> is not in the cache", url)));
+    }
+    let response = send(client, client.head(url), observer)?;
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {
+            return Err(Error::ArtifactNotFound {
+                commit: artifact.commit.to_owned(),
+                component: artifact.component.to_owned(),
+                channel: artifact.channel.to_owned(),
+                target: artifact.target.to_owned(),
+                url: Some(response_url(url, &response)),
+            })
+        }
+        status => {
+            return Err(Error::UnexpectedStatus {
+                status,
+                url: response_url(url, &response),
+            })
+        }
+    }
+    let size = parse_header(&response, CONTENT_LENGTH)
+        .ok_or_else(|| Error::Other(anyhow::anyhow!("<{}> has no Content-Length", url)))?;
+    Ok((size, true))
+}
+
+/// Requests the artifact at `url`, turning a 404 or 403 into
+/// [`Error::ArtifactNotFound`].
+fn get_artifact(
+    client: &Client,
+    url: &str,
+    artifact: &Artifact<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<Response, Error> {
+    let response = send(client, client.get(url), observer)?;
+    let status = response.status();
+    if status == StatusCode::OK {
+        return Ok(response);
+    }
+    let url = response_url(url, &response);
+    discard(response);
+    match status {
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Err(Error::ArtifactNotFound {
+            commit: artifact.commit.to_owned(),
+            component: artifact.component.to_owned(),
+            channel: artifact.channel.to_owned(),
+            target: artifact.target.to_owned(),
+            url: Some(url),
+        }),
+        status => Err(Error::UnexpectedStatus { status, url }),
+    }
+}
+
+/// Extracts a `.tar.xz`, `.tar.zst` or `.tar.gz` archive of a rustc component
+/// into `dest`, dropping the top two levels of directories.
+pub fn unpack_tarball(
+    reader: impl Read,
+    dest: &Path,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    unpack_entries(reader, dest, observer).map_err(|err| or_cancelled(observer, err))
+}
+
+fn unpack_entries(
+    reader: impl Read,
+    dest: &Path,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    let reader = decompress(reader)?;
+    for entry in Archive::new(reader).entries()? {
+        check_cancelled(observer)?;
+        let mut entry = entry?;
+        let relpath = entry.path()?;
+        let is_version = relpath.components().count() == 2
+            && relpath.ends_with("version")
+            && entry.header().entry_type().is_file();
+
+        let mut components = relpath.components();
+
+        // Reject path components that are not normal (.|..|/| etc)
+        for part in components.clone() {
+            match part {
+                std::path::Component::Normal(_) => {}
+                _ => {
+                    return Err(Error::InvalidArchive(format!(
+                        "bad path in tar: {}",
+                        relpath.display()
+                    )))
+                }
+            }
+        }
+
+        // Throw away the first two path components: our root was supplied
+        components.next();
+        components.next();
+
+        let full_path = dest.join(components.as_path());
+        if full_path == dest {
+            if is_version {
+                let mut version = String::new();
+                entry.read_to_string(&mut version)?;
+                observer.version_read(version.trim());
+            }
+            // The tmp dir code makes the root dir for us.
+            continue;
+        }
+
+        // Bail out if we get hard links, device nodes or any other unusual content
+        // - it is most likely an attack, as rusts cross-platform nature precludes
+        // such artifacts
+        let kind = entry.header().entry_type();
+
+        match kind {
+            tar::EntryType::Directory => {
+                create_dir_all(full_path)?;
+            }
+            tar::EntryType::Regular => {
+                entry.unpack(&full_path)?;
+                observer.file_extracted(&full_path);
+            }
+            _ => {
+                return Err(Error::InvalidArchive(format!(
+                    "unsupported tar entry: {:?}",
+                    kind
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a reader of the decompressed content of the xz, zstd or gzip
+/// stream, after checking that it starts like one. A captive portal or a proxy
+/// may answer with an HTML page instead, which would otherwise fail deep
+/// inside the decoder.
+fn decompress<'a>(mut reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>, Error> {
+    const XZ_MAGIC: &[u8] = b"\xfd7zXZ\0";
+    const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+    const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+    let mut magic = Vec::with_capacity(XZ_MAGIC.len());
+    (&mut reader)
+        .take(XZ_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let reader = io::Cursor::new(magic.clone()).chain(reader);
+    if magic.starts_with(XZ_MAGIC) {
+        Ok(xz::decoder(reader)?)
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::new(reader)?))
+    } else if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else {
+        let content = if magic.is_empty() {
+            "empty content"
+        } else if magic.trim_ascii_start().starts_with(b"<") {
+            "an HTML page"
+        } else {
+            "content which is not an xz, zstd or gzip archive"
+        };
+        Err(Error::InvalidArchive(format!(
+            "the server returned {} instead of an archive (is a proxy intercepting the downloads?)",
+            content
+        )))
+    }
+}
+
+/// Replaces the error caused by a cancelled read with [`Error::Cancelled`].
+fn or_cancelled(observer: &dyn ProgressObserver, err: impl Into<Error>) -> Error {
+    if observer.is_cancelled() {
+        Error::Cancelled
+    } else {
+        err.into()
+    }
+}
+
+pub(crate) fn parse_header<T: FromStr>(response: &Response, name: impl AsHeaderName) -> Option<T> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse().ok())
+}
+
+/// The size of an error page which is read to reuse its connection, instead
+/// of closing it.
+const DISCARDED_BODY_LIMIT: u64 = 64 * 1024;
+
+/// Drops a response which is not needed, reading a short body first so the
+/// connection goes back to the pool.
+fn discard(response: Response) {
+    let _ = copy(&mut response.take(DISCARDED_BODY_LIMIT), &mut sink());
+}
+
+/// Describes the URL `response` was received from for error messages, which
+/// differs from the requested `url` if the request was redirected.
+pub(crate) fn response_url(url: &str, response: &Response) -> String {
+    let final_url = response.url().as_str();
+    if final_url == url {
+        url.to_owned()
+    } else {
+        format!("{} (redirected from {})", final_url, url)
+    }
+}
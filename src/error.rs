@@ -0,0 +1,137 @@
+use std::io;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use reqwest::StatusCode;
+
+/// The errors returned by the library.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An artifact of the toolchain does not exist on the server, e.g. the
+    /// component is not built for the target. The server answers either 404
+    /// or 403 for a missing artifact.
+    #[error(
+        "missing component `{component}` on toolchain `{commit}` on channel `{channel}` for target `{target}`{}",
+        missing_reason(.url)
+    )]
+    ArtifactNotFound {
+        commit: String,
+        component: String,
+        channel: String,
+        target: String,
+        /// The URL which was probed, unless the artifact is missing from a
+        /// release manifest.
+        url: Option<String>,
+    },
+
+    /// The commit has no artifacts in any channel. CI artifacts are deleted
+    /// after a while, so this usually means the commit is too old, although
+    /// it could also have never been built by CI at all.
+    #[error(
+        "toolchain `{commit}` doesn't exist in any channel: CI artifacts are deleted after a while, \
+         and only the merge commits of bors are built"
+    )]
+    ArtifactExpired { commit: String },
+
+    /// A toolchain with the same name is installed, and replacing it is not
+    /// forced.
+    #[error("toolchain `{name}` is already installed")]
+    AlreadyInstalled { name: String },
+
+    /// The name cannot be used for a toolchain, e.g. it contains a path
+    /// separator or is reserved by rustup.
+    #[error("invalid toolchain name `{name}`: {reason}")]
+    InvalidToolchainName { name: String, reason: &'static str },
+
+    /// The directory of rustup cannot be written to, e.g. the rustup home of a
+    /// shared machine owned by root.
+    #[error("`{}` is not writable", .path.display())]
+    NotWritable { path: PathBuf },
+
+    /// No installed toolchain matches the name or commit given by the user.
+    #[error("no installed toolchain matches `{query}`")]
+    ToolchainNotFound { query: String },
+
+    /// Several installed toolchains match the abbreviated name or commit given
+    /// by the user.
+    #[error(
+        "`{query}` matches several installed toolchains: {}",
+        .candidates.join(", ")
+    )]
+    AmbiguousToolchain {
+        query: String,
+        candidates: Vec<String>,
+    },
+
+    /// The `toolchains` directory of rustup does not exist.
+    #[error("`{}` is not a directory. please reinstall rustup.", .path.display())]
+    RustupHomeMissing { path: PathBuf },
+
+    /// The GitHub API refused to serve more requests.
+    #[error(
+        "GitHub API rate limit exceeded{}: {}",
+        .reset.map(|r| r.format(", resets at %Y-%m-%d %H:%M:%S %:z").to_string()).unwrap_or_default(),
+        if *.authenticated {
+            "the rate limit of the supplied GitHub token is exhausted"
+        } else {
+            "no GitHub token was supplied, which would raise the rate limit"
+        }
+    )]
+    RateLimitExceeded {
+        /// When the rate limit will be reset, if the server told so.
+        reset: Option<DateTime<Local>>,
+        /// Whether the requests were authenticated with a GitHub token.
+        authenticated: bool,
+    },
+
+    /// The server responded with an unexpected status code.
+    #[error("received status {status} for {url}")]
+    UnexpectedStatus { status: StatusCode, url: String },
+
+    /// The commit of the master branch could not be determined.
+    #[error("{0}")]
+    MasterCommitUnavailable(String),
+
+    /// The downloaded archive does not have the hash listed in the manifest.
+    #[error("checksum mismatch for {url}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// The downloaded archive contains something which cannot be installed.
+    #[error("{0}")]
+    InvalidArchive(String),
+
+    /// The operation needs the network, but the cache is offline.
+    #[error("{0}, and the network is not used when offline")]
+    Offline(String),
+
+    /// The [`crate::ProgressObserver`] asked to cancel the operation.
+    #[error("the operation was cancelled")]
+    Cancelled,
+
+    /// Sending the request or receiving the response failed.
+    #[error(transparent)]
+    NetworkError(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// Any other failure, e.g. writing the metadata or the cache.
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+fn missing_reason(url: &Option<String>) -> String {
+    match url {
+        Some(url) => format!(
+            " at {}: the component may not be built for this target, \
+             or the artifacts of the commit may have expired",
+            url
+        ),
+        None => ": the release does not include it".to_owned(),
+    }
+}
@@ -0,0 +1,105 @@
+use std::env::{join_paths, split_paths, var_os};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{ensure, Context, Error};
+
+/// Prepares to run `program` with the toolchain `name`, by setting
+/// `RUSTUP_TOOLCHAIN` and putting the `bin` directory of the toolchain first
+/// in `PATH`.
+pub fn toolchain_command(
+    program: impl AsRef<OsStr>,
+    toolchains_path: &Path,
+    name: &str,
+) -> Result<Command, Error> {
+    let mut paths = vec![toolchains_path.join(name).join("bin")];
+    if let Some(path) = var_os("PATH") {
+        paths.extend(split_paths(&path));
+    }
+    let mut command = Command::new(program);
+    command
+        .env("RUSTUP_TOOLCHAIN", name)
+        .env("PATH", join_paths(paths)?);
+    Ok(command)
+}
+
+/// Runs the shell command `script` with the toolchain `name`, failing if the
+/// command fails.
+pub fn run_with_toolchain(script: &str, toolchains_path: &Path, name: &str) -> Result<(), Error> {
+    let mut command = if cfg!(windows) {
+        let mut command = toolchain_command("cmd", toolchains_path, name)?;
+        command.arg("/C");
+        command
+    } else {
+        let mut command = toolchain_command("sh", toolchains_path, name)?;
+        command.arg("-c");
+        command
+    };
+    eprintln!("running `{}` with toolchain `{}`...", script, name);
+    let status = command
+        .arg(script)
+        .status()
+        .with_context(|| format!("failed to run `{}`", script))?;
+    ensure!(status.success(), "`{}` exited with {}", script, status);
+    Ok(())
+}
+
+/// A command run by `run` exited unsuccessfully, so this tool exits with the
+/// same `code` without reporting an error itself.
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub code: i32,
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the command exited with code {}", self.code)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// Runs the program and arguments of `command` with the toolchain `name`,
+/// failing with [`CommandFailed`] if it does not succeed.
+pub fn exec_with_toolchain(
+    command: &[String],
+    toolchains_path: &Path,
+    name: &str,
+) -> Result<(), Error> {
+    let status = toolchain_command(&command[0], toolchains_path, name)?
+        .args(&command[1..])
+        .status()
+        .with_context(|| format!("failed to run `{}`", command[0]))?;
+    if status.success() {
+        return Ok(());
+    }
+    // a command killed by a signal has no exit code on unix.
+    Err(CommandFailed {
+        code: status.code().unwrap_or(1),
+    }
+    .into())
+}
+
+/// Runs `cargo <cargo_command>` in the crate at `path` with the toolchain
+/// `name`, returning whether it passed.
+pub fn check_crate(
+    path: &Path,
+    cargo_command: &str,
+    toolchains_path: &Path,
+    name: &str,
+) -> Result<bool, Error> {
+    eprintln!(
+        "running `cargo {}` in `{}` with toolchain `{}`...",
+        cargo_command,
+        path.display(),
+        name
+    );
+    let status = toolchain_command("cargo", toolchains_path, name)?
+        .arg(cargo_command)
+        .current_dir(path)
+        .status()
+        .context("failed to run cargo")?;
+    Ok(status.success())
+}
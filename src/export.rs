@@ -0,0 +1,139 @@
+use std::fs::{read_dir, read_link};
+use std::io;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::install::check_toolchain_name;
+use crate::metadata::InstallMetadata;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// The prefix cargo-bisect-rustc gives the names of CI toolchains.
+static BISECT_PREFIX: &str = "bisector-ci-";
+
+/// Returns the name cargo-bisect-rustc gives the toolchain built from
+/// `commit`, so it can reuse toolchains installed under that name.
+pub fn bisect_toolchain_name(commit: &str, alt: bool, host: &str) -> String {
+    format!(
+        "{}{}{}-{}",
+        BISECT_PREFIX,
+        commit,
+        if alt { "-alt" } else { "" },
+        host
+    )
+}
+
+/// Links every toolchain installed by this tool under the name expected by
+/// cargo-bisect-rustc, so it does not download the toolchains again.
+pub fn export_for_bisect(
+    toolchains_path: &Path,
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    for entry in read_dir(toolchains_path)? {
+        check_cancelled(observer)?;
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let metadata = match InstallMetadata::read(&path) {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => continue,
+            Err(err) => {
+                observer.warning(&err);
+                continue;
+            }
+        };
+
+        let link_name = bisect_toolchain_name(&metadata.commit, metadata.alt, &metadata.host);
+        let link = toolchains_path.join(&link_name);
+        if link.symlink_metadata().is_ok() {
+            continue;
+        }
+        observer.phase(Phase::Exporting {
+            name: &entry.file_name().to_string_lossy(),
+            link_name: &link_name,
+            dry_run,
+        });
+        if !dry_run {
+            // link relatively, so the link survives moving the rustup home.
+            symlink_dir(Path::new(&entry.file_name()), &link)?;
+        }
+    }
+    Ok(())
+}
+
+/// Links the installed toolchain `name` as `alias` as well, so it can be
+/// referred to by either name. An existing link of that name is only replaced
+/// if `force` is set, and a toolchain of that name never.
+pub fn alias_toolchain(
+    toolchains_path: &Path,
+    name: &str,
+    alias: &str,
+    force: bool,
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    check_toolchain_name(alias)?;
+    let link = toolchains_path.join(alias);
+    match link.symlink_metadata() {
+        Ok(_) if read_link(&link).is_ok_and(|target| target == Path::new(name)) => return Ok(()),
+        Ok(metadata) if metadata.file_type().is_symlink() && force => {
+            if !dry_run {
+                remove_link(&link)?;
+            }
+        }
+        Ok(_) => {
+            return Err(Error::AlreadyInstalled {
+                name: alias.to_owned(),
+            })
+        }
+        Err(_) => {}
+    }
+    observer.phase(Phase::Exporting {
+        name,
+        link_name: alias,
+        dry_run,
+    });
+    if !dry_run {
+        symlink_dir(Path::new(name), &link)?;
+    }
+    Ok(())
+}
+
+/// Removes the links created by [`export_for_bisect`] and
+/// [`alias_toolchain`] whose toolchains are no longer installed.
+pub(crate) fn remove_dangling_links(toolchains_path: &Path) -> Result<(), Error> {
+    for entry in read_dir(toolchains_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        // both link to the name of a toolchain next to them.
+        let is_own_link = read_link(&path)
+            .is_ok_and(|target| target.components().count() == 1 && target.file_name().is_some());
+        if entry.file_type()?.is_symlink() && is_own_link && !path.exists() {
+            remove_link(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+#[cfg(unix)]
+fn remove_link(link: &Path) -> io::Result<()> {
+    std::fs::remove_file(link)
+}
+
+#[cfg(windows)]
+fn remove_link(link: &Path) -> io::Result<()> {
+    // directory symlinks are removed like directories on Windows.
+    std::fs::remove_dir(link)
+}
@@ -0,0 +1,443 @@
+//! Records which files each component extracted into a toolchain with their
+//! hashes, so a single component can be removed again later, and the files
+//! can be checked for changes.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{
+    create_dir_all, read_dir, read_to_string, remove_dir, remove_file, symlink_metadata, write,
+    File,
+};
+use std::io::{self, copy, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::http::HttpExchange;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// The prefix of the file lists in `lib/rustlib`, followed by the component
+/// and the target, e.g. `rtim-files-rustc-dev-x86_64-unknown-linux-gnu`.
+///
+/// Each line of a list is the path of a file relative to the toolchain,
+/// followed by a tab and its SHA-256 hash. Lists written by older versions
+/// lack the hashes.
+static FILE_LIST_PREFIX: &str = "rtim-files-";
+
+/// How the permissions of the extracted files are set.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileModes {
+    /// The modes stored in the archives are kept.
+    #[default]
+    Archive,
+    /// Directories and executables get `755`, and other files `644`.
+    Normalize,
+    /// Like [`FileModes::Normalize`], but writable by the group, i.e. `775`
+    /// and `664`.
+    NormalizeGroup,
+}
+
+impl FileModes {
+    /// Whether the modes stored in the archives are kept.
+    pub fn is_archive(&self) -> bool {
+        *self == Self::Archive
+    }
+
+    /// Returns the mode a directory, or a file which is executable if
+    /// `executable`, is set to, unless the archive modes are kept.
+    fn mode(self, is_dir: bool, executable: bool) -> Option<u32> {
+        let group_write = if self == Self::NormalizeGroup {
+            0o020
+        } else {
+            0
+        };
+        match self {
+            Self::Archive => None,
+            _ if is_dir || executable => Some(0o755 | group_write),
+            _ => Some(0o644 | group_write),
+        }
+    }
+}
+
+impl FromStr for FileModes {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "archive" => Ok(Self::Archive),
+            "normalize" => Ok(Self::Normalize),
+            "normalize-group" => Ok(Self::NormalizeGroup),
+            _ => Err(Error::Other(anyhow!(
+                "unknown file modes `{}`, expected `archive`, `normalize` or `normalize-group`",
+                s
+            ))),
+        }
+    }
+}
+
+/// The result of [`crate::audit_toolchain`].
+#[derive(Debug, Default, Clone)]
+pub struct Audit {
+    /// The number of files which have been checked.
+    pub checked: usize,
+    /// The files which have been deleted since they were extracted.
+    pub missing: Vec<String>,
+    /// The files whose content has changed since they were extracted.
+    pub modified: Vec<String>,
+    /// The number of files which cannot be checked for changes, since their
+    /// hashes were not recorded.
+    pub unhashed: usize,
+}
+
+impl Audit {
+    /// Whether every file which could be checked is unchanged.
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Forwards the progress to `inner`, recording the extracted files.
+pub(crate) struct FileRecorder<'a> {
+    inner: &'a dyn ProgressObserver,
+    files: RefCell<Vec<PathBuf>>,
+    version: RefCell<Option<String>>,
+}
+
+impl<'a> FileRecorder<'a> {
+    pub(crate) fn new(inner: &'a dyn ProgressObserver) -> Self {
+        Self {
+            inner,
+            files: RefCell::new(Vec::new()),
+            version: RefCell::new(None),
+        }
+    }
+
+    /// Returns the version of the component read from its archive, if any.
+    pub(crate) fn version(&self) -> Option<String> {
+        self.version.borrow().clone()
+    }
+
+    /// Sets the permissions of the files extracted so far, and of the
+    /// directories containing them inside `root`, according to `modes`.
+    pub(crate) fn set_modes(&self, root: &Path, modes: FileModes) -> Result<(), Error> {
+        if modes.is_archive() {
+            return Ok(());
+        }
+        let mut dirs = BTreeSet::new();
+        for file in &*self.files.borrow() {
+            set_mode(file, modes)?;
+            for dir in file.ancestors().skip(1) {
+                if !dir.starts_with(root) || !dirs.insert(dir.to_owned()) {
+                    break;
+                }
+            }
+        }
+        for dir in &dirs {
+            set_mode(dir, modes)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the list of the files extracted so far into the toolchain being
+    /// assembled in `root`, as the files of `component` for `target`, with
+    /// the permissions given by `modes`.
+    pub(crate) fn write(
+        self,
+        root: &Path,
+        component: &str,
+        target: &str,
+        modes: FileModes,
+    ) -> Result<(), Error> {
+        let mut list = String::new();
+        for file in self.files.into_inner() {
+            let relative = file.strip_prefix(root).map_err(|_| {
+                anyhow!(
+                    "`{}` was extracted outside of `{}`",
+                    file.display(),
+                    root.display()
+                )
+            })?;
+            for (i, part) in relative.components().enumerate() {
+                if i > 0 {
+                    list.push('/');
+                }
+                list.push_str(&part.as_os_str().to_string_lossy());
+            }
+            list.push('\t');
+            list.push_str(
+                &sha256_file(&file)
+                    .with_context(|| format!("failed to read `{}`", file.display()))?,
+            );
+            list.push('\n');
+        }
+        let path = file_list_path(root, component, target);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(&path, list).with_context(|| format!("failed to write `{}`", path.display()))?;
+        set_mode(&path, modes)
+    }
+}
+
+impl ProgressObserver for FileRecorder<'_> {
+    fn phase(&self, phase: Phase<'_>) {
+        self.inner.phase(phase);
+    }
+
+    fn download_started(&self, length: Option<u64>) {
+        self.inner.download_started(length);
+    }
+
+    fn bytes_read(&self, amount: u64) {
+        self.inner.bytes_read(amount);
+    }
+
+    fn download_finished(&self) {
+        self.inner.download_finished();
+    }
+
+    fn file_extracted(&self, path: &Path) {
+        self.files.borrow_mut().push(path.to_owned());
+        self.inner.file_extracted(path);
+    }
+
+    fn version_read(&self, version: &str) {
+        *self.version.borrow_mut() = Some(version.to_owned());
+        self.inner.version_read(version);
+    }
+
+    fn http_exchange(&self, exchange: &HttpExchange<'_>) {
+        self.inner.http_exchange(exchange);
+    }
+
+    fn warning(&self, warning: &anyhow::Error) {
+        self.inner.warning(warning);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// Sets the permissions of the file or directory at `path` according to
+/// `modes`.
+#[cfg(unix)]
+pub(crate) fn set_mode(path: &Path, modes: FileModes) -> Result<(), Error> {
+    use std::fs::{set_permissions, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = symlink_metadata(path)?;
+    let executable = metadata.permissions().mode() & 0o111 != 0;
+    if let Some(mode) = modes.mode(metadata.is_dir(), executable) {
+        set_permissions(path, Permissions::from_mode(mode))
+            .with_context(|| format!("failed to change the permissions of `{}`", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Only Unix has modes.
+#[cfg(not(unix))]
+pub(crate) fn set_mode(_: &Path, _: FileModes) -> Result<(), Error> {
+    Ok(())
+}
+
+fn file_list_path(root: &Path, component: &str, target: &str) -> PathBuf {
+    root.join("lib")
+        .join("rustlib")
+        .join(format!("{}{}-{}", FILE_LIST_PREFIX, component, target))
+}
+
+/// Returns the total size of the files `component` for `target` extracted
+/// into the toolchain at `root`, or `None` if they were not recorded.
+pub(crate) fn installed_size(root: &Path, component: &str, target: &str) -> Option<u64> {
+    let files = read_file_list(&file_list_path(root, component, target)).ok()?;
+    let size = files
+        .iter()
+        .filter_map(|file| symlink_metadata(root.join(file)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Some(size)
+}
+
+/// Reads the files listed in `path`, relative to the toolchain.
+fn read_file_list(path: &Path) -> io::Result<Vec<String>> {
+    Ok(read_hashed_file_list(path)?
+        .into_iter()
+        .map(|(file, _)| file)
+        .collect())
+}
+
+/// Reads the files listed in `path` with their hashes, if recorded.
+fn read_hashed_file_list(path: &Path) -> io::Result<Vec<(String, Option<String>)>> {
+    Ok(read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.rsplit_once('\t') {
+            Some((file, hash))
+                if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) =>
+            {
+                (file.to_owned(), Some(hash.to_owned()))
+            }
+            _ => (line.to_owned(), None),
+        })
+        .collect())
+}
+
+/// Returns the SHA-256 hash of the content of the file at `path`.
+fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut writer = DigestWriter(digest::Context::new(&digest::SHA256));
+    copy(&mut File::open(path)?, &mut writer)?;
+    Ok(writer
+        .0
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+struct DigestWriter(digest::Context);
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Checks the files which every component extracted into the toolchain `name`
+/// at `root` against the hashes recorded when they were extracted.
+///
+/// A file extracted by several components only needs to match one of them,
+/// since the last one extracted overwrites the others.
+pub(crate) fn audit_files(
+    root: &Path,
+    name: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<Audit, Error> {
+    let rustlib = root.join("lib").join("rustlib");
+    let mut files = BTreeMap::<String, Vec<Option<String>>>::new();
+    if rustlib.is_dir() {
+        for entry in read_dir(&rustlib)? {
+            let path = entry?.path();
+            let is_list = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_LIST_PREFIX));
+            if is_list {
+                for (file, hash) in read_hashed_file_list(&path)? {
+                    files.entry(file).or_default().push(hash);
+                }
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::Other(anyhow!(
+            "the files of toolchain `{}` were not recorded when it was installed, reinstall it \
+             to audit it",
+            name
+        )));
+    }
+
+    let mut audit = Audit::default();
+    for (file, hashes) in files {
+        check_cancelled(observer)?;
+        let path = root.join(&file);
+        match symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => {
+                audit.modified.push(file);
+                continue;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                audit.missing.push(file);
+                continue;
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("failed to read `{}`", path.display()))
+                    .into())
+            }
+        }
+        let hashes = hashes.into_iter().flatten().collect::<Vec<_>>();
+        if hashes.is_empty() {
+            audit.unhashed += 1;
+            continue;
+        }
+        let actual =
+            sha256_file(&path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        if !hashes.iter().any(|hash| hash.eq_ignore_ascii_case(&actual)) {
+            audit.modified.push(file);
+        }
+        audit.checked += 1;
+    }
+    Ok(audit)
+}
+
+/// Removes the files which `component` for `target` extracted into the
+/// toolchain at `root`, except those which another recorded component
+/// extracted as well, and then the directories left empty.
+pub(crate) fn remove_files(root: &Path, component: &str, target: &str) -> Result<(), Error> {
+    let list_path = file_list_path(root, component, target);
+    let files = match read_file_list(&list_path) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::Other(anyhow!(
+                "the files of `{}` for `{}` were not recorded when the toolchain was installed, \
+                 reinstall it to remove single components",
+                component,
+                target
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut shared = BTreeSet::new();
+    for entry in read_dir(list_path.parent().unwrap_or(root))? {
+        let path = entry?.path();
+        let is_other_list = path != list_path
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_LIST_PREFIX));
+        if is_other_list {
+            shared.extend(read_file_list(&path)?);
+        }
+    }
+
+    for file in files.iter().filter(|file| !shared.contains(*file)) {
+        let path = root.join(file);
+        // the list is only trusted to name files inside the toolchain.
+        if !path
+            .strip_prefix(root)
+            .is_ok_and(|p| p.components().all(|c| matches!(c, Component::Normal(_))))
+        {
+            continue;
+        }
+        match remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("failed to remove `{}`", path.display()))
+                    .into())
+            }
+            _ => {}
+        }
+        for dir in path.ancestors().skip(1) {
+            // stops at the first directory which is not empty.
+            if dir == root || remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+    remove_file(&list_path)?;
+    Ok(())
+}
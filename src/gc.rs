@@ -0,0 +1,146 @@
+use std::cmp::Reverse;
+use std::fs::{create_dir_all, read_dir, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::Utc;
+use remove_dir_all::remove_dir_all;
+use tempfile::{Builder, TempDir};
+
+use crate::error::Error;
+use crate::export::remove_dangling_links;
+use crate::metadata::InstallMetadata;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// Decides which of the toolchains installed by this tool should be kept.
+#[derive(Debug, Default)]
+pub struct RetentionPolicy {
+    /// Toolchains installed longer ago than this are removed.
+    pub max_age: Option<Duration>,
+    /// Only this number of the most recently installed toolchains are kept.
+    pub max_toolchains: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.max_age.is_none() && self.max_toolchains.is_none()
+    }
+}
+
+/// Removes the toolchains which violate the retention policy, and returns what
+/// they were. Toolchains not installed by this tool are never touched.
+pub fn prune(
+    toolchains_path: &Path,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<(String, InstallMetadata)>, Error> {
+    let mut installed = Vec::<(PathBuf, InstallMetadata)>::new();
+    for entry in read_dir(toolchains_path)? {
+        let entry = entry?;
+        // skip the links made by `export_for_bisect`.
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        match InstallMetadata::read(&path) {
+            Ok(Some(metadata)) => installed.push((path, metadata)),
+            Ok(None) => {}
+            Err(err) => observer.warning(&err),
+        }
+    }
+
+    // newest first
+    installed.sort_by_key(|(_, metadata)| Reverse(metadata.installed_at));
+
+    let now = Utc::now();
+    let mut removed = Vec::new();
+    for (i, (path, metadata)) in installed.into_iter().enumerate() {
+        check_cancelled(observer)?;
+        let age = (now - metadata.installed_at).to_std().unwrap_or_default();
+        let reason = if policy.max_age.is_some_and(|max_age| age > max_age) {
+            format!("installed {} days ago", age.as_secs() / 86400)
+        } else if policy.max_toolchains.is_some_and(|max| i >= max) {
+            "too many toolchains are installed".to_owned()
+        } else {
+            continue;
+        };
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        observer.phase(Phase::Pruning {
+            name: &name,
+            reason: &reason,
+            dry_run,
+        });
+        if !dry_run {
+            remove_dir_all(&path)?;
+        }
+        removed.push((name.into_owned(), metadata));
+    }
+
+    if !dry_run {
+        remove_dangling_links(toolchains_path)?;
+    }
+    Ok(removed)
+}
+
+/// The file marking the temporary directories created by this tool.
+static TEMP_DIR_MARKER: &str = ".rtim-extracting";
+
+/// Temporary directories untouched for longer than this were left behind by a
+/// crashed or killed run.
+pub static STALE_TEMP_DIR_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Creates a temporary directory in `tmp_path` to extract toolchains into,
+/// marked so that [`remove_stale_temp_dirs`] recognizes it.
+pub fn create_temp_dir(tmp_path: &Path) -> Result<TempDir, Error> {
+    create_dir_all(tmp_path)?;
+    let dir = Builder::new().prefix("rtim-").tempdir_in(tmp_path)?;
+    File::create(dir.path().join(TEMP_DIR_MARKER))?;
+    Ok(dir)
+}
+
+/// Removes the directories made by [`create_temp_dir`] in `tmp_path` which
+/// have not been modified within `max_age`, and returns their paths. Other
+/// directories of rustup are never touched.
+pub fn remove_stale_temp_dirs(
+    tmp_path: &Path,
+    max_age: Duration,
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<PathBuf>, Error> {
+    let entries = match read_dir(tmp_path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for entry in entries {
+        check_cancelled(observer)?;
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_dir() || !path.join(TEMP_DIR_MARKER).is_file() {
+            continue;
+        }
+        // every toolchain extracted into the directory or moved out of it
+        // updates its modification time, so a long `--watch` keeps its own.
+        let modified = entry.metadata()?.modified()?;
+        if now
+            .duration_since(modified)
+            .map_or(true, |age| age <= max_age)
+        {
+            continue;
+        }
+        observer.phase(Phase::RemovingTempDir {
+            path: &path,
+            dry_run,
+        });
+        if !dry_run {
+            remove_dir_all(&path)?;
+        }
+        removed.push(path);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,61 @@
+//! Workflow commands for GitHub Actions, which folds the output of each phase
+//! into a group and shows errors and warnings as annotations.
+
+use std::env::{var, var_os};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Error};
+
+/// Whether a group has been started and not ended yet.
+static GROUP_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether this runs in a GitHub Actions workflow.
+pub fn is_github_actions() -> bool {
+    var_os("GITHUB_ACTIONS").is_some_and(|value| value == "true")
+}
+
+/// Starts a group of the log titled `title`, ending the previous one.
+pub fn start_group(title: &str) {
+    end_group();
+    eprintln!("::group::{}", escape(title));
+    GROUP_STARTED.store(true, Ordering::SeqCst);
+}
+
+/// Ends the current group of the log, if any.
+pub fn end_group() {
+    if GROUP_STARTED.swap(false, Ordering::SeqCst) {
+        eprintln!("::endgroup::");
+    }
+}
+
+/// Prints `err` with its causes as an annotation of `level`, i.e. `error` or
+/// `warning`, outside of any group so it is not folded away.
+pub fn annotate(level: &str, err: &Error) {
+    end_group();
+    eprintln!("::{}::{}", level, escape(&format!("{:#}", err)));
+}
+
+/// Sets the output `name` of the step to `value`, if the workflow collects
+/// them.
+pub fn set_output(name: &str, value: &str) -> Result<(), Error> {
+    let path = match var("GITHUB_OUTPUT") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return Ok(()),
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open `{}`", path))?;
+    writeln!(file, "{}={}", name, value).with_context(|| format!("failed to write `{}`", path))
+}
+
+/// Escapes the message of a workflow command, which ends at a line break.
+fn escape(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
@@ -0,0 +1,103 @@
+//! The journal of every toolchain installed or removed, kept in the cache
+//! directory to reconstruct what was tested during a long regression hunt.
+
+use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::Cache;
+use crate::error::Error;
+
+/// What happened to the toolchain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Install,
+    Uninstall,
+}
+
+/// Whether the action succeeded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Success,
+    AlreadyInstalled,
+    Failure,
+}
+
+/// A single line of the journal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    pub action: Action,
+    /// The name of the toolchain in rustup.
+    pub name: String,
+    /// The commit of the toolchain, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+    pub outcome: Outcome,
+    /// The error message of a failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(with = "duration_secs")]
+    pub duration: Duration,
+}
+
+/// Appends `entry` to the journal in the cache directory.
+pub fn record(cache: &Cache, entry: &HistoryEntry) -> Result<(), Error> {
+    let path = cache.history_path();
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(entry).context("failed to serialize the history")?;
+    line.push('\n');
+    // a single write to a file opened for appending is never interleaved with
+    // the writes of concurrent runs.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the whole journal, oldest first.
+pub fn read(cache: &Cache) -> Result<Vec<HistoryEntry>, Error> {
+    let path = cache.history_path();
+    let content = match read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let entries = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+    Ok(entries)
+}
+
+mod duration_secs {
+    use std::time::Duration;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
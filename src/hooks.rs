@@ -0,0 +1,60 @@
+//! Runs the commands of the `[hooks]` table in the config file around
+//! installing and removing toolchains, e.g. to scan or register them.
+
+use std::io::stderr;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{ensure, Context, Error};
+
+/// The toolchain a hook runs for, passed to it in `RTIM_HOOK_*` environment
+/// variables.
+pub struct HookContext<'a> {
+    pub name: &'a str,
+    pub path: &'a Path,
+    pub commit: &'a str,
+    pub host: &'a str,
+    pub components: &'a [String],
+    pub targets: &'a [String],
+}
+
+/// Runs the shell command `script` of the hook `hook`, e.g. `post-install`,
+/// failing if the command fails.
+///
+/// The output of the command goes to stderr, to keep stdout for the results
+/// of this tool.
+pub fn run_hook(hook: &str, script: &str, context: &HookContext<'_>) -> Result<(), Error> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    eprintln!(
+        "running the {} hook of toolchain `{}`...",
+        hook, context.name
+    );
+    let status = command
+        .arg(script)
+        .env("RTIM_HOOK", hook)
+        .env("RTIM_HOOK_TOOLCHAIN", context.name)
+        .env("RTIM_HOOK_TOOLCHAIN_PATH", context.path)
+        .env("RTIM_HOOK_COMMIT", context.commit)
+        .env("RTIM_HOOK_HOST", context.host)
+        .env("RTIM_HOOK_COMPONENTS", context.components.join(","))
+        .env("RTIM_HOOK_TARGETS", context.targets.join(","))
+        .stdout(stderr())
+        .status()
+        .with_context(|| format!("failed to run the {} hook `{}`", hook, script))?;
+    ensure!(
+        status.success(),
+        "the {} hook `{}` exited with {}",
+        hook,
+        script,
+        status
+    );
+    Ok(())
+}
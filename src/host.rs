@@ -0,0 +1,166 @@
+//! Detects the triple of the machine at runtime, since the triple this tool is
+//! built for is wrong for a binary run under emulation, e.g. an x86_64 build
+//! under Rosetta on aarch64 macOS.
+
+use std::process::Command;
+
+/// The triple this tool is built for.
+pub static BUILD_HOST: &str = env!("HOST");
+
+/// The hosts CI builds toolchains for which [`detect_host`] may switch to
+/// from an emulated build.
+static NATIVE_HOSTS: &[&str] = &[
+    "aarch64-apple-darwin",
+    "aarch64-pc-windows-gnullvm",
+    "aarch64-pc-windows-msvc",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-unknown-linux-musl",
+    "i686-pc-windows-gnu",
+    "i686-pc-windows-msvc",
+    "i686-unknown-linux-gnu",
+    "x86_64-apple-darwin",
+    "x86_64-pc-windows-gnu",
+    "x86_64-pc-windows-gnullvm",
+    "x86_64-pc-windows-msvc",
+    "x86_64-unknown-linux-gnu",
+    "x86_64-unknown-linux-musl",
+];
+
+/// Returns the triple of the machine this tool runs on.
+///
+/// The architecture of [`BUILD_HOST`] is replaced by the native architecture
+/// if the build cannot run on it natively, e.g. an x86_64 build on aarch64,
+/// but not an i686 build on x86_64. If the native architecture is unknown or
+/// has no toolchains with the ABI of the build, the host of `rustc -vV` is
+/// used, or finally [`BUILD_HOST`] itself.
+pub fn detect_host() -> String {
+    let (build_arch, rest) = BUILD_HOST.split_once('-').unwrap_or((BUILD_HOST, ""));
+    match native_arch() {
+        Some(arch) if runs_natively(build_arch, &arch) => BUILD_HOST.to_owned(),
+        arch => arch
+            .and_then(|arch| native_host(&arch, rest))
+            .or_else(rustc_host)
+            .unwrap_or_else(|| BUILD_HOST.to_owned()),
+    }
+}
+
+/// Returns the host on `arch` with the vendor, OS and ABI `rest` of a build,
+/// if CI builds toolchains for it.
+///
+/// The GNU ABI on aarch64 Windows only exists with LLVM, as `gnullvm`.
+fn native_host(arch: &str, rest: &str) -> Option<String> {
+    let rest = match (arch, rest) {
+        ("aarch64", "pc-windows-gnu") => "pc-windows-gnullvm",
+        _ => rest,
+    };
+    let host = format!("{}-{}", arch, rest);
+    NATIVE_HOSTS.contains(&&*host).then_some(host)
+}
+
+/// Whether a binary built for `build_arch` runs natively on `arch`.
+pub fn runs_natively(build_arch: &str, arch: &str) -> bool {
+    build_arch == arch
+        || matches!(
+            (build_arch, arch),
+            ("i586" | "i686", "x86_64") | ("arm" | "armv7", "aarch64")
+        )
+}
+
+/// Returns the architecture of the machine as used in triples.
+#[cfg(target_os = "macos")]
+fn native_arch() -> Option<String> {
+    // an x86_64 process under Rosetta sees the x86_64 `uname`.
+    let output = Command::new("sysctl")
+        .args(["-n", "sysctl.proc_translated"])
+        .output()
+        .ok()?;
+    if String::from_utf8_lossy(&output.stdout).trim() == "1" {
+        Some("aarch64".to_owned())
+    } else {
+        uname_arch()
+    }
+}
+
+/// Returns the architecture of the machine as used in triples.
+#[cfg(windows)]
+fn native_arch() -> Option<String> {
+    // a 32-bit process under WOW64 sees the architecture of the emulated
+    // process in `PROCESSOR_ARCHITECTURE` and the native one in the other.
+    let arch = std::env::var("PROCESSOR_ARCHITEW6432")
+        .or_else(|_| std::env::var("PROCESSOR_ARCHITECTURE"))
+        .ok()?;
+    match &*arch {
+        "AMD64" => Some("x86_64".to_owned()),
+        "ARM64" => Some("aarch64".to_owned()),
+        "x86" => Some("i686".to_owned()),
+        _ => None,
+    }
+}
+
+/// Returns the architecture of the machine as used in triples.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn native_arch() -> Option<String> {
+    uname_arch()
+}
+
+#[cfg(not(windows))]
+fn uname_arch() -> Option<String> {
+    let output = Command::new("uname").arg("-m").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // other architectures are named differently in triples, e.g. `armv7l`
+    // and `ppc64le`, and left to `rustc -vV`.
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "x86_64" | "amd64" => Some("x86_64".to_owned()),
+        "aarch64" | "arm64" => Some("aarch64".to_owned()),
+        "i386" | "i486" | "i586" | "i686" => Some("i686".to_owned()),
+        _ => None,
+    }
+}
+
+/// Returns the host of `rustc -vV`, if rustc is installed.
+fn rustc_host() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_architectures() {
+        assert!(runs_natively("x86_64", "x86_64"));
+        assert!(runs_natively("i686", "x86_64"));
+        assert!(runs_natively("i586", "x86_64"));
+        assert!(runs_natively("armv7", "aarch64"));
+        assert!(!runs_natively("x86_64", "aarch64"));
+        assert!(!runs_natively("aarch64", "x86_64"));
+        assert!(!runs_natively("x86_64", "i686"));
+    }
+
+    #[test]
+    fn native_hosts() {
+        assert_eq!(
+            native_host("aarch64", "apple-darwin").as_deref(),
+            Some("aarch64-apple-darwin")
+        );
+        assert_eq!(
+            native_host("aarch64", "pc-windows-gnu").as_deref(),
+            Some("aarch64-pc-windows-gnullvm")
+        );
+        assert_eq!(
+            native_host("aarch64", "pc-windows-msvc").as_deref(),
+            Some("aarch64-pc-windows-msvc")
+        );
+        assert_eq!(native_host("aarch64", "unknown-freebsd"), None);
+        assert_eq!(native_host("aarch64", "sun-solaris"), None);
+    }
+}
@@ -0,0 +1,154 @@
+//! Checks that an installed compiler can run and link on this machine, since
+//! installing the toolchain of another `--host` succeeds regardless.
+
+use std::convert::TryInto;
+use std::env::consts::OS;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, Error};
+
+use rustup_toolchain_install_master::{detect_host, runs_natively};
+
+/// The executable format and architecture of a binary.
+struct Binary {
+    format: &'static str,
+    arch: &'static str,
+}
+
+/// Returns the problems of running the toolchain at `path`, installed for
+/// `host`, on this machine, to be reported as warnings.
+pub fn check_host(path: &Path, host: &str) -> Vec<Error> {
+    let mut problems = Vec::new();
+    let machine = detect_host();
+    let rustc = path.join("bin").join(if host.contains("windows") {
+        "rustc.exe"
+    } else {
+        "rustc"
+    });
+    let expected_format = match OS {
+        "windows" => "PE",
+        "macos" | "ios" => "Mach-O",
+        _ => "ELF",
+    };
+    if let Some(binary) = read_binary(&rustc) {
+        let machine_arch = normalize_arch(triple_arch(&machine));
+        if binary.format != expected_format || !runs_natively(binary.arch, machine_arch) {
+            problems.push(anyhow!(
+                "the rustc of toolchain `{}` is a binary for {} ({}), which cannot run natively on \
+                 this {} machine",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                binary.arch,
+                binary.format,
+                machine
+            ));
+            return problems;
+        }
+    }
+    // a windows-gnu compiler runs on a windows-msvc machine, but needs MinGW
+    // instead of Visual Studio to link, and vice versa.
+    if host != machine
+        && triple_arch(host) == triple_arch(&machine)
+        && host.contains("windows")
+        && machine.contains("windows")
+    {
+        problems.push(anyhow!(
+            "toolchain `{}` is installed for `{}` while this machine is `{}`, so it may fail to \
+             link without the matching linker and system libraries",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            host,
+            machine
+        ));
+    }
+    problems
+}
+
+fn triple_arch(triple: &str) -> &str {
+    triple.split('-').next().unwrap_or_default()
+}
+
+/// Names the architecture of a triple like [`read_binary`] does.
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "i386" | "i586" | "i686" => "i686",
+        "riscv64gc" => "riscv64",
+        "powerpc64le" => "powerpc64",
+        arch if arch.starts_with("arm") || arch.starts_with("thumb") => "arm",
+        arch => arch,
+    }
+}
+
+/// Reads the format and architecture from the header of the executable at
+/// `path`, or `None` if it is missing or unrecognized.
+fn read_binary(path: &Path) -> Option<Binary> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(4096)
+        .read_to_end(&mut header)
+        .ok()?;
+    let u16_at = |offset: usize, big_endian: bool| {
+        let bytes = [*header.get(offset)?, *header.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |offset: usize, big_endian: bool| {
+        let bytes = header.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    if header.starts_with(b"\x7fELF") {
+        let arch = match u16_at(18, *header.get(5)? == 2)? {
+            3 => "i686",
+            20 => "powerpc",
+            21 => "powerpc64",
+            22 => "s390x",
+            40 => "arm",
+            62 => "x86_64",
+            183 => "aarch64",
+            243 => "riscv64",
+            258 => "loongarch64",
+            _ => return None,
+        };
+        return Some(Binary {
+            format: "ELF",
+            arch,
+        });
+    }
+    if header.starts_with(b"MZ") {
+        let offset = u32_at(0x3c, false)? as usize;
+        if header.get(offset..offset + 4)? != b"PE\0\0" {
+            return None;
+        }
+        let arch = match u16_at(offset + 4, false)? {
+            0x14c => "i686",
+            0x1c4 => "arm",
+            0x8664 => "x86_64",
+            0xaa64 => "aarch64",
+            _ => return None,
+        };
+        return Some(Binary { format: "PE", arch });
+    }
+    // only thin 64-bit Mach-O binaries, which rustc has been for years.
+    let big_endian = match u32_at(0, true)? {
+        0xfeed_facf => true,
+        0xcffa_edfe => false,
+        _ => return None,
+    };
+    let arch = match u32_at(4, big_endian)? {
+        0x0100_0007 => "x86_64",
+        0x0100_000c => "aarch64",
+        _ => return None,
+    };
+    Some(Binary {
+        format: "Mach-O",
+        arch,
+    })
+}
@@ -0,0 +1,257 @@
+use std::fs::{create_dir_all, read, read_to_string, write, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, ResponseBuilderExt, StatusCode, Url};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::progress::ProgressObserver;
+
+/// A request sent to a server and its response, reported to
+/// [`ProgressObserver::http_exchange`] for tracing.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct HttpExchange<'a> {
+    pub method: &'a Method,
+    pub url: &'a Url,
+    /// The headers of the request, without the default headers of the client.
+    pub request_headers: &'a HeaderMap,
+    /// The response, or the error if none was received.
+    pub result: Result<HttpResponse<'a>, &'a reqwest::Error>,
+    /// The time until the headers of the response were received.
+    pub elapsed: Duration,
+}
+
+/// The head of a response in an [`HttpExchange`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct HttpResponse<'a> {
+    pub status: StatusCode,
+    /// The URL the response came from after following the redirects.
+    pub url: &'a Url,
+    pub headers: &'a HeaderMap,
+}
+
+/// Saves the responses of the servers into a directory, or serves them back
+/// from there without the network, e.g. for testing.
+#[derive(Debug, Clone)]
+pub enum HttpFixtures {
+    /// Saves every response with its body into the directory.
+    Record(PathBuf),
+    /// Answers every request with the response saved into the directory,
+    /// failing for requests which were not recorded.
+    Replay(PathBuf),
+}
+
+static FIXTURES: Mutex<Option<HttpFixtures>> = Mutex::new(None);
+
+/// Records or replays the responses to all requests sent from now on, or
+/// sends them normally again if `fixtures` is `None`.
+///
+/// A recorded body is written to the directory while it is read, and the rest
+/// of it is downloaded when the response is dropped early, so that every
+/// recording is complete.
+pub fn set_http_fixtures(fixtures: Option<HttpFixtures>) {
+    *FIXTURES.lock().unwrap() = fixtures;
+}
+
+/// A recorded response, stored as `<key>.json` next to its body in
+/// `<key>.body`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Recording {
+    method: String,
+    url: String,
+    final_url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+/// Sends `request` with `client`, reporting the exchange to `observer`.
+pub(crate) fn send(
+    client: &Client,
+    request: RequestBuilder,
+    observer: &dyn ProgressObserver,
+) -> Result<Response, Error> {
+    let request = request.build()?;
+    let method = request.method().clone();
+    let url = request.url().clone();
+    let request_headers = request.headers().clone();
+    let fixtures = FIXTURES.lock().unwrap().clone();
+    let start = Instant::now();
+    let result = match &fixtures {
+        Some(HttpFixtures::Replay(dir)) => Ok(replay(dir, &method, &url)?),
+        _ => client.execute(request),
+    };
+    observer.http_exchange(&HttpExchange {
+        method: &method,
+        url: &url,
+        request_headers: &request_headers,
+        result: match &result {
+            Ok(response) => Ok(HttpResponse {
+                status: response.status(),
+                url: response.url(),
+                headers: response.headers(),
+            }),
+            Err(err) => Err(err),
+        },
+        elapsed: start.elapsed(),
+    });
+    match (fixtures, result) {
+        (Some(HttpFixtures::Record(dir)), Ok(response)) => record(&dir, &method, &url, response),
+        (_, result) => Ok(result?),
+    }
+}
+
+/// Returns the path of the recording of `method` on `url` in `dir`, without
+/// the extension.
+fn recording_path(dir: &Path, method: &Method, url: &Url) -> PathBuf {
+    let hash = digest::digest(&digest::SHA256, format!("{} {}", method, url).as_bytes());
+    let hash = hash.as_ref()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    dir.join(format!("{}-{}", method.as_str().to_lowercase(), hash))
+}
+
+fn record(dir: &Path, method: &Method, url: &Url, response: Response) -> Result<Response, Error> {
+    let recording = Recording {
+        method: method.to_string(),
+        url: url.to_string(),
+        final_url: response.url().to_string(),
+        status: response.status().as_u16(),
+        headers: response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+            .collect(),
+    };
+    let path = recording_path(dir, method, url);
+    create_dir_all(dir).with_context(|| format!("failed to create `{}`", dir.display()))?;
+    write(
+        path.with_extension("json"),
+        serde_json::to_string_pretty(&recording).context("failed to serialize the response")?
+            + "\n",
+    )?;
+    let file = File::create(path.with_extension("body"))?;
+    let body = futures_util::stream::iter(TeeBody { response, file });
+    to_response(recording, reqwest::Body::wrap_stream(body))
+}
+
+/// The size of the chunks of a recorded body passed on to the caller.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Yields the body of `response` in chunks, copying them into `file`.
+struct TeeBody {
+    response: Response,
+    file: File,
+}
+
+impl Iterator for TeeBody {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match self.response.read(&mut chunk) {
+            Ok(0) => None,
+            Ok(len) => {
+                chunk.truncate(len);
+                Some(self.file.write_all(&chunk).map(|()| chunk))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl Drop for TeeBody {
+    fn drop(&mut self) {
+        // nothing is left to copy after the whole body has been read.
+        let _ = io::copy(&mut self.response, &mut self.file);
+    }
+}
+
+fn replay(dir: &Path, method: &Method, url: &Url) -> Result<Response, Error> {
+    let path = recording_path(dir, method, url).with_extension("json");
+    let content = read_to_string(&path).map_err(|_| {
+        anyhow!(
+            "no response to `{} {}` is recorded in `{}`",
+            method,
+            url,
+            dir.display()
+        )
+    })?;
+    let recording = serde_json::from_str::<Recording>(&content)
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+    let body = read(path.with_extension("body"))?;
+    to_response(recording, body)
+}
+
+fn to_response(recording: Recording, body: impl Into<reqwest::Body>) -> Result<Response, Error> {
+    let mut builder = http::Response::builder()
+        .status(recording.status)
+        .url(Url::parse(&recording.final_url).context("invalid recorded URL")?);
+    for (name, value) in &recording.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let response = builder.body(body).context("invalid recorded response")?;
+    Ok(Response::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &[u8]) -> Response {
+        let response = http::Response::builder()
+            .status(200)
+            .url(Url::parse("https://example.com/archive").unwrap())
+            .header("content-type", "application/x-xz")
+            .body(body.to_vec())
+            .unwrap();
+        Response::from(response)
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse("https://example.com/archive").unwrap();
+        let body = vec![7; CHUNK_SIZE * 3 + 5];
+        let mut recorded = record(dir.path(), &Method::GET, &url, response(&body)).unwrap();
+        let mut read_body = Vec::new();
+        recorded.copy_to(&mut read_body).unwrap();
+        assert_eq!(read_body, body);
+
+        let mut replayed = replay(dir.path(), &Method::GET, &url).unwrap();
+        assert_eq!(replayed.status(), StatusCode::OK);
+        assert_eq!(replayed.headers()["content-type"], "application/x-xz");
+        let mut replayed_body = Vec::new();
+        replayed.copy_to(&mut replayed_body).unwrap();
+        assert_eq!(replayed_body, body);
+    }
+
+    #[test]
+    fn record_completes_a_dropped_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse("https://example.com/archive").unwrap();
+        let body = vec![7; CHUNK_SIZE * 3 + 5];
+        let mut recorded = record(dir.path(), &Method::GET, &url, response(&body)).unwrap();
+        recorded.read_exact(&mut [0; 10]).unwrap();
+        drop(recorded);
+        let path = recording_path(dir.path(), &Method::GET, &url).with_extension("body");
+        assert_eq!(read(path).unwrap(), body);
+    }
+
+    #[test]
+    fn replay_missing_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse("https://example.com/missing").unwrap();
+        assert!(replay(dir.path(), &Method::HEAD, &url).is_err());
+    }
+}
@@ -0,0 +1,1250 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{copy, create_dir, read_dir, rename};
+use std::io::{self, ErrorKind};
+use std::iter::once;
+use std::path::{is_separator, Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::Utc;
+use remove_dir_all::remove_dir_all;
+use reqwest::blocking::Client;
+use tempfile::tempfile_in;
+
+use crate::cache::Cache;
+use crate::channel::{detect_channel, SUPPORTED_CHANNELS};
+use crate::download::{
+    artifact_size, download_tarball, download_verified_tarball, prefetch_artifact, probe_archive,
+    Artifact,
+};
+use crate::error::Error;
+use crate::files::{audit_files, remove_files, set_mode, Audit, FileModes, FileRecorder};
+use crate::gc::create_temp_dir;
+use crate::lock::{lock_file, FileLock};
+use crate::manifest::Manifest;
+use crate::metadata::{InstallMetadata, InstalledArtifact, METADATA_FILE_NAME, SUMMARY_FILE_NAME};
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+use crate::url::component_urls;
+
+/// The directory of the toolchain which the `rust-dev` component, i.e. the
+/// LLVM build used by CI, is installed into.
+pub static RUST_DEV_DIR: &str = "ci-llvm";
+
+/// Describes a toolchain to install.
+#[derive(Debug)]
+pub struct Toolchain {
+    /// The full commit hash of the rustc build.
+    pub commit: String,
+    /// Whether this is the alt build, recorded in the metadata.
+    pub alt: bool,
+    /// The host triple of rustc and the other components.
+    pub host_target: String,
+    /// The targets to install rust-std for, usually including the host. No
+    /// rust-std is installed if it is empty.
+    pub rust_std_targets: Vec<String>,
+    /// Additional components besides rustc and rust-std.
+    pub components: Vec<String>,
+    /// The components to take from other commits than `commit`, e.g.
+    /// `rust-std` to test the standard library of one commit with the
+    /// compiler of another.
+    pub component_commits: BTreeMap<String, String>,
+    /// The URLs to download components from instead of the server, e.g. a
+    /// locally built rustc as a `file://` URL. A key like
+    /// `rust-std-wasm32-unknown-unknown` only replaces the component for that
+    /// target.
+    pub component_urls: BTreeMap<String, String>,
+    /// The SHA-256 hashes which the archives must have, e.g. as recorded in a
+    /// lock file, keyed like `rust-std-wasm32-unknown-unknown`. Archives
+    /// without a hash here are not verified.
+    pub artifact_hashes: BTreeMap<String, String>,
+    /// How the permissions of the extracted files are set, also for the
+    /// targets and components added later.
+    pub file_modes: FileModes,
+    /// The name of the installed toolchain.
+    pub name: String,
+    /// The temporary directory to extract the components into, as an absolute
+    /// path rather than one relative to the working directory of the process.
+    pub dest: PathBuf,
+}
+
+/// Describes a toolchain which has been installed.
+#[derive(Debug, Clone)]
+pub struct InstalledToolchain {
+    /// The name of the toolchain in rustup.
+    pub name: String,
+    /// The directory of the toolchain.
+    pub path: PathBuf,
+    /// The channel of the toolchain.
+    pub channel: String,
+}
+
+/// Returns the directory of `rustup_home` where toolchains are installed.
+pub fn toolchains_path(rustup_home: &Path) -> Result<PathBuf, Error> {
+    let path = rustup_home.join("toolchains");
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err(Error::RustupHomeMissing { path })
+    }
+}
+
+/// Checks that toolchains can be installed into `dir`, so an unwritable rustup
+/// home fails before anything is downloaded.
+pub fn check_writable(dir: &Path) -> Result<(), Error> {
+    match tempfile_in(dir) {
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+            ) =>
+        {
+            Err(Error::NotWritable {
+                path: dir.to_owned(),
+            })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Downloads all components of the toolchain and installs it as
+/// `toolchains_path/toolchain.name`.
+///
+/// The artifacts are downloaded from `prefix`, as returned by
+/// [`crate::builds_url`] or [`crate::templated_builds_url`]. The channel is
+/// detected automatically unless `override_channel` is given. An existing
+/// toolchain with the same name is only replaced if `force` is set, otherwise
+/// [`Error::AlreadyInstalled`] is returned; it is kept until the new toolchain
+/// is completely downloaded. If `maybe_dry_client` is `None`,
+/// nothing is downloaded or installed.
+#[allow(clippy::too_many_arguments)]
+pub fn install_single_toolchain(
+    client: &Client,
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    prefix: &str,
+    toolchains_path: &Path,
+    toolchain: &Toolchain,
+    override_channel: Option<&str>,
+    force: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<InstalledToolchain, Error> {
+    check_toolchain_name(&toolchain.name)?;
+    let _lock = match maybe_dry_client {
+        Some(_) => {
+            check_writable(toolchains_path)?;
+            Some(lock_toolchain(toolchains_path, &toolchain.name, observer)?)
+        }
+        None => None,
+    };
+    let toolchain_path = prepare_install(toolchains_path, toolchain, force)?;
+    let channels = detect_channels(client, cache, prefix, toolchain, override_channel, observer)?;
+
+    let mut installed = Vec::new();
+    for (component, target, commit) in artifacts(toolchain) {
+        let channel = channels[commit];
+        let url = match overridden_url(toolchain, component, target) {
+            Some(url) => url.to_owned(),
+            None => probe_archive(
+                client,
+                cache,
+                component_urls(prefix, commit, channel, component, target),
+                observer,
+            )?,
+        };
+        let sha256 = toolchain
+            .artifact_hashes
+            .get(&format!("{}-{}", component, target));
+        installed.extend(install_artifact(
+            maybe_dry_client,
+            cache,
+            &url,
+            sha256.map(String::as_str),
+            &toolchain.dest,
+            &Artifact {
+                commit,
+                component,
+                channel,
+                target,
+            },
+            toolchain.file_modes,
+            observer,
+        )?);
+    }
+
+    let channel = channels[&*toolchain.commit];
+    finish_install(
+        maybe_dry_client,
+        toolchain_path,
+        toolchain,
+        channel,
+        installed,
+        observer,
+    )
+}
+
+/// Updates the installed toolchain `toolchain.name` to `toolchain` in place,
+/// by downloading only the missing components and rust-std targets and
+/// removing those no longer requested, as a cheaper `force` for
+/// [`install_single_toolchain`].
+///
+/// Returns `None` if the toolchain needs to be replaced entirely instead: if it
+/// was not installed by this tool, or from another commit, host or file modes,
+/// or if nothing is missing or extra, since reinstalling is what is left to do.
+/// Toolchains with `component_urls` or `artifact_hashes` are always replaced.
+/// `maybe_dry_client` works the same as in [`install_single_toolchain`].
+#[allow(clippy::too_many_arguments)]
+pub fn update_toolchain(
+    client: &Client,
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    prefix: &str,
+    toolchains_path: &Path,
+    toolchain: &Toolchain,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<InstalledToolchain>, Error> {
+    let name = &toolchain.name;
+    check_toolchain_name(name)?;
+    let toolchain_path = toolchains_path.join(name);
+    let metadata = match InstallMetadata::read(&toolchain_path)? {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    // the removed artifacts are found from the records of the installed ones.
+    if metadata.commit != toolchain.commit
+        || metadata.alt != toolchain.alt
+        || metadata.host != toolchain.host_target
+        || metadata.component_commits != toolchain.component_commits
+        || metadata.file_modes != toolchain.file_modes
+        || metadata.artifacts.is_empty()
+        || !metadata.component_urls.is_empty()
+        || !toolchain.component_urls.is_empty()
+        || !toolchain.artifact_hashes.is_empty()
+    {
+        return Ok(None);
+    }
+    let missing = |requested: &[String], installed: &[String]| {
+        requested
+            .iter()
+            .filter(|item| !installed.contains(item))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let components = missing(&toolchain.components, &metadata.components);
+    let targets = missing(&toolchain.rust_std_targets, &metadata.targets);
+    let extra_components = missing(&metadata.components, &toolchain.components);
+    let extra_targets = missing(&metadata.targets, &toolchain.rust_std_targets);
+    if components.is_empty()
+        && targets.is_empty()
+        && extra_components.is_empty()
+        && extra_targets.is_empty()
+    {
+        return Ok(None);
+    }
+
+    // the extras are only removed after the missing artifacts are installed,
+    // so a failed download never takes away what the toolchain had.
+    add_components(
+        client,
+        maybe_dry_client,
+        cache,
+        prefix,
+        toolchains_path,
+        name,
+        &components,
+        observer,
+    )?;
+    add_targets(
+        client,
+        maybe_dry_client,
+        cache,
+        prefix,
+        toolchains_path,
+        name,
+        &targets,
+        observer,
+    )?;
+    let dry_run = maybe_dry_client.is_none();
+    remove_components(toolchains_path, name, &extra_components, dry_run, observer)?;
+    remove_targets(toolchains_path, name, &extra_targets, dry_run, observer)?;
+    Ok(Some(InstalledToolchain {
+        name: name.clone(),
+        path: toolchain_path,
+        channel: metadata.channel,
+    }))
+}
+
+/// Downloads all components of the toolchain as listed in the release
+/// `manifest` of `channel`, verifying their hashes, and installs it as
+/// `toolchains_path/toolchain.name`.
+///
+/// `toolchain.commit` should be the commit of the release, i.e.
+/// [`Manifest::commit`], and `toolchain.component_commits` and
+/// `toolchain.component_urls` must be empty, and `toolchain.artifact_hashes`
+/// is ignored.
+/// `force` and `maybe_dry_client` work the same as in
+/// [`install_single_toolchain`].
+#[allow(clippy::too_many_arguments)]
+pub fn install_from_manifest(
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    manifest: &Manifest,
+    channel: &str,
+    toolchains_path: &Path,
+    toolchain: &Toolchain,
+    force: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<InstalledToolchain, Error> {
+    if !toolchain.component_commits.is_empty() || !toolchain.component_urls.is_empty() {
+        return Err(Error::Other(anyhow!(
+            "a release cannot be mixed with components of other commits or URLs"
+        )));
+    }
+    check_toolchain_name(&toolchain.name)?;
+    let _lock = match maybe_dry_client {
+        Some(_) => {
+            check_writable(toolchains_path)?;
+            Some(lock_toolchain(toolchains_path, &toolchain.name, observer)?)
+        }
+        None => None,
+    };
+    let toolchain_path = prepare_install(toolchains_path, toolchain, force)?;
+    let mut installed = Vec::new();
+    for (component, target, _) in artifacts(toolchain) {
+        let artifact = Artifact {
+            commit: &toolchain.commit,
+            component,
+            channel,
+            target,
+        };
+        let (url, sha256) = match manifest.artifact(component, target) {
+            Some(found) => found,
+            None => {
+                return Err(Error::ArtifactNotFound {
+                    commit: toolchain.commit.clone(),
+                    component: component.to_owned(),
+                    channel: channel.to_owned(),
+                    target: target.to_owned(),
+                    url: None,
+                })
+            }
+        };
+        installed.extend(install_artifact(
+            maybe_dry_client,
+            cache,
+            url,
+            Some(sha256),
+            &toolchain.dest,
+            &artifact,
+            toolchain.file_modes,
+            observer,
+        )?);
+    }
+    finish_install(
+        maybe_dry_client,
+        toolchain_path,
+        toolchain,
+        channel,
+        installed,
+        observer,
+    )
+}
+
+/// Downloads rust-std for the `targets` missing from the installed toolchain
+/// `name` and adds it to the toolchain, instead of reinstalling everything.
+///
+/// The artifacts are downloaded from `prefix` for the commit and channel
+/// recorded in the metadata of the toolchain, so only toolchains installed by
+/// this tool are supported. Returns the targets which have been added.
+/// `maybe_dry_client` works the same as in [`install_single_toolchain`].
+#[allow(clippy::too_many_arguments)]
+pub fn add_targets(
+    client: &Client,
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    prefix: &str,
+    toolchains_path: &Path,
+    name: &str,
+    targets: &[String],
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    add_artifacts(
+        client,
+        maybe_dry_client,
+        cache,
+        prefix,
+        toolchains_path,
+        name,
+        Selection::Targets(targets),
+        observer,
+    )
+}
+
+/// Downloads the `components` missing from the installed toolchain `name` for
+/// its host and adds them to the toolchain, like [`add_targets`]. rustc and
+/// rust-std are always installed and never added.
+#[allow(clippy::too_many_arguments)]
+pub fn add_components(
+    client: &Client,
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    prefix: &str,
+    toolchains_path: &Path,
+    name: &str,
+    components: &[String],
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    add_artifacts(
+        client,
+        maybe_dry_client,
+        cache,
+        prefix,
+        toolchains_path,
+        name,
+        Selection::Components(components),
+        observer,
+    )
+}
+
+/// Removes rust-std for the `targets` from the installed toolchain `name`,
+/// deleting exactly the files it extracted when it was installed. Returns the
+/// targets which have been removed, ignoring those not installed.
+///
+/// Only toolchains installed by this tool are supported, since the files of
+/// each component are recorded in the toolchain. If `dry_run` is true, nothing
+/// is removed.
+pub fn remove_targets(
+    toolchains_path: &Path,
+    name: &str,
+    targets: &[String],
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    remove_artifacts(
+        toolchains_path,
+        name,
+        Selection::Targets(targets),
+        dry_run,
+        observer,
+    )
+}
+
+/// Removes the `components` from the installed toolchain `name`, like
+/// [`remove_targets`]. rustc and rust-std are never removed.
+pub fn remove_components(
+    toolchains_path: &Path,
+    name: &str,
+    components: &[String],
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    remove_artifacts(
+        toolchains_path,
+        name,
+        Selection::Components(components),
+        dry_run,
+        observer,
+    )
+}
+
+/// What is added to or removed from an installed toolchain.
+#[derive(Clone, Copy)]
+enum Selection<'a> {
+    Targets(&'a [String]),
+    Components(&'a [String]),
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_artifacts(
+    client: &Client,
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    prefix: &str,
+    toolchains_path: &Path,
+    name: &str,
+    selection: Selection<'_>,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    check_toolchain_name(name)?;
+    let _lock = match maybe_dry_client {
+        Some(_) => Some(lock_toolchain(toolchains_path, name, observer)?),
+        None => None,
+    };
+    let toolchain_path = toolchains_path.join(name);
+    let mut metadata = read_installed_metadata(&toolchain_path, name)?;
+    let (requested, installed) = match selection {
+        Selection::Targets(targets) => (targets, &metadata.targets),
+        Selection::Components(components) => (components, &metadata.components),
+    };
+    let mut added = Vec::<String>::new();
+    for item in requested {
+        let always_installed = matches!(selection, Selection::Components(_))
+            && (item == "rustc" || item == "rust-std");
+        if !always_installed && !installed.contains(item) && !added.contains(item) {
+            added.push(item.clone());
+        }
+    }
+    if added.is_empty() {
+        return Ok(added);
+    }
+
+    let extract_dir = create_temp_dir(&toolchains_path.with_file_name("tmp"))?;
+    let dest = extract_dir.path().join(name);
+    let mut installed = Vec::new();
+    for item in &added {
+        let (component, target) = match selection {
+            Selection::Targets(_) => ("rust-std", item.as_str()),
+            Selection::Components(_) => (item.as_str(), metadata.host.as_str()),
+        };
+        let commit = metadata
+            .component_commits
+            .get(component)
+            .unwrap_or(&metadata.commit);
+        let channel = if *commit == metadata.commit {
+            metadata.channel.as_str()
+        } else {
+            match cache.channel(commit) {
+                Some(channel) => channel,
+                None => {
+                    let channel = detect_channel(client, prefix, commit, observer)?;
+                    cache.store_channel(commit, channel);
+                    channel
+                }
+            }
+        };
+        let url = probe_archive(
+            client,
+            cache,
+            component_urls(prefix, commit, channel, component, target),
+            observer,
+        )?;
+        installed.extend(install_artifact(
+            maybe_dry_client,
+            cache,
+            &url,
+            None,
+            &dest,
+            &Artifact {
+                commit,
+                component,
+                channel,
+                target,
+            },
+            metadata.file_modes,
+            observer,
+        )?);
+    }
+
+    check_cancelled(observer)?;
+    let dry_run = maybe_dry_client.is_none();
+    if !dry_run {
+        merge_dir(&dest, &toolchain_path)?;
+        match selection {
+            Selection::Targets(_) => metadata.targets.extend(added.iter().cloned()),
+            Selection::Components(_) => metadata.components.extend(added.iter().cloned()),
+        }
+        metadata.artifacts.extend(installed);
+        metadata.write(&toolchain_path)?;
+    }
+    observer.phase(match selection {
+        Selection::Targets(_) => Phase::AddingTargets {
+            name,
+            targets: &added,
+            dry_run,
+        },
+        Selection::Components(_) => Phase::AddingComponents {
+            name,
+            components: &added,
+            dry_run,
+        },
+    });
+    Ok(added)
+}
+
+fn remove_artifacts(
+    toolchains_path: &Path,
+    name: &str,
+    selection: Selection<'_>,
+    dry_run: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    check_toolchain_name(name)?;
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(lock_toolchain(toolchains_path, name, observer)?)
+    };
+    let toolchain_path = toolchains_path.join(name);
+    let mut metadata = read_installed_metadata(&toolchain_path, name)?;
+    let (requested, installed) = match selection {
+        Selection::Targets(targets) => (targets, &metadata.targets),
+        Selection::Components(components) => (components, &metadata.components),
+    };
+    let mut removed = Vec::<String>::new();
+    for item in requested {
+        if matches!(selection, Selection::Components(_)) && (item == "rustc" || item == "rust-std")
+        {
+            return Err(Error::Other(anyhow!(
+                "`{}` cannot be removed from a toolchain",
+                item
+            )));
+        }
+        if installed.contains(item) && !removed.contains(item) {
+            removed.push(item.clone());
+        }
+    }
+    if removed.is_empty() {
+        return Ok(removed);
+    }
+
+    if !dry_run {
+        for item in &removed {
+            let (component, target) = match selection {
+                Selection::Targets(_) => ("rust-std", item.as_str()),
+                Selection::Components(_) => (item.as_str(), metadata.host.as_str()),
+            };
+            remove_files(&toolchain_path, component, target)?;
+            metadata
+                .artifacts
+                .retain(|a| a.component != component || a.target != target);
+        }
+        match selection {
+            Selection::Targets(_) => metadata.targets.retain(|t| !removed.contains(t)),
+            Selection::Components(_) => metadata.components.retain(|c| !removed.contains(c)),
+        }
+        metadata.write(&toolchain_path)?;
+    }
+    observer.phase(match selection {
+        Selection::Targets(_) => Phase::RemovingTargets {
+            name,
+            targets: &removed,
+            dry_run,
+        },
+        Selection::Components(_) => Phase::RemovingComponents {
+            name,
+            components: &removed,
+            dry_run,
+        },
+    });
+    Ok(removed)
+}
+
+/// Checks the files of the installed toolchain `name` against the hashes
+/// recorded when they were extracted, to find files which have been deleted,
+/// corrupted or tampered with since.
+///
+/// Only toolchains installed by this tool are supported.
+pub fn audit_toolchain(
+    toolchains_path: &Path,
+    name: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<Audit, Error> {
+    check_toolchain_name(name)?;
+    let toolchain_path = toolchains_path.join(name);
+    read_installed_metadata(&toolchain_path, name)?;
+    audit_files(&toolchain_path, name, observer)
+}
+
+/// Finds the installed toolchain `query` refers to: its exact name, a prefix of
+/// the commit of a toolchain installed by this tool, or a part of its name.
+///
+/// Fails with [`Error::AmbiguousToolchain`] if several toolchains match.
+pub fn find_toolchain(toolchains_path: &Path, query: &str) -> Result<String, Error> {
+    if !query.is_empty() && toolchains_path.join(query).is_dir() {
+        check_toolchain_name(query)?;
+        return Ok(query.to_owned());
+    }
+    let mut candidates = Vec::new();
+    if !query.is_empty() {
+        for entry in read_dir(toolchains_path)? {
+            let entry = entry?;
+            // skip the links made by `export_for_bisect`.
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let matches_commit = || {
+                InstallMetadata::read(&entry.path())
+                    .ok()
+                    .flatten()
+                    .is_some_and(|metadata| metadata.commit.starts_with(query))
+            };
+            if name.contains(query) || matches_commit() {
+                candidates.push(name);
+            }
+        }
+    }
+    candidates.sort();
+    match candidates.len() {
+        0 => Err(Error::ToolchainNotFound {
+            query: query.to_owned(),
+        }),
+        1 => Ok(candidates.remove(0)),
+        _ => Err(Error::AmbiguousToolchain {
+            query: query.to_owned(),
+            candidates,
+        }),
+    }
+}
+
+/// Reads the metadata of the installed toolchain `name` at `toolchain_path`,
+/// failing if it was not installed by this tool.
+fn read_installed_metadata(toolchain_path: &Path, name: &str) -> Result<InstallMetadata, Error> {
+    match InstallMetadata::read(toolchain_path)? {
+        Some(metadata) => Ok(metadata),
+        None if toolchain_path.is_dir() => Err(Error::Other(anyhow!(
+            "toolchain `{}` was not installed by this tool",
+            name
+        ))),
+        None => Err(Error::Other(anyhow!(
+            "toolchain `{}` is not installed",
+            name
+        ))),
+    }
+}
+
+/// Checks that `name` can be used as the name of a toolchain, i.e. it names a
+/// single directory in `toolchains` and is not reserved by rustup for the
+/// official toolchains or by Windows for devices.
+pub fn check_toolchain_name(name: &str) -> Result<(), Error> {
+    let reason = if name.is_empty() {
+        "the name is empty"
+    } else if name
+        .chars()
+        .any(|c| is_separator(c) || c == ':' || c.is_control())
+    {
+        "the name contains a path separator or a control character"
+    } else if name == "." || name == ".." {
+        "the name is reserved for directories"
+    } else if ["stable", "beta", "nightly"]
+        .iter()
+        .any(|channel| name == *channel || name.starts_with(&format!("{}-", channel)))
+        || is_version(name)
+    {
+        "the name is reserved for the official toolchains"
+    } else if is_windows_device(name) {
+        "the name is reserved for devices on Windows"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidToolchainName {
+        name: name.to_owned(),
+        reason,
+    })
+}
+
+/// Whether the name begins with a version like `1.70` or `1.70.0`.
+fn is_version(name: &str) -> bool {
+    let version = name.split('-').next().unwrap_or_default();
+    let parts = version.split('.').collect::<Vec<_>>();
+    (2..=3).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_windows_device(name: &str) -> bool {
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && matches!(stem.as_bytes()[3..], [b'1'..=b'9'])
+        }
+    }
+}
+
+/// Locks the toolchain `name` until it is installed, so a concurrent
+/// invocation installing the same toolchain waits and then finds it installed.
+/// The locks are kept in `$RUSTUP_HOME/tmp`, where rustup ignores them.
+fn lock_toolchain(
+    toolchains_path: &Path,
+    name: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<FileLock, Error> {
+    let path = toolchains_path
+        .with_file_name("tmp")
+        .join("rtim-locks")
+        .join(format!("{}.lock", name));
+    lock_file(&path, observer)
+}
+
+/// Returns where the toolchain is to be installed. A toolchain already
+/// installed there is only replaced by [`finish_install`] once the new one is
+/// completely downloaded, so a failed or cancelled install keeps it.
+fn prepare_install(
+    toolchains_path: &Path,
+    toolchain: &Toolchain,
+    force: bool,
+) -> Result<PathBuf, Error> {
+    let toolchain_path = toolchains_path.join(&toolchain.name);
+    if toolchain_path.is_dir() && !force {
+        return Err(Error::AlreadyInstalled {
+            name: toolchain.name.clone(),
+        });
+    }
+    Ok(toolchain_path)
+}
+
+/// Moves the downloaded components of the toolchain into `toolchain_path`,
+/// recording the `artifacts` they were extracted from.
+fn finish_install(
+    maybe_dry_client: Option<&Client>,
+    toolchain_path: PathBuf,
+    toolchain: &Toolchain,
+    channel: &str,
+    artifacts: Vec<InstalledArtifact>,
+    observer: &dyn ProgressObserver,
+) -> Result<InstalledToolchain, Error> {
+    let name = &toolchain.name;
+    check_cancelled(observer)?;
+    if maybe_dry_client.is_some() {
+        InstallMetadata {
+            commit: toolchain.commit.clone(),
+            channel: channel.to_owned(),
+            host: toolchain.host_target.clone(),
+            alt: toolchain.alt,
+            components: toolchain.components.clone(),
+            targets: toolchain.rust_std_targets.clone(),
+            installed_at: Utc::now(),
+            component_commits: toolchain.component_commits.clone(),
+            component_urls: toolchain.component_urls.clone(),
+            pull_request: None,
+            artifacts,
+            file_modes: toolchain.file_modes,
+        }
+        .write(&toolchain.dest)?;
+        for file_name in [METADATA_FILE_NAME, SUMMARY_FILE_NAME] {
+            set_mode(&toolchain.dest.join(file_name), toolchain.file_modes)?;
+        }
+        replace_dir(&toolchain.dest, &toolchain_path)?;
+        observer.phase(Phase::Installed {
+            name,
+            path: &toolchain_path,
+        });
+    } else {
+        observer.phase(Phase::WouldInstall {
+            name,
+            path: &toolchain_path,
+        });
+    }
+
+    Ok(InstalledToolchain {
+        name: name.clone(),
+        path: toolchain_path,
+        channel: channel.to_owned(),
+    })
+}
+
+/// Moves `src` to `dest`, replacing the directory at `dest`. The old directory
+/// is moved next to `src` first and moved back if `src` cannot be moved.
+fn replace_dir(src: &Path, dest: &Path) -> Result<(), Error> {
+    if !dest.is_dir() {
+        move_dir(src, dest)?;
+        return Ok(());
+    }
+    let mut backup_name = OsString::from(".replaced-");
+    backup_name.push(src.file_name().unwrap_or_default());
+    let backup = src.with_file_name(backup_name);
+    rename_retrying(dest, &backup)?;
+    if let Err(e) = move_dir(src, dest) {
+        rename_retrying(&backup, dest)?;
+        return Err(e);
+    }
+    remove_dir_all(&backup)?;
+    Ok(())
+}
+
+/// Moves the directory `src` to `dest`. If it is still locked after
+/// [`rename_retrying`], it is copied and then removed instead, like rustup
+/// does.
+fn move_dir(src: &Path, dest: &Path) -> Result<(), Error> {
+    match rename_retrying(src, dest) {
+        Err(e) if is_locked(&e) => {
+            if let Err(e) = copy_dir(src, dest) {
+                // leave no partial toolchain behind.
+                let _ = remove_dir_all(dest);
+                return Err(e.into());
+            }
+            remove_dir_all(src)?;
+            Ok(())
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Renames `src` to `dest`, retrying with backoff for about 5 seconds while
+/// the files are locked, e.g. by an antivirus scanning the files just
+/// extracted on Windows.
+fn rename_retrying(src: &Path, dest: &Path) -> io::Result<()> {
+    let mut delay = Duration::from_millis(10);
+    loop {
+        match rename(src, dest) {
+            Err(e) if is_locked(&e) && delay < Duration::from_secs(3) => {
+                sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether the error is caused by another process using the files, which is
+/// only expected on Windows.
+fn is_locked(e: &io::Error) -> bool {
+    cfg!(windows) && e.kind() == ErrorKind::PermissionDenied
+}
+
+/// Moves the content of the directory `src` into the directory `dest`,
+/// merging the directories existing in both and replacing the files.
+fn merge_dir(src: &Path, dest: &Path) -> Result<(), Error> {
+    for entry in read_dir(src)? {
+        let entry = entry?;
+        let dest = dest.join(entry.file_name());
+        if !entry.file_type()?.is_dir() {
+            rename_retrying(&entry.path(), &dest)?;
+        } else if dest.is_dir() {
+            merge_dir(&entry.path(), &dest)?;
+        } else {
+            move_dir(&entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    create_dir(dest)?;
+    for entry in read_dir(src)? {
+        let entry = entry?;
+        let dest = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Downloads all components of the toolchain into `cache` without installing
+/// it, so installing it later does not need the network. Returns the channel
+/// of the toolchain.
+///
+/// `toolchain.name` and `toolchain.dest` are not used, and the components of
+/// `toolchain.component_urls` are not prefetched.
+pub fn prefetch_toolchain(
+    client: &Client,
+    cache: &Cache,
+    prefix: &str,
+    toolchain: &Toolchain,
+    override_channel: Option<&str>,
+    observer: &dyn ProgressObserver,
+) -> Result<String, Error> {
+    let channels = detect_channels(client, cache, prefix, toolchain, override_channel, observer)?;
+    for (component, target, commit) in artifacts(toolchain) {
+        if overridden_url(toolchain, component, target).is_some() {
+            continue;
+        }
+        let channel = channels[commit];
+        let url = probe_archive(
+            client,
+            cache,
+            component_urls(prefix, commit, channel, component, target),
+            observer,
+        )?;
+        prefetch_artifact(
+            client,
+            cache,
+            &url,
+            &Artifact {
+                commit,
+                component,
+                channel,
+                target,
+            },
+            observer,
+        )?;
+    }
+    Ok(channels[&*toolchain.commit].to_owned())
+}
+
+/// The size of an archive a toolchain would be installed from, as returned by
+/// [`toolchain_sizes`].
+#[derive(Debug, Clone)]
+pub struct ArtifactSize {
+    pub component: String,
+    pub target: String,
+    pub url: String,
+    /// The size of the compressed archive in bytes.
+    pub size: u64,
+    /// Whether the archive would be downloaded, i.e. it is neither cached nor
+    /// a local file.
+    pub download: bool,
+}
+
+/// Looks up the size of every archive `toolchain` would be installed from
+/// with [`install_single_toolchain`], without downloading them.
+pub fn toolchain_sizes(
+    client: &Client,
+    cache: &Cache,
+    prefix: &str,
+    toolchain: &Toolchain,
+    override_channel: Option<&str>,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<ArtifactSize>, Error> {
+    let channels = detect_channels(client, cache, prefix, toolchain, override_channel, observer)?;
+    let mut sizes = Vec::new();
+    for (component, target, commit) in artifacts(toolchain) {
+        let channel = channels[commit];
+        let url = match overridden_url(toolchain, component, target) {
+            Some(url) => url.to_owned(),
+            None => probe_archive(
+                client,
+                cache,
+                component_urls(prefix, commit, channel, component, target),
+                observer,
+            )?,
+        };
+        let (size, download) = artifact_size(
+            client,
+            cache,
+            &url,
+            &Artifact {
+                commit,
+                component,
+                channel,
+                target,
+            },
+            observer,
+        )?;
+        sizes.push(ArtifactSize {
+            component: component.to_owned(),
+            target: target.to_owned(),
+            url,
+            size,
+            download,
+        });
+    }
+    Ok(sizes)
+}
+
+/// Detects the channel of every commit the toolchain is assembled from,
+/// unless `override_channel` is given for all of them. Channels known from
+/// previous detections are taken from `cache`.
+fn detect_channels<'a>(
+    client: &Client,
+    cache: &Cache,
+    prefix: &str,
+    toolchain: &'a Toolchain,
+    override_channel: Option<&'a str>,
+    observer: &dyn ProgressObserver,
+) -> Result<BTreeMap<&'a str, &'a str>, Error> {
+    let mut channels = BTreeMap::new();
+    let commits = once(&toolchain.commit).chain(toolchain.component_commits.values());
+    for commit in commits {
+        if !channels.contains_key(&**commit) {
+            let channel = match override_channel {
+                Some(channel) => channel,
+                None => match cache.channel(commit) {
+                    Some(channel) => channel,
+                    None => {
+                        let channel = if cache.is_offline() {
+                            cached_channel(cache, prefix, toolchain, commit, observer)?
+                        } else {
+                            detect_channel(client, prefix, commit, observer)?
+                        };
+                        cache.store_channel(commit, channel);
+                        channel
+                    }
+                },
+            };
+            channels.insert(&**commit, channel);
+        }
+    }
+    Ok(channels)
+}
+
+/// Detects the channel of `commit` from which of its artifacts are cached, for
+/// installing offline.
+fn cached_channel(
+    cache: &Cache,
+    prefix: &str,
+    toolchain: &Toolchain,
+    commit: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<&'static str, Error> {
+    observer.phase(Phase::DetectingChannel { commit });
+    SUPPORTED_CHANNELS
+        .iter()
+        .copied()
+        .find(|channel| {
+            artifacts(toolchain).any(|(component, target, c)| {
+                c == commit
+                    && component_urls(prefix, commit, channel, component, target)
+                        .iter()
+                        .any(|url| cache.get(url).is_some())
+            })
+        })
+        .ok_or_else(|| {
+            Error::Offline(format!(
+                "the channel of `{}` is unknown without its artifacts in the cache",
+                commit
+            ))
+        })
+}
+
+/// Returns the URL in `toolchain.component_urls` replacing `component` for
+/// `target`, if any.
+fn overridden_url<'a>(toolchain: &'a Toolchain, component: &str, target: &str) -> Option<&'a str> {
+    let urls = &toolchain.component_urls;
+    urls.get(&format!("{}-{}", component, target))
+        .or_else(|| urls.get(component))
+        .map(String::as_str)
+}
+
+/// Downloads and extracts the artifact into the toolchain assembled in `root`
+/// like [`download_tarball`], or like [`download_verified_tarball`] if `sha256`
+/// is given, and records the extracted files so the component can be removed
+/// again. The permissions of the files are set according to `modes`. Returns
+/// where the archive came from, unless in dry-run mode.
+#[allow(clippy::too_many_arguments)]
+fn install_artifact(
+    maybe_dry_client: Option<&Client>,
+    cache: &Cache,
+    url: &str,
+    sha256: Option<&str>,
+    root: &Path,
+    artifact: &Artifact<'_>,
+    modes: FileModes,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<InstalledArtifact>, Error> {
+    let recorder = FileRecorder::new(observer);
+    let dest = component_dest(root, artifact.component);
+    let actual = match sha256 {
+        Some(sha256) => download_verified_tarball(
+            maybe_dry_client,
+            cache,
+            url,
+            sha256,
+            &dest,
+            artifact,
+            &recorder,
+        )?,
+        None => download_tarball(maybe_dry_client, cache, url, &dest, artifact, &recorder)?,
+    };
+    let Some(sha256) = actual else {
+        return Ok(None);
+    };
+    recorder.set_modes(root, modes)?;
+    let version = recorder.version();
+    recorder.write(root, artifact.component, artifact.target, modes)?;
+    Ok(Some(InstalledArtifact {
+        component: artifact.component.to_owned(),
+        target: artifact.target.to_owned(),
+        commit: artifact.commit.to_owned(),
+        channel: artifact.channel.to_owned(),
+        url: url.to_owned(),
+        sha256,
+        version,
+    }))
+}
+
+/// Returns where the archive of `component` is extracted into. The LLVM
+/// build of `rust-dev` contains its own `bin` and `lib`, which would clash
+/// with rustc's, so it is kept apart in [`RUST_DEV_DIR`].
+fn component_dest(dest: &Path, component: &str) -> PathBuf {
+    if component == "rust-dev" {
+        dest.join(RUST_DEV_DIR)
+    } else {
+        dest.to_owned()
+    }
+}
+
+/// Lists the components of the toolchain with the target each is built for
+/// and the commit to take it from: rustc and the other components for the
+/// host, and rust-std for every target.
+fn artifacts(toolchain: &Toolchain) -> impl Iterator<Item = (&str, &str, &str)> {
+    once("rustc")
+        .chain(toolchain.components.iter().map(String::as_str))
+        .map(move |component| (component, &*toolchain.host_target))
+        .chain(
+            toolchain
+                .rust_std_targets
+                .iter()
+                .map(|target| ("rust-std", &**target)),
+        )
+        .map(move |(component, target)| {
+            let commit = toolchain
+                .component_commits
+                .get(component)
+                .unwrap_or(&toolchain.commit);
+            (component, target, &**commit)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_toolchain_names() {
+        for name in [
+            "master", "pr-12345", "a1b2c3d", "nightly2", "1.70.0.1", "com0", "lpt",
+        ] {
+            assert!(check_toolchain_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_toolchain_names() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "c:",
+            "a\nb",
+            "stable",
+            "beta-2024-01-01",
+            "nightly",
+            "1.70",
+            "1.70.0-x86_64",
+            "CON",
+            "nul.txt",
+            "Com1",
+            "lpt9",
+        ] {
+            assert!(check_toolchain_name(name).is_err(), "{:?}", name);
+        }
+        if cfg!(windows) {
+            assert!(check_toolchain_name("a\\b").is_err());
+        }
+    }
+
+    #[test]
+    fn versions() {
+        assert!(is_version("1.70"));
+        assert!(is_version("1.70.0"));
+        assert!(is_version("1.70.0-aarch64-apple-darwin"));
+        assert!(!is_version("1"));
+        assert!(!is_version("1.70.0.1"));
+        assert!(!is_version("1..0"));
+        assert!(!is_version("v1.70"));
+    }
+
+    #[test]
+    fn windows_devices() {
+        for name in ["CON", "prn", "aux.tar", "NUL", "COM1", "lpt9.x"] {
+            assert!(is_windows_device(name), "{}", name);
+        }
+        for name in ["CONSOLE", "COM", "COM0", "COM10", "LPT", "co", "nul-1"] {
+            assert!(!is_windows_device(name), "{}", name);
+        }
+    }
+}
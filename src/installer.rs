@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use reqwest::blocking::Client;
+
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::files::FileModes;
+use crate::gc::create_temp_dir;
+use crate::host::detect_host;
+use crate::install::{install_single_toolchain, toolchains_path, InstalledToolchain, Toolchain};
+use crate::progress::ProgressObserver;
+use crate::url::{builds_url, templated_builds_url};
+use crate::DEFAULT_SERVER;
+
+/// Builds and installs a toolchain, filling in the same defaults as the
+/// command line.
+///
+/// ```no_run
+/// # use rustup_toolchain_install_master::ToolchainInstaller;
+/// let toolchain = ToolchainInstaller::new("0123456789abcdef0123456789abcdef01234567")
+///     .targets(["wasm32-unknown-unknown"])
+///     .components(["rust-src"])
+///     .install()?;
+/// println!("installed {} to {}", toolchain.name, toolchain.path.display());
+/// # Ok::<_, rustup_toolchain_install_master::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ToolchainInstaller {
+    commit: String,
+    name: Option<String>,
+    host: Option<String>,
+    targets: Vec<String>,
+    no_std: bool,
+    components: Vec<String>,
+    component_commits: BTreeMap<String, String>,
+    component_urls: BTreeMap<String, String>,
+    file_modes: FileModes,
+    alt: bool,
+    server: String,
+    url_template: Option<String>,
+    channel: Option<String>,
+    force: bool,
+    dry_run: bool,
+    client: Option<Client>,
+    cache: Option<Cache>,
+    rustup_home: Option<PathBuf>,
+}
+
+impl ToolchainInstaller {
+    /// Prepares to install the toolchain built from the full 40-digit `commit`.
+    pub fn new(commit: impl Into<String>) -> Self {
+        Self {
+            commit: commit.into(),
+            name: None,
+            host: None,
+            targets: Vec::new(),
+            no_std: false,
+            components: Vec::new(),
+            component_commits: BTreeMap::new(),
+            component_urls: BTreeMap::new(),
+            file_modes: FileModes::Archive,
+            alt: false,
+            server: DEFAULT_SERVER.to_owned(),
+            url_template: None,
+            channel: None,
+            force: false,
+            dry_run: false,
+            client: None,
+            cache: None,
+            rustup_home: None,
+        }
+    }
+
+    /// Sets the name of the toolchain in rustup. Defaults to the commit hash,
+    /// with an `-alt` suffix for alt builds.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the host triple. Defaults to [`crate::detect_host`].
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Sets the additional targets to install rust-std for. The host is
+    /// always included.
+    pub fn targets<I>(mut self, targets: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Skips rust-std for every target including the host, e.g. for tools
+    /// only using `rustc_private`.
+    pub fn no_std(mut self, no_std: bool) -> Self {
+        self.no_std = no_std;
+        self
+    }
+
+    /// Sets the additional components to install besides rustc and rust-std.
+    pub fn components<I>(mut self, components: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.components = components.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Takes `component` from the build of another `commit`, e.g. `rust-std`
+    /// to assemble a toolchain from the compiler and the standard library of
+    /// different commits.
+    pub fn component_commit(
+        mut self,
+        component: impl Into<String>,
+        commit: impl Into<String>,
+    ) -> Self {
+        self.component_commits
+            .insert(component.into(), commit.into());
+        self
+    }
+
+    /// Downloads `component` from `url` instead of the server, e.g. a locally
+    /// built rustc as a `file://` URL.
+    pub fn component_url(mut self, component: impl Into<String>, url: impl Into<String>) -> Self {
+        self.component_urls.insert(component.into(), url.into());
+        self
+    }
+
+    /// Sets how the permissions of the extracted files are set. Defaults to
+    /// [`FileModes::Archive`].
+    pub fn file_modes(mut self, file_modes: FileModes) -> Self {
+        self.file_modes = file_modes;
+        self
+    }
+
+    /// Installs the alt build instead of the normal build.
+    pub fn alt(mut self, alt: bool) -> Self {
+        self.alt = alt;
+        self
+    }
+
+    /// Sets the server storing the artifacts. Defaults to [`DEFAULT_SERVER`].
+    pub fn server(mut self, server: impl Into<String>) -> Self {
+        self.server = server.into();
+        self
+    }
+
+    /// Sets the template of the artifact URLs for a server with a
+    /// nonstandard layout, as described in [`templated_builds_url`].
+    pub fn url_template(mut self, url_template: impl Into<String>) -> Self {
+        self.url_template = Some(url_template.into());
+        self
+    }
+
+    /// Sets the channel instead of detecting it from the server.
+    pub fn channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = Some(channel.into());
+        self
+    }
+
+    /// Replaces an installed toolchain with the same name.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Only reports what would be downloaded, without installing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the HTTP client, e.g. to configure a proxy.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the cache of downloaded artifacts. By default nothing is cached.
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sets the rustup home directory. Defaults to `$RUSTUP_HOME` or
+    /// `~/.rustup`.
+    pub fn rustup_home(mut self, rustup_home: impl Into<PathBuf>) -> Self {
+        self.rustup_home = Some(rustup_home.into());
+        self
+    }
+
+    /// Installs the toolchain.
+    pub fn install(&self) -> Result<InstalledToolchain, Error> {
+        self.install_with_progress(&())
+    }
+
+    /// Installs the toolchain, reporting the progress to `observer`.
+    pub fn install_with_progress(
+        &self,
+        observer: &dyn ProgressObserver,
+    ) -> Result<InstalledToolchain, Error> {
+        let rustup_home = match &self.rustup_home {
+            Some(rustup_home) => rustup_home.clone(),
+            None => home::rustup_home()?,
+        };
+        let toolchains_path = toolchains_path(&rustup_home)?;
+
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => Client::builder()
+                .user_agent("rustup-toolchain-install-master")
+                .build()?,
+        };
+        let cache = self.cache.clone().unwrap_or_else(|| {
+            Cache::new(
+                Cache::default_dir().unwrap_or_else(|| rustup_home.join("tmp").join("rtim-cache")),
+                false,
+            )
+        });
+
+        let extract_dir = create_temp_dir(&rustup_home.join("tmp"))?;
+
+        let alt_suffix = if self.alt { "-alt" } else { "" };
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{}{}", self.commit, alt_suffix),
+        };
+        let host = self.host.clone().unwrap_or_else(detect_host);
+        let mut rust_std_targets = self.targets.clone();
+        if self.no_std {
+            rust_std_targets.clear();
+        } else if !rust_std_targets.contains(&host) {
+            rust_std_targets.push(host.clone());
+        }
+
+        let prefix = match &self.url_template {
+            Some(template) => {
+                templated_builds_url(template, &self.server, &builds_url(&self.server, self.alt))
+            }
+            None => builds_url(&self.server, self.alt),
+        };
+        install_single_toolchain(
+            &client,
+            if self.dry_run { None } else { Some(&client) },
+            &cache,
+            &prefix,
+            &toolchains_path,
+            &Toolchain {
+                commit: self.commit.clone(),
+                alt: self.alt,
+                host_target: host,
+                rust_std_targets,
+                components: self.components.clone(),
+                component_commits: self.component_commits.clone(),
+                component_urls: self.component_urls.clone(),
+                artifact_hashes: BTreeMap::new(),
+                file_modes: self.file_modes,
+                dest: extract_dir.path().join(&name),
+                name,
+            },
+            self.channel.as_deref(),
+            self.force,
+            observer,
+        )
+    }
+}
@@ -0,0 +1,64 @@
+//! Installs compiler artifacts generated fresh from Rust's CI into `rustup`.
+//!
+//! This is the library behind the `rustup-toolchain-install-master` command,
+//! for tools which want to install CI toolchains without shelling out to it.
+
+#![warn(rust_2018_idioms)]
+
+mod cache;
+mod channel;
+mod download;
+mod error;
+mod export;
+mod files;
+mod gc;
+pub mod history;
+mod host;
+mod http;
+mod install;
+mod installer;
+mod lock;
+mod manifest;
+mod metadata;
+mod progress;
+pub mod resolve;
+mod url;
+mod xz;
+
+#[cfg(feature = "async")]
+pub mod nonblocking;
+
+pub use crate::cache::{Cache, CacheLimits, PendingDownload};
+pub use crate::channel::{
+    detect_channel, probe_channel, upload_time, ARTIFACT_RETENTION_DAYS, SUPPORTED_CHANNELS,
+};
+pub use crate::download::{
+    download_tarball, download_verified_tarball, prefetch_artifact, unpack_tarball, Artifact,
+};
+pub use crate::error::Error;
+pub use crate::export::{alias_toolchain, bisect_toolchain_name, export_for_bisect};
+pub use crate::files::{Audit, FileModes};
+pub use crate::gc::{
+    create_temp_dir, prune, remove_stale_temp_dirs, RetentionPolicy, STALE_TEMP_DIR_AGE,
+};
+pub use crate::host::{detect_host, runs_natively, BUILD_HOST};
+pub use crate::http::{set_http_fixtures, HttpExchange, HttpFixtures, HttpResponse};
+pub use crate::install::{
+    add_components, add_targets, audit_toolchain, check_toolchain_name, check_writable,
+    find_toolchain, install_from_manifest, install_single_toolchain, prefetch_toolchain,
+    remove_components, remove_targets, toolchain_sizes, toolchains_path, update_toolchain,
+    ArtifactSize, InstalledToolchain, Toolchain, RUST_DEV_DIR,
+};
+pub use crate::installer::ToolchainInstaller;
+pub use crate::manifest::{
+    fetch_nightly_commit, manifest_url, Manifest, Package, PackageTarget, Rename,
+    DEFAULT_DIST_SERVER,
+};
+pub use crate::metadata::{
+    InstallMetadata, InstalledArtifact, METADATA_FILE_NAME, SUMMARY_FILE_NAME,
+};
+pub use crate::progress::{Phase, ProgressObserver};
+pub use crate::url::{artifact_url, builds_url, templated_builds_url, ARCHIVE_EXTENSIONS};
+
+/// The server storing the artifacts of Rust's CI.
+pub static DEFAULT_SERVER: &str = "https://ci-artifacts.rust-lang.org";
@@ -0,0 +1,84 @@
+//! Locks files across processes, so concurrent invocations sharing a rustup
+//! home or a cache take turns instead of racing.
+
+use std::fs::{create_dir_all, File, OpenOptions, TryLockError};
+use std::io::ErrorKind;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::error::Error;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// How often a lock held by another process is retried.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// An exclusive lock on a file, released when dropped.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    _file: Option<File>,
+}
+
+/// Locks the file at `path` exclusively, creating it if needed. If another
+/// process holds the lock, reports [`Phase::WaitingForLock`] and waits until
+/// it is released or the operation is cancelled.
+///
+/// File systems which do not support locking are used without locks.
+pub(crate) fn lock_file(path: &Path, observer: &dyn ProgressObserver) -> Result<FileLock, Error> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open the lock file `{}`", path.display()))?;
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(FileLock { _file: Some(file) }),
+            Err(TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => {
+                return Ok(FileLock { _file: None })
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("failed to lock `{}`", path.display()))
+                    .into())
+            }
+            Err(TryLockError::WouldBlock) => {}
+        }
+        if !waiting {
+            observer.phase(Phase::WaitingForLock { path });
+            waiting = true;
+        }
+        check_cancelled(observer)?;
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// Locks the file at `path` exclusively like [`lock_file`], but returns `None`
+/// instead of waiting if another process holds the lock.
+pub(crate) fn try_lock_file(path: &Path) -> Result<Option<FileLock>, Error> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open the lock file `{}`", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(FileLock { _file: Some(file) })),
+        Err(TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => {
+            Ok(Some(FileLock { _file: None }))
+        }
+        Err(TryLockError::Error(e)) => Err(anyhow::Error::new(e)
+            .context(format!("failed to lock `{}`", path.display()))
+            .into()),
+        Err(TryLockError::WouldBlock) => Ok(None),
+    }
+}
@@ -0,0 +1,146 @@
+//! Records the toolchains installed with `--lock`, so `--locked` installs
+//! exactly the same archives again, e.g. on every CI machine.
+
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Error};
+use serde::{Deserialize, Serialize};
+
+use rustup_toolchain_install_master::{InstallMetadata, InstalledArtifact};
+
+static HEADER: &str =
+    "# Written by rustup-toolchain-install-master `--lock`, do not edit it by hand.\n\n";
+
+/// The content of a lock file.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Lockfile {
+    #[serde(default, rename = "toolchain")]
+    pub toolchains: Vec<LockedToolchain>,
+}
+
+/// A toolchain as resolved when the lock file was written.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockedToolchain {
+    pub commit: String,
+    pub alt: bool,
+    pub channel: String,
+    pub host: String,
+    pub components: Vec<String>,
+    pub targets: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub component_commits: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub component_urls: BTreeMap<String, String>,
+    pub artifacts: Vec<InstalledArtifact>,
+}
+
+impl Lockfile {
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let content =
+            read_to_string(path).with_context(|| format!("failed to read `{}`", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let content = format!("{}{}", HEADER, toml::to_string(self)?);
+        write(path, content).with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Returns the locked toolchain of `commit`, the alt build if `alt`.
+    pub fn find(&self, commit: &str, alt: bool) -> Option<&LockedToolchain> {
+        self.toolchains
+            .iter()
+            .find(|toolchain| toolchain.commit == commit && toolchain.alt == alt)
+    }
+
+    /// Lists the locked commits in order, once even if both builds are locked.
+    pub fn commits(&self) -> Vec<String> {
+        let mut commits = Vec::<String>::new();
+        for toolchain in &self.toolchains {
+            if !commits.contains(&toolchain.commit) {
+                commits.push(toolchain.commit.clone());
+            }
+        }
+        commits
+    }
+}
+
+impl LockedToolchain {
+    /// Locks the toolchain `name` as described by its `metadata`.
+    pub fn from_metadata(name: &str, metadata: InstallMetadata) -> Result<Self, Error> {
+        if metadata.artifacts.is_empty() {
+            bail!(
+                "the archives of toolchain `{}` were not recorded when it was installed, \
+                 reinstall it with `--force` to lock it",
+                name
+            );
+        }
+        Ok(Self {
+            commit: metadata.commit,
+            alt: metadata.alt,
+            channel: metadata.channel,
+            host: metadata.host,
+            components: metadata.components,
+            targets: metadata.targets,
+            component_commits: metadata.component_commits,
+            component_urls: metadata.component_urls,
+            artifacts: metadata.artifacts,
+        })
+    }
+
+    /// Returns the hashes of the archives, keyed like
+    /// [`rustup_toolchain_install_master::Toolchain::artifact_hashes`].
+    pub fn artifact_hashes(&self) -> BTreeMap<String, String> {
+        self.artifacts
+            .iter()
+            .map(|a| (format!("{}-{}", a.component, a.target), a.sha256.clone()))
+            .collect()
+    }
+
+    /// Checks that the lock file is not out of date for the `host`, and for the
+    /// `components` and rust-std `targets` unless they are empty, i.e. not
+    /// given on the command line.
+    pub fn check_request(
+        &self,
+        host: &str,
+        components: &[String],
+        targets: &[String],
+    ) -> Result<(), Error> {
+        let same = |requested: &[String], locked: &[String]| {
+            requested.iter().all(|item| locked.contains(item))
+                && locked.iter().all(|item| requested.contains(item))
+        };
+        if self.host != host
+            || (!components.is_empty() && !same(components, &self.components))
+            || (!targets.is_empty() && !same(targets, &self.targets))
+        {
+            bail!(
+                "the lock file of commit `{}` does not match the requested host, components or \
+                 targets, install without `--locked` to update it",
+                self.commit
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that the toolchain `name` described by `metadata` was installed
+    /// from the locked archives.
+    pub fn check_installed(&self, name: &str, metadata: &InstallMetadata) -> Result<(), Error> {
+        let installed = metadata
+            .artifacts
+            .iter()
+            .map(|a| (format!("{}-{}", a.component, a.target), a.sha256.clone()))
+            .collect::<BTreeMap<_, _>>();
+        if installed != self.artifact_hashes() {
+            bail!(
+                "toolchain `{}` is installed from other archives than locked, \
+                 reinstall it with `--force`",
+                name
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,29 +1,127 @@
 #![warn(rust_2018_idioms)]
 
-use std::env::set_current_dir;
-use std::fs::{create_dir_all, rename};
-use std::io::{stderr, stdout, Write};
+mod bench;
+mod bisect;
+mod compare;
+mod config;
+mod container;
+mod credentials;
+mod exec;
+mod github_actions;
+mod hooks;
+mod host_check;
+mod lockfile;
+mod mangen;
+mod pick;
+mod rustup;
+mod sbom;
+mod shell_env;
+mod system;
+mod targets;
+mod terminal;
+mod toolchain_file;
+mod update_check;
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::env::consts::EXE_SUFFIX;
+use std::env::{current_dir, var, var_os};
+use std::fs::{create_dir_all, remove_file, rename};
+use std::io::stdin;
 use std::iter::once;
-use std::ops::Deref;
-use std::path::{Path, PathBuf};
+use std::mem::take;
+use std::path::{Component, Path, PathBuf};
 use std::process::exit;
 use std::process::Command;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use ansi_term::Color::{Red, Yellow};
-use anyhow::{bail, ensure, Context, Error};
-use pbr::{ProgressBar, Units};
+use ansi_term::Color::{Cyan, Red, Yellow};
+use anyhow::{anyhow, bail, ensure, Context, Error};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
 use remove_dir_all::remove_dir_all;
 use reqwest::blocking::{Client, ClientBuilder};
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_LENGTH, USER_AGENT};
-use reqwest::{Proxy, StatusCode};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use reqwest::{redirect, Proxy, Url};
+use structopt::clap::{App, Arg, ArgMatches};
 use structopt::StructOpt;
-use tar::Archive;
-use tee::TeeReader;
-use tempfile::{tempdir, tempdir_in};
-use xz2::read::XzDecoder;
+use tempfile::{tempdir, TempDir};
+
+use rustup_toolchain_install_master::history::{self, Action, HistoryEntry, Outcome};
+use rustup_toolchain_install_master::resolve::{
+    contains_commit, fetch_bors_commits, fetch_commit_info, fetch_master_commit_via_git,
+    fetch_master_commit_via_http, fetch_recent_bors_commits, is_full_commit, PullRequest,
+};
+use rustup_toolchain_install_master::{
+    add_components, add_targets, alias_toolchain, audit_toolchain, builds_url,
+    check_toolchain_name, check_writable, create_temp_dir, detect_host, export_for_bisect,
+    fetch_nightly_commit, find_toolchain, install_from_manifest, install_single_toolchain,
+    manifest_url, prefetch_toolchain, probe_channel, prune, remove_components,
+    remove_stale_temp_dirs, remove_targets, set_http_fixtures, templated_builds_url,
+    toolchain_sizes, toolchains_path, update_toolchain, upload_time, ArtifactSize, Cache,
+    CacheLimits, Error as InstallError, FileModes, HttpFixtures, InstallMetadata, Manifest,
+    ProgressObserver, RetentionPolicy, Toolchain, ARTIFACT_RETENTION_DAYS, DEFAULT_DIST_SERVER,
+    DEFAULT_SERVER, METADATA_FILE_NAME, RUST_DEV_DIR, STALE_TEMP_DIR_AGE,
+};
 
-static SUPPORTED_CHANNELS: &[&str] = &["nightly", "beta", "stable"];
+use crate::bench::{bench_crate, format_results, Format as BenchFormat};
+use crate::bisect::bisect;
+use crate::compare::{compare, format_size};
+use crate::config::{
+    check_url_template, env_var, parse_size, Config, ConfigFile, Hooks, CONFIG_VAR, KEYS,
+    PROFILE_VAR,
+};
+use crate::container::{write_context, write_layer};
+use crate::credentials::{delete_github_token, load_github_token, store_github_token, TokenSource};
+use crate::exec::{check_crate, exec_with_toolchain, run_with_toolchain, CommandFailed};
+use crate::github_actions::{annotate, is_github_actions, set_output};
+use crate::hooks::{run_hook, HookContext};
+use crate::host_check::check_host;
+use crate::lockfile::{LockedToolchain, Lockfile};
+use crate::mangen::man_page;
+use crate::pick::{pick_commit, Candidate};
+use crate::rustup::{
+    format_version, is_outdated, link_toolchain, rustup_version, MIN_RUSTUP_VERSION,
+};
+use crate::sbom::write_sbom;
+use crate::shell_env::{print_env, Shell};
+use crate::system::{prepare_system_dir, print_link_instructions, share_toolchain};
+use crate::targets::{cargo_config_targets, expand_targets};
+use crate::terminal::TerminalProgress;
+use crate::toolchain_file::load_toolchain_file;
+use crate::update_check::check_for_update;
+
+static DEFAULT_NAME_TEMPLATE: &str = "{commit}{alt}";
+static TRY_BUILDS_DIR: &str = "rustc-builds-try";
+/// The limit of `--max-redirects` if none is given, as in reqwest.
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+/// The switches which can be enabled by the configs, with the hidden switch
+/// turning each off again on the command line.
+static SWITCHES: &[(&str, &str)] = &[
+    ("alt", "no-alt"),
+    ("wait-for-rate-limit", "no-wait-for-rate-limit"),
+    ("force", "no-force"),
+    ("dry-run", "no-dry-run"),
+    ("keep-going", "no-keep-going"),
+    ("no-git", "git"),
+    ("no-update-check", "update-check"),
+    ("offline", "no-offline"),
+    ("verbose", "no-verbose"),
+    ("debug-http", "no-debug-http"),
+    ("from-toolchain-file", "no-from-toolchain-file"),
+    ("detect-targets", "no-detect-targets"),
+    ("no-std", "std"),
+    ("show-commit", "no-show-commit"),
+    ("show-pr", "no-show-pr"),
+    ("rust-analyzer", "no-rust-analyzer"),
+    ("both-abis", "no-both-abis"),
+    ("wasm", "no-wasm"),
+    ("embedded", "no-embedded"),
+    ("group-writable", "no-group-writable"),
+    ("cache-downloads", "no-cache-downloads"),
+];
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(StructOpt, Debug)]
@@ -38,6 +136,21 @@ struct Args {
     #[structopt(short = "n", long = "name", help = "the name to call the toolchain")]
     name: Option<String>,
 
+    #[structopt(
+        long = "alias",
+        number_of_values = 1,
+        help = "another name to link the toolchain as, can be given repeatedly"
+    )]
+    aliases: Vec<String>,
+
+    #[structopt(
+        long = "name-template",
+        help = "the name to call the toolchains, where `{commit}` is replaced by the commit hash, \
+                `{alt}` by `-alt` for alt builds and `{host}` by the host triple \
+                [default: {commit}{alt}]"
+    )]
+    name_template: Option<String>,
+
     #[structopt(
         short = "a",
         long = "alt",
@@ -45,31 +158,232 @@ struct Args {
     )]
     alt: bool,
 
+    #[structopt(
+        long = "try",
+        conflicts_with_all = &["alt", "both", "prefix"],
+        help = "download the try build instead of normal build, i.e. `--prefix rustc-builds-try`"
+    )]
+    try_build: bool,
+
+    #[structopt(
+        long = "prefix",
+        conflicts_with_all = &["alt", "both"],
+        help = "the directory on the server which stores the builds, e.g. `rustc-builds-try` \
+                [default: rustc-builds, or rustc-builds-alt with --alt]"
+    )]
+    prefix: Option<String>,
+
+    #[structopt(
+        long = "std-commit",
+        conflicts_with = "nightly",
+        help = "take rust-std from the build of this commit, to mix it with the compiler of \
+                another commit"
+    )]
+    std_commit: Option<String>,
+
+    #[structopt(
+        long = "component-commit",
+        number_of_values = 1,
+        conflicts_with = "nightly",
+        parse(try_from_str = parse_component_commit),
+        help = "take a component from the build of another commit, given as `component=commit`"
+    )]
+    component_commits: Vec<(String, String)>,
+
+    #[structopt(
+        long = "component-url",
+        number_of_values = 1,
+        conflicts_with = "nightly",
+        parse(try_from_str = parse_component_url),
+        help = "download a component from another URL or a local file, given as `component=url`, \
+                e.g. `rustc=path/to/rustc-nightly-x86_64-unknown-linux-gnu.tar.xz`"
+    )]
+    component_urls: Vec<(String, String)>,
+
+    #[structopt(
+        long = "destdir",
+        help = "stage the toolchain in `<destdir>/<install-prefix>` for packaging, instead of \
+                installing it into the rustup home",
+        parse(from_os_str)
+    )]
+    destdir: Option<PathBuf>,
+
+    #[structopt(
+        long = "install-prefix",
+        default_value = "/usr/local",
+        help = "the directory the package stages by `--destdir` installs the toolchain into",
+        parse(from_os_str)
+    )]
+    install_prefix: PathBuf,
+
+    #[structopt(
+        long = "sbom",
+        help = "write a CycloneDX document listing the archives of the installed toolchains \
+                with their URLs, commits and SHA-256 hashes",
+        parse(from_os_str)
+    )]
+    sbom: Option<PathBuf>,
+
+    #[structopt(
+        long = "lock",
+        help = "record the installed toolchains with the hashes of their archives in this lock \
+                file, or with `--locked` install exactly the toolchains recorded in it",
+        parse(from_os_str)
+    )]
+    lock: Option<PathBuf>,
+
+    #[structopt(
+        long = "locked",
+        requires = "lock",
+        help = "install the toolchains recorded in the `--lock` file, failing if any archive has \
+                changed since"
+    )]
+    locked: bool,
+
+    #[structopt(
+        long = "dest",
+        help = "move the rust-dev component (the LLVM build of CI) here, instead of keeping it \
+                in the `ci-llvm` directory of the toolchain",
+        parse(from_os_str)
+    )]
+    rust_dev_dest: Option<PathBuf>,
+
+    #[structopt(
+        long = "nightly",
+        conflicts_with_all = &["alt", "both", "try-build", "prefix", "url-template", "channel", "watch"],
+        help = "install the nightly release of this date, e.g. `2020-01-31`, or `latest`, \
+                verifying the artifacts against the release manifest"
+    )]
+    nightly: Option<String>,
+
+    #[structopt(
+        long = "dist-server",
+        help = "the server storing the release manifests and artifacts for `--nightly` \
+                [default: https://static.rust-lang.org]"
+    )]
+    dist_server: Option<String>,
+
+    #[structopt(
+        long = "both",
+        conflicts_with = "alt",
+        conflicts_with = "name",
+        help = "download both the normal and alt builds, named `<commit>` and `<commit>-alt`"
+    )]
+    both: bool,
+
     #[structopt(
         short = "s",
         long = "server",
-        help = "the server path which stores the compilers",
-        default_value = "https://ci-artifacts.rust-lang.org"
+        help = "the server path which stores the compilers, or a `file://` URL of a local mirror \
+                [default: https://ci-artifacts.rust-lang.org]"
+    )]
+    server: Option<String>,
+
+    #[structopt(
+        long = "url-template",
+        help = "the URL of each artifact, for servers with a nonstandard layout, e.g. \
+                `{server}/{commit}/{target}/{component}.{ext}`; `{prefix}` expands to the \
+                standard URL of the builds, and `{target}` is empty for rust-src"
+    )]
+    url_template: Option<String>,
+
+    #[structopt(
+        short = "i",
+        long = "host",
+        use_delimiter = true,
+        help = "the triples of host platform, detected automatically if omitted; given repeatedly \
+                or separated by commas, a toolchain is installed for each host, named with \
+                `-<host>` appended"
     )]
-    server: String,
+    hosts: Vec<String>,
 
-    #[structopt(short = "i", long = "host", help = "the triples of host platform")]
-    host: Option<String>,
+    #[structopt(
+        long = "both-abis",
+        help = "Also install the toolchain for the other ABI of each Windows host, i.e. \
+                `-pc-windows-gnu` besides `-pc-windows-msvc` and vice versa"
+    )]
+    both_abis: bool,
 
     #[structopt(
         short = "t",
         long = "targets",
-        help = "additional target platforms to install rust-std for, besides the host platform"
+        use_delimiter = true,
+        help = "additional target platforms to install rust-std for, besides the host platform, \
+                as triples or the groups `tier1`, `windows`, `wasm`, `embedded` and `installed`, \
+                separated by commas or given repeatedly"
     )]
     targets: Vec<String>,
 
     #[structopt(
         short = "c",
         long = "component",
-        help = "additional components to install, besides rustc and rust-std"
+        alias = "components",
+        use_delimiter = true,
+        help = "additional components to install, besides rustc and rust-std, separated by \
+                commas or given repeatedly"
     )]
     components: Vec<String>,
 
+    #[structopt(
+        long = "exclude",
+        use_delimiter = true,
+        help = "components and targets to leave out, e.g. of the ones from the config file, the \
+                toolchain file or a target group, separated by commas or given repeatedly"
+    )]
+    exclude: Vec<String>,
+
+    #[structopt(
+        long = "from-toolchain-file",
+        help = "Also install the components and targets listed in the `rust-toolchain.toml` of the \
+                project in the current directory"
+    )]
+    from_toolchain_file: bool,
+
+    #[structopt(
+        long = "rust-analyzer",
+        help = "Also install rust-analyzer and rust-src, and print the editor settings pointing \
+                rust-analyzer at the toolchain, e.g. for hacking on the compiler"
+    )]
+    rust_analyzer: bool,
+
+    #[structopt(
+        long = "wasm",
+        help = "Also install rust-std for the WebAssembly targets, like `-t wasm`"
+    )]
+    wasm: bool,
+
+    #[structopt(
+        long = "embedded",
+        help = "Also install rust-std for the common bare-metal Arm Cortex-M targets, like \
+                `-t embedded`"
+    )]
+    embedded: bool,
+
+    #[structopt(
+        long = "print-env",
+        value_name = "shell",
+        possible_values = &["sh", "fish", "powershell", "cmd"],
+        help = "Print the lines setting `RUSTC`, `RUSTDOC`, `CARGO` and `RUST_SYSROOT` to each \
+                installed toolchain, for build systems running rustc without rustup \
+                [default: guessed from $SHELL]"
+    )]
+    print_env: Option<Option<Shell>>,
+
+    #[structopt(
+        long = "no-std",
+        conflicts_with_all = &["targets", "std-commit", "detect-targets"],
+        help = "Do not install rust-std for any target, not even the host, e.g. for tools using \
+                only rustc_private"
+    )]
+    no_std: bool,
+
+    #[structopt(
+        long = "detect-targets",
+        help = "Also install rust-std for the targets the project in the current directory is \
+                built for according to its `.cargo/config.toml`"
+    )]
+    detect_targets: bool,
+
     #[structopt(
         long = "channel",
         help = "specify the channel of the commits instead of detecting it automatically"
@@ -85,10 +399,24 @@ struct Args {
 
     #[structopt(
         long = "github-token",
-        help = "An authorization token to access GitHub APIs"
+        help = "An authorization token to access GitHub APIs; \
+                if omitted, `GITHUB_TOKEN`, `GH_TOKEN` or `gh auth token` is used"
     )]
     github_token: Option<String>,
 
+    #[structopt(
+        long = "github-token-from",
+        help = "where to find the GitHub token if `--github-token` is not given: \
+                `auto` or `keyring` [default: auto]"
+    )]
+    github_token_from: Option<TokenSource>,
+
+    #[structopt(
+        long = "wait-for-rate-limit",
+        help = "Wait until the GitHub API rate limit resets instead of failing"
+    )]
+    wait_for_rate_limit: bool,
+
     #[structopt(
         long = "dry-run",
         help = "Only log the URLs, without downloading the artifacts"
@@ -102,420 +430,3353 @@ struct Args {
     )]
     force: bool,
 
+    #[structopt(
+        long = "offline",
+        help = "Only install from the cache and local files, failing immediately for anything \
+                which needs the network"
+    )]
+    offline: bool,
+
+    #[structopt(
+        long = "no-git",
+        help = "Fetch the master commit from the GitHub API directly instead of trying \
+                `git ls-remote` first"
+    )]
+    no_git: bool,
+
+    #[structopt(
+        long = "no-update-check",
+        help = "Do not check crates.io once a day for a newer release of this tool"
+    )]
+    no_update_check: bool,
+
+    #[structopt(
+        long = "verbose",
+        short = "v",
+        help = "Print every redirect followed by the downloads"
+    )]
+    verbose: bool,
+
+    #[structopt(
+        long = "debug-http",
+        help = "Print every HTTP request with the status, timing and headers of its response, \
+                hiding credentials"
+    )]
+    debug_http: bool,
+
+    #[structopt(
+        long = "record",
+        help = "Save every HTTP response into this directory, to be served back by `--replay`",
+        parse(from_os_str),
+        conflicts_with = "replay"
+    )]
+    record: Option<PathBuf>,
+
+    #[structopt(
+        long = "replay",
+        help = "Answer every HTTP request with the response saved by `--record` into this \
+                directory, without the network",
+        parse(from_os_str)
+    )]
+    replay: Option<PathBuf>,
+
     #[structopt(
         long = "keep-going",
         short = "k",
         help = "Continue downloading toolchains even if some of them failed"
     )]
     keep_going: bool,
-}
 
-fn download_tar_xz(
-    client: Option<&Client>,
-    url: &str,
-    dest: &Path,
-    commit: &str,
-    component: &str,
-    channel: &str,
-    target: &str,
-) -> Result<(), Error> {
-    eprintln!("downloading <{}>...", url);
-    if let Some(client) = client {
-        let response = client.get(url).send()?;
-
-        match response.status() {
-            StatusCode::OK => {}
-            StatusCode::NOT_FOUND => bail!(
-                "missing component `{}` on toolchain `{}` on channel `{}` for target `{}`",
-                component,
-                commit,
-                channel,
-                target,
-            ),
-            status => bail!("received status {} for GET {}", status, url),
-        };
+    #[structopt(
+        long = "run",
+        help = "the shell command to run with each installed toolchain, \
+                with `RUSTUP_TOOLCHAIN` and `PATH` pointing at it"
+    )]
+    run: Option<String>,
 
-        let length = response
-            .headers()
-            .get(CONTENT_LENGTH)
-            .and_then(|h| h.to_str().ok())
-            .and_then(|h| h.parse().ok())
-            .unwrap_or(0);
-
-        let err = stderr();
-        let lock = err.lock();
-        let mut progress_bar = ProgressBar::on(lock, length);
-        progress_bar.set_units(Units::Bytes);
-        progress_bar.set_max_refresh_rate(Some(Duration::from_secs(1)));
-
-        let response = TeeReader::new(response, &mut progress_bar);
-        let response = XzDecoder::new(response);
-        for entry in Archive::new(response).entries()? {
-            let mut entry = entry?;
-            let relpath = entry.path()?;
-
-            let mut components = relpath.components();
-
-            // Reject path components that are not normal (.|..|/| etc)
-            for part in components.clone() {
-                match part {
-                    std::path::Component::Normal(_) => {}
-                    _ => bail!("bad path in tar: {}", relpath.display()),
-                }
-            }
+    #[structopt(
+        long = "check-crate",
+        help = "the crate to run `cargo check` in with each installed toolchain, \
+                reporting which toolchains pass in a summary",
+        parse(from_os_str)
+    )]
+    check_crate: Option<PathBuf>,
 
-            // Throw away the first two path components: our root was supplied
-            components.next();
-            components.next();
+    #[structopt(
+        long = "check-command",
+        help = "the cargo subcommand to run with `--check-crate`",
+        default_value = "check"
+    )]
+    check_command: String,
 
-            let full_path = dest.join(&components.as_path());
-            if full_path == dest {
-                // The tmp dir code makes the root dir for us.
-                continue;
-            }
+    #[structopt(
+        long = "ephemeral",
+        help = "Remove each toolchain after running the `--run` command or checking the crate with it"
+    )]
+    ephemeral: bool,
 
-            // Bail out if we get hard links, device nodes or any other unusual content
-            // - it is most likely an attack, as rusts cross-platform nature precludes
-            // such artifacts
-            let kind = entry.header().entry_type();
+    #[structopt(
+        long = "show-commit",
+        help = "Print the author, date and subject of each commit before installing it"
+    )]
+    show_commit: bool,
 
-            match kind {
-                tar::EntryType::Directory => {
-                    create_dir_all(full_path)?;
-                }
-                tar::EntryType::Regular => {
-                    entry.unpack(full_path)?;
-                }
-                _ => bail!("unsupported tar entry: {:?}", kind),
-            }
-        }
+    #[structopt(
+        long = "show-pr",
+        help = "Look up the pull request merged by each installed commit, printing it and recording \
+                it in the toolchain"
+    )]
+    show_pr: bool,
 
-        progress_bar.finish();
-        eprintln!();
-    }
+    #[structopt(
+        long = "expires",
+        conflicts_with = "watch",
+        help = "Only print when the artifacts of the commits were uploaded and when they are expected \
+                to be deleted, instead of installing them"
+    )]
+    expires: bool,
 
-    Ok(())
-}
+    #[structopt(
+        long = "sizes",
+        conflicts_with_all = &["watch", "expires"],
+        help = "Only print the download size of each component and target of the toolchains, \
+                instead of installing them"
+    )]
+    sizes: bool,
 
-#[derive(Debug)]
-struct Toolchain<'a> {
-    commit: &'a str,
-    host_target: &'a str,
-    rust_std_targets: &'a [&'a str],
-    components: &'a [&'a str],
-    dest: PathBuf,
-}
+    #[structopt(
+        long = "watch",
+        conflicts_with = "name",
+        help = "Keep polling the master branch and install every new commit, until interrupted"
+    )]
+    watch: bool,
 
-fn install_single_toolchain(
-    client: &Client,
-    maybe_dry_client: Option<&Client>,
-    prefix: &str,
-    toolchains_path: &Path,
-    toolchain: &Toolchain<'_>,
-    override_channel: Option<&str>,
-    force: bool,
-) -> Result<(), Error> {
-    let toolchain_path = toolchains_path.join(&toolchain.dest);
-    if toolchain_path.is_dir() {
-        if force {
-            if maybe_dry_client.is_some() {
-                remove_dir_all(&toolchain_path)?;
-            }
-        } else {
-            eprintln!(
-                "toolchain `{}` is already installed",
-                toolchain.dest.display()
-            );
-            return Ok(());
-        }
-    }
+    #[structopt(
+        long = "interval",
+        help = "How long to wait between polls of the master branch with `--watch`",
+        default_value = "30m",
+        parse(try_from_str = humantime::parse_duration)
+    )]
+    interval: Duration,
 
-    let channel = if let Some(channel) = override_channel {
-        channel
-    } else {
-        get_channel(client, prefix, toolchain.commit)?
-    };
+    #[structopt(
+        long = "config",
+        help = "Read the default options from this file instead of \
+                ~/.config/rustup-toolchain-install-master/config.toml",
+        parse(from_os_str)
+    )]
+    config: Option<PathBuf>,
 
-    // download every component except rust-std.
-    for component in once(&"rustc").chain(toolchain.components) {
-        let component_filename = if *component == "rust-src" {
-            // rust-src is the only target-independent component
-            format!("{}-{}", component, channel)
-        } else {
-            format!("{}-{}-{}", component, channel, toolchain.host_target)
-        };
-        download_tar_xz(
-            maybe_dry_client,
-            &format!(
-                "{}/{}/{}.tar.xz",
-                prefix, toolchain.commit, &component_filename
-            ),
-            &toolchain.dest,
-            toolchain.commit,
-            component,
-            channel,
-            toolchain.host_target,
-        )?;
-    }
+    #[structopt(
+        long = "profile-name",
+        help = "Apply the options of this profile from the config file"
+    )]
+    profile_name: Option<String>,
 
-    // download rust-std for every target.
-    for target in toolchain.rust_std_targets {
-        let rust_std_filename = format!("rust-std-{}-{}", channel, target);
-        download_tar_xz(
-            maybe_dry_client,
-            &format!(
-                "{}/{}/{}.tar.xz",
-                prefix, toolchain.commit, rust_std_filename
-            ),
-            &toolchain.dest,
-            toolchain.commit,
-            "rust-std",
-            channel,
-            target,
-        )?;
-    }
+    #[structopt(
+        long = "no-config",
+        help = "Ignore the config files and the `RTIM_*` environment variables; a single switch \
+                enabled there is turned off by `--no-<switch>` instead, \
+                e.g. `--no-force`, or `--git`, `--update-check` and `--std` for the `--no-*` \
+                switches",
+        conflicts_with_all = &["config", "profile-name"]
+    )]
+    no_config: bool,
 
-    // install
-    if maybe_dry_client.is_some() {
-        rename(&toolchain.dest, toolchain_path)?;
-        eprintln!(
-            "toolchain `{}` is successfully installed!",
-            toolchain.dest.display()
-        );
-    } else {
-        eprintln!(
-            "toolchain `{}` will be installed to `{}` on real run",
-            toolchain.dest.display(),
-            toolchain_path.display()
-        );
-    }
+    #[structopt(
+        long = "retention",
+        help = "After installing, remove toolchains installed by this tool longer ago than this \
+                duration, e.g. `14d`"
+    )]
+    retention: Option<String>,
 
-    Ok(())
-}
+    #[structopt(
+        long = "max-toolchains",
+        help = "After installing, remove the oldest toolchains installed by this tool \
+                to keep at most this number of them"
+    )]
+    max_toolchains: Option<usize>,
 
-fn fetch_master_commit(client: &Client, github_token: Option<&str>) -> Result<String, Error> {
-    eprintln!("fetching master commit hash... ");
-    fetch_master_commit_via_git()
-        .context("unable to fetch master commit via git, falling back to HTTP")
-        .or_else(|err| {
-            report_warn(&err);
-            fetch_master_commit_via_http(client, github_token)
-        })
-}
+    #[structopt(
+        long = "jobs",
+        short = "j",
+        help = "the number of toolchains to install in parallel [default: 1]"
+    )]
+    jobs: Option<usize>,
 
-fn fetch_master_commit_via_git() -> Result<String, Error> {
-    let mut output = Command::new("git")
-        .args(&[
-            "ls-remote",
-            "https://github.com/rust-lang/rust.git",
-            "master",
-        ])
-        .output()?;
-    ensure!(output.status.success(), "git ls-remote exited with error");
-    ensure!(
-        output
-            .stdout
-            .get(..40)
-            .map_or(false, |h| h.iter().all(u8::is_ascii_hexdigit)),
-        "git ls-remote does not return a commit"
-    );
+    #[structopt(
+        long = "max-redirects",
+        help = "the number of redirects to follow for a single request [default: 10]"
+    )]
+    max_redirects: Option<usize>,
 
-    output.stdout.truncate(40);
-    Ok(unsafe { String::from_utf8_unchecked(output.stdout) })
-}
+    #[structopt(
+        long = "max-download-size",
+        help = "fail before downloading a toolchain if its archives not cached yet are larger than \
+                this, e.g. `500MB` or `2GiB`"
+    )]
+    max_download_size: Option<String>,
 
-fn fetch_master_commit_via_http(
-    client: &Client,
-    github_token: Option<&str>,
-) -> Result<String, Error> {
-    static URL: &str = "https://api.github.com/repos/rust-lang/rust/commits/master";
-    static MEDIA_TYPE: &str = "application/vnd.github.VERSION.sha";
-    let mut req = client.get(URL).header(ACCEPT, MEDIA_TYPE);
-    if let Some(token) = github_token {
-        req = req.header(AUTHORIZATION, format!("token {}", token));
-    }
-    let response = req.send()?;
-    match response.status() {
-        StatusCode::OK => {}
-        status @ StatusCode::FORBIDDEN => {
-            let rate_limit = response
-                .headers()
-                .get("X-RateLimit-Remaining")
-                .and_then(|r| r.to_str().ok())
-                .and_then(|r| r.parse::<u32>().ok())
-                .unwrap_or(0);
-            if rate_limit == 0 {
-                bail!("GitHub API rate limit exceeded");
-            } else {
-                bail!("status: {} with rate limit: {}", status, rate_limit);
-            }
-        }
-        status => bail!("received status {} for URL {}", status, URL),
-    }
-    let master_commit = response.text()?;
-    if master_commit.len() == 40
-        && master_commit
-            .chars()
-            .all(|c| matches!(c, '0'..='9' | 'a'..='f'))
-    {
-        let out = stdout();
-        let mut lock = out.lock();
-        lock.write_all(master_commit.as_bytes())?;
-        lock.flush()?;
-        eprintln!();
-        Ok(master_commit)
-    } else {
-        bail!("unable to parse `{}` as a commit", master_commit)
-    }
-}
+    #[structopt(
+        long = "pool-idle-timeout",
+        help = "How long an idle connection is kept open for reuse by later requests, e.g. `5m` \
+                [default: 90s]"
+    )]
+    pool_idle_timeout: Option<String>,
 
-fn get_channel(client: &Client, prefix: &str, commit: &str) -> Result<&'static str, Error> {
-    eprintln!("detecting the channel of the `{}` toolchain...", commit);
+    #[structopt(
+        long = "pool-max-idle",
+        help = "the number of idle connections kept open for reuse per host [default: unlimited]"
+    )]
+    pool_max_idle: Option<usize>,
 
-    for channel in SUPPORTED_CHANNELS {
-        let url = format!("{}/{}/rust-src-{}.tar.xz", prefix, commit, channel);
-        let resp = client.head(&url).send()?;
+    #[structopt(
+        long = "cache-dir",
+        help = "the directory to cache downloads and metadata in \
+                [default: the platform cache directory]",
+        parse(from_os_str)
+    )]
+    cache_dir: Option<PathBuf>,
 
-        match resp.status() {
-            StatusCode::OK => return Ok(channel),
-            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {}
-            status => bail!("unexpected status code {} for HEAD {}", status, url),
-        }
-    }
+    #[structopt(
+        long = "rustup-home",
+        help = "the rustup home to install the toolchains into [default: $RUSTUP_HOME or ~/.rustup]",
+        parse(from_os_str)
+    )]
+    rustup_home: Option<PathBuf>,
 
-    bail!("toolchain `{}` doesn't exist in any channel", commit);
-}
+    #[structopt(
+        long = "chmod",
+        possible_values = &["archive", "normalize", "normalize-group"],
+        help = "how to set the permissions of the extracted files: `archive` keeps the modes of \
+                the archives, `normalize` sets 755 for directories and executables and 644 for \
+                other files, and `normalize-group` 775 and 664 [default: archive]"
+    )]
+    chmod: Option<FileModes>,
 
-fn run() -> Result<(), Error> {
-    let mut args = Args::from_args();
+    #[structopt(
+        long = "system",
+        conflicts_with_all = &["rustup-home", "destdir"],
+        help = "install the toolchains into this directory shared by every user, e.g. \
+                `/opt/rust-ci`, instead of a rustup home, keeping the cache there too",
+        parse(from_os_str)
+    )]
+    system: Option<PathBuf>,
 
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static("rustup-toolchain-install-master"),
-    );
+    #[structopt(
+        long = "group-writable",
+        help = "let the group of the `--system` directory install and remove toolchains too"
+    )]
+    group_writable: bool,
 
-    let mut client_builder = ClientBuilder::new().default_headers(headers);
-    if let Some(proxy) = args.proxy {
-        client_builder = client_builder.proxy(Proxy::all(&proxy)?);
-    }
-    let client = client_builder.build()?;
+    #[structopt(
+        long = "cache-downloads",
+        help = "Keep the downloaded artifacts in the cache directory for later installs"
+    )]
+    cache_downloads: bool,
 
-    let rustup_home = home::rustup_home().expect("$RUSTUP_HOME is undefined?");
-    let toolchains_path = rustup_home.join("toolchains");
-    if !toolchains_path.is_dir() {
-        bail!(
-            "`{}` is not a directory. please reinstall rustup.",
-            toolchains_path.display()
-        );
-    }
+    #[structopt(
+        long = "cache-max-size",
+        help = "After installing, remove the least recently used downloads from the cache until it \
+                is no larger than this, e.g. `5GiB`"
+    )]
+    cache_max_size: Option<String>,
 
-    if args.commits.len() > 1 && args.name.is_some() {
-        return Err(Error::msg(
-            "name argument can only be provided with a single commit",
-        ));
-    }
+    #[structopt(
+        long = "cache-max-age",
+        help = "After installing, remove the downloads not used for longer than this duration from \
+                the cache, e.g. `30d`"
+    )]
+    cache_max_age: Option<String>,
 
-    let host = args.host.as_deref().unwrap_or(env!("HOST"));
+    /// The hooks from the config file, which cannot be given on the command
+    /// line.
+    #[structopt(skip)]
+    hooks: Hooks,
 
-    let components = args.components.iter().map(Deref::deref).collect::<Vec<_>>();
+    /// The switches turned off on the command line, which the configs cannot
+    /// turn on.
+    #[structopt(skip)]
+    negated: Vec<&'static str>,
 
-    let rust_std_targets = args
-        .targets
-        .iter()
-        .map(Deref::deref)
-        .chain(once(host))
-        .collect::<Vec<_>>();
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
 
-    let toolchains_dir = {
-        let path = rustup_home.join("tmp");
-        if !path.exists() {
-            create_dir_all(&path)?;
-        }
-        if path.is_dir() {
-            tempdir_in(&path)
-        } else {
-            tempdir()
-        }
-    }?;
-    set_current_dir(toolchains_dir.path())?;
+#[derive(StructOpt, Debug)]
+enum Subcommand {
+    #[structopt(name = "config", about = "Read or edit the config file")]
+    Config(ConfigCommand),
 
-    let prefix = format!(
-        "{}/rustc-builds{}",
-        args.server,
-        if args.alt { "-alt" } else { "" }
-    );
+    #[structopt(name = "keyring", about = "Manage the GitHub token in the OS keyring")]
+    Keyring(KeyringCommand),
 
-    if args.commits.is_empty() {
-        args.commits
-            .push(fetch_master_commit(&client, args.github_token.as_deref())?);
-    }
+    #[structopt(name = "cache", about = "Manage the downloads kept in the cache")]
+    Cache(CacheCommand),
 
-    let dry_run_client = if args.dry_run { None } else { Some(&client) };
-    let mut failed = false;
-    for commit in args.commits {
-        let dest = if let Some(name) = args.name.as_deref() {
-            PathBuf::from(name)
-        } else if args.alt {
-            PathBuf::from(format!("{}-alt", commit))
-        } else {
-            PathBuf::from(&commit)
-        };
+    #[structopt(
+        name = "add-target",
+        about = "Add rust-std for more targets to a toolchain installed by this tool"
+    )]
+    AddTarget {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+        #[structopt(
+            required = true,
+            use_delimiter = true,
+            help = "the targets to add, as triples or the groups accepted by `--targets`"
+        )]
+        targets: Vec<String>,
+    },
+
+    #[structopt(
+        name = "add-component",
+        about = "Add more components to a toolchain installed by this tool"
+    )]
+    AddComponent {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+        #[structopt(required = true, use_delimiter = true, help = "the components to add")]
+        components: Vec<String>,
+    },
+
+    #[structopt(
+        name = "remove-target",
+        about = "Remove rust-std for some targets from a toolchain installed by this tool"
+    )]
+    RemoveTarget {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+        #[structopt(
+            required = true,
+            use_delimiter = true,
+            help = "the targets to remove rust-std for"
+        )]
+        targets: Vec<String>,
+    },
+
+    #[structopt(
+        name = "remove-component",
+        about = "Remove some components from a toolchain installed by this tool"
+    )]
+    RemoveComponent {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+        #[structopt(
+            required = true,
+            use_delimiter = true,
+            help = "the components to remove"
+        )]
+        components: Vec<String>,
+    },
+
+    #[structopt(
+        name = "audit",
+        about = "Check the files of a toolchain installed by this tool for deletions and changes"
+    )]
+    Audit {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+    },
+
+    #[structopt(
+        name = "containerize",
+        about = "Pack an installed toolchain into a Dockerfile context or an OCI layer, placed under \
+                 `/usr/local`"
+    )]
+    Containerize {
+        #[structopt(
+            help = "the installed toolchain, by its name, a part of it, or a prefix of its commit"
+        )]
+        toolchain: String,
+        #[structopt(
+            long = "output",
+            short = "o",
+            parse(from_os_str),
+            help = "the directory to write the Dockerfile context into, or the file to write the \
+                    layer into with `--layer`"
+        )]
+        output: PathBuf,
+        #[structopt(
+            long = "layer",
+            help = "Write an OCI layer tar instead of a Dockerfile context"
+        )]
+        layer: bool,
+        #[structopt(
+            long = "base-image",
+            default_value = "buildpack-deps:bookworm",
+            help = "the image to build the Dockerfile from, which needs a C toolchain for linking"
+        )]
+        base_image: String,
+    },
+
+    #[structopt(
+        name = "gc",
+        about = "Remove toolchains installed by this tool according to the retention policy"
+    )]
+    Gc {
+        #[structopt(long = "dry-run", help = "Only log the toolchains to be removed")]
+        dry_run: bool,
+    },
+
+    #[structopt(
+        name = "history",
+        about = "Show the journal of the toolchains installed and removed by this tool"
+    )]
+    History {
+        #[structopt(
+            long = "commit",
+            help = "only show the toolchains whose commit starts with this"
+        )]
+        commit: Option<String>,
+        #[structopt(
+            long = "since",
+            help = "only show the entries not older than this duration, e.g. `2d`, or this date, \
+                    e.g. `2024-05-01`"
+        )]
+        since: Option<String>,
+        #[structopt(long = "limit", help = "only show this number of the latest entries")]
+        limit: Option<usize>,
+        #[structopt(long = "json", help = "print the entries as a JSON array")]
+        json: bool,
+    },
+
+    #[structopt(
+        name = "export-for-bisect",
+        about = "Link the toolchains installed by this tool under the names used by cargo-bisect-rustc"
+    )]
+    ExportForBisect {
+        #[structopt(long = "dry-run", help = "Only log the toolchains to be linked")]
+        dry_run: bool,
+    },
+
+    #[structopt(
+        name = "bisect",
+        about = "Find the first bors merge commit for which a test command fails"
+    )]
+    Bisect {
+        #[structopt(long = "start", help = "the full hash of a good commit")]
+        start: String,
+        #[structopt(long = "end", help = "the full hash of a bad commit")]
+        end: String,
+        #[structopt(
+            last = true,
+            required = true,
+            help = "the command to run with `RUSTUP_TOOLCHAIN` set to each installed toolchain, \
+                    which should fail on bad commits"
+        )]
+        test_command: Vec<String>,
+    },
+
+    #[structopt(
+        name = "compare",
+        about = "Install the toolchains of two commits if needed and print how they differ"
+    )]
+    Compare {
+        #[structopt(help = "the full hash of the first commit, or an installed toolchain")]
+        a: String,
+        #[structopt(help = "the full hash of the second commit, or an installed toolchain")]
+        b: String,
+    },
+
+    #[structopt(
+        name = "run",
+        about = "Install the toolchain of a commit unless it is installed and run a command with it"
+    )]
+    Run {
+        #[structopt(
+            help = "the full hash of the commit, `master` for the latest master commit, or an \
+                    installed toolchain"
+        )]
+        commit: String,
+        #[structopt(
+            last = true,
+            required = true,
+            help = "the program and arguments to run with `RUSTUP_TOOLCHAIN` and `PATH` pointing \
+                    at the toolchain"
+        )]
+        command: Vec<String>,
+    },
+
+    #[structopt(
+        name = "bench",
+        about = "Measure the time of building a crate with the toolchain of each commit"
+    )]
+    Bench {
+        #[structopt(long = "crate", help = "the path to the crate to build")]
+        crate_path: PathBuf,
+        #[structopt(
+            long = "time-passes",
+            help = "also record the time of each compiler pass of the crate with `-Ztime-passes`"
+        )]
+        time_passes: bool,
+        #[structopt(
+            long = "format",
+            default_value = "csv",
+            help = "print the results as `csv` or `json`"
+        )]
+        format: BenchFormat,
+        #[structopt(
+            required = true,
+            help = "full commit hashes of the toolchains to build with"
+        )]
+        commits: Vec<String>,
+    },
+
+    #[structopt(
+        name = "mangen",
+        about = "Print the man page of this tool in roff format, e.g. for packaging"
+    )]
+    Mangen,
+
+    #[structopt(
+        name = "which-nightly",
+        about = "Print the first nightly release which contains a commit"
+    )]
+    WhichNightly {
+        #[structopt(help = "the commit to look for")]
+        commit: String,
+    },
+
+    #[structopt(
+        name = "list-available",
+        about = "List the latest bors merge commits and whether their artifacts still exist"
+    )]
+    ListAvailable {
+        #[structopt(
+            long = "count",
+            default_value = "10",
+            help = "the number of commits to list"
+        )]
+        count: usize,
+    },
+
+    #[structopt(
+        name = "prefetch",
+        about = "Download the artifacts of the latest bors merge commits into the cache without \
+                 installing them"
+    )]
+    Prefetch {
+        #[structopt(
+            long = "count",
+            default_value = "10",
+            help = "the number of commits to download"
+        )]
+        count: usize,
+    },
+
+    #[structopt(
+        name = "pick",
+        about = "List the latest bors merge commits and install the one picked interactively"
+    )]
+    Pick {
+        #[structopt(
+            long = "count",
+            default_value = "20",
+            help = "the number of commits to pick from"
+        )]
+        count: usize,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum KeyringCommand {
+    #[structopt(
+        name = "store",
+        about = "Read a GitHub token from stdin and store it in the keyring"
+    )]
+    Store,
+
+    #[structopt(name = "delete", about = "Delete the GitHub token from the keyring")]
+    Delete,
+}
+
+#[derive(StructOpt, Debug)]
+enum CacheCommand {
+    #[structopt(
+        name = "prune",
+        about = "Remove the downloads exceeding `cache-max-size` or `cache-max-age` from the cache"
+    )]
+    Prune {
+        #[structopt(long = "dry-run", help = "Only log the downloads to be removed")]
+        dry_run: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum ConfigCommand {
+    #[structopt(name = "list", about = "Print all values in the config file")]
+    List,
+
+    #[structopt(name = "get", about = "Print the value of a key in the config file")]
+    Get {
+        #[structopt(help = "the key to read, e.g. `server` or `profile.miri.components`")]
+        key: String,
+    },
+
+    #[structopt(name = "set", about = "Store a value in the config file")]
+    Set {
+        #[structopt(help = "the key to write, e.g. `server` or `profile.miri.components`")]
+        key: String,
+        #[structopt(help = "the new value; lists are separated by commas")]
+        value: String,
+    },
+
+    #[structopt(name = "unset", about = "Remove a key from the config file")]
+    Unset {
+        #[structopt(help = "the key to remove")]
+        key: String,
+    },
+}
+
+/// Builds the parser of the command line, with the hidden counterparts of
+/// [`SWITCHES`].
+fn app() -> App<'static, 'static> {
+    let negations = SWITCHES
+        .iter()
+        .map(|&(name, negation)| {
+            Arg::with_name(negation)
+                .long(negation)
+                .hidden(true)
+                .overrides_with(name)
+        })
+        .collect::<Vec<_>>();
+    static ENV_HELP: OnceLock<String> = OnceLock::new();
+    Args::clap()
+        .args(&negations)
+        .after_help(ENV_HELP.get_or_init(env_help).as_str())
+}
+
+/// Lists the environment variables of the options, which are read by
+/// [`Config::from_env`] except the two choosing the config files.
+fn env_help() -> String {
+    let mut vars = vec![
+        (CONFIG_VAR.to_owned(), "config"),
+        (PROFILE_VAR.to_owned(), "profile-name"),
+    ];
+    vars.extend(KEYS.iter().map(|(key, _)| (env_var(key), *key)));
+    vars.sort();
+    let mut help = "ENVIRONMENT:\n".to_owned();
+    for (var, key) in vars {
+        help += &format!("    {:<28}--{}\n", var, key);
+    }
+    help
+}
+
+impl Args {
+    fn from_matches(matches: &ArgMatches<'_>) -> Self {
+        let mut args = Self::from_clap(matches);
+        args.negated = SWITCHES
+            .iter()
+            .filter(|(_, negation)| matches.is_present(negation))
+            .map(|(name, _)| *name)
+            .collect();
+        args
+    }
+
+    /// Fills in every option not given on the command line from the configs,
+    /// ordered from the highest to the lowest precedence.
+    fn merge_configs(&mut self, configs: Vec<Config>) {
+        fn merge<T>(arg: &mut Option<T>, value: Option<T>) {
+            if arg.is_none() {
+                *arg = value;
+            }
+        }
+        fn merge_list(arg: &mut Vec<String>, value: Vec<String>) {
+            if arg.is_empty() {
+                *arg = value;
+            }
+        }
+
+        // a switch given on the command line either way is decided there,
+        // otherwise by the first config setting it.
+        let negated = take(&mut self.negated);
+        let flag = |arg: &mut bool, name: &str, get: fn(&Config) -> Option<bool>| {
+            if !*arg && !negated.contains(&name) {
+                *arg = configs.iter().find_map(get) == Some(true);
+            }
+        };
+        flag(&mut self.alt, "alt", |c| c.alt);
+        flag(&mut self.wait_for_rate_limit, "wait-for-rate-limit", |c| {
+            c.wait_for_rate_limit
+        });
+        flag(&mut self.force, "force", |c| c.force);
+        flag(&mut self.dry_run, "dry-run", |c| c.dry_run);
+        flag(&mut self.keep_going, "keep-going", |c| c.keep_going);
+        flag(&mut self.no_git, "no-git", |c| c.no_git);
+        flag(&mut self.no_update_check, "no-update-check", |c| {
+            c.no_update_check
+        });
+        flag(&mut self.offline, "offline", |c| c.offline);
+        flag(&mut self.verbose, "verbose", |c| c.verbose);
+        flag(&mut self.debug_http, "debug-http", |c| c.debug_http);
+        flag(&mut self.from_toolchain_file, "from-toolchain-file", |c| {
+            c.from_toolchain_file
+        });
+        flag(&mut self.detect_targets, "detect-targets", |c| {
+            c.detect_targets
+        });
+        flag(&mut self.no_std, "no-std", |c| c.no_std);
+        flag(&mut self.show_commit, "show-commit", |c| c.show_commit);
+        flag(&mut self.show_pr, "show-pr", |c| c.show_pr);
+        flag(&mut self.rust_analyzer, "rust-analyzer", |c| {
+            c.rust_analyzer
+        });
+        flag(&mut self.both_abis, "both-abis", |c| c.both_abis);
+        flag(&mut self.wasm, "wasm", |c| c.wasm);
+        flag(&mut self.embedded, "embedded", |c| c.embedded);
+        flag(&mut self.group_writable, "group-writable", |c| {
+            c.group_writable
+        });
+        flag(&mut self.cache_downloads, "cache-downloads", |c| {
+            c.cache_downloads
+        });
+
+        for config in configs {
+            merge(&mut self.server, config.server);
+            merge(&mut self.url_template, config.url_template);
+            merge(&mut self.prefix, config.prefix);
+            merge(&mut self.dist_server, config.dist_server);
+            merge_list(&mut self.hosts, config.host);
+            merge(&mut self.name_template, config.name_template);
+            merge_list(&mut self.targets, config.targets);
+            merge_list(&mut self.components, config.components);
+            merge_list(&mut self.exclude, config.exclude);
+            merge(&mut self.channel, config.channel);
+            merge(&mut self.proxy, config.proxy);
+            merge(&mut self.github_token, config.github_token);
+            merge(&mut self.github_token_from, config.github_token_from);
+            merge(&mut self.retention, config.retention);
+            merge(&mut self.cache_dir, config.cache_dir);
+            merge(&mut self.rustup_home, config.rustup_home);
+            merge(&mut self.system, config.system);
+            merge(&mut self.chmod, config.chmod);
+            merge(&mut self.cache_max_size, config.cache_max_size);
+            merge(&mut self.cache_max_age, config.cache_max_age);
+            merge(&mut self.hooks.pre_install, config.hooks.pre_install);
+            merge(&mut self.hooks.post_install, config.hooks.post_install);
+            merge(&mut self.hooks.post_uninstall, config.hooks.post_uninstall);
+            merge(&mut self.max_toolchains, config.max_toolchains);
+            merge(&mut self.jobs, config.jobs);
+            merge(&mut self.max_redirects, config.max_redirects);
+            merge(&mut self.max_download_size, config.max_download_size);
+            merge(&mut self.pool_idle_timeout, config.pool_idle_timeout);
+            merge(&mut self.pool_max_idle, config.pool_max_idle);
+        }
+    }
+
+    fn retention_policy(&self) -> Result<RetentionPolicy, Error> {
+        let max_age = match &self.retention {
+            Some(retention) => Some(
+                humantime::parse_duration(retention)
+                    .with_context(|| format!("invalid retention duration `{}`", retention))?,
+            ),
+            None => None,
+        };
+        Ok(RetentionPolicy {
+            max_age,
+            max_toolchains: self.max_toolchains,
+        })
+    }
+
+    fn cache_limits(&self) -> Result<CacheLimits, Error> {
+        let max_size = match &self.cache_max_size {
+            Some(size) => Some(parse_size(size)?),
+            None => None,
+        };
+        let max_age = match &self.cache_max_age {
+            Some(age) => Some(
+                humantime::parse_duration(age)
+                    .with_context(|| format!("invalid cache age `{}`", age))?,
+            ),
+            None => None,
+        };
+        Ok(CacheLimits { max_size, max_age })
+    }
+}
+
+/// Follows at most `max` redirects per request, printing each of them if
+/// `verbose`. Exceeding the limit fails with the whole chain of URLs, since the
+/// last hop is often a signed CDN URL which explains the failure.
+fn redirect_policy(max: usize, verbose: bool) -> redirect::Policy {
+    redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > max {
+            let chain = attempt
+                .previous()
+                .iter()
+                .chain(once(attempt.url()))
+                .map(|url| format!("\n  <{}>", url))
+                .collect::<String>();
+            return attempt.error(format!(
+                "more than {} redirects (see `--max-redirects`):{}",
+                max, chain
+            ));
+        }
+        if verbose {
+            if let Some(from) = attempt.previous().last() {
+                eprintln!("redirected from <{}> to <{}>", from, attempt.url());
+            }
+        }
+        attempt.follow()
+    })
+}
+
+/// Prints the settings of rust-analyzer to use the toolchain at `path`, which
+/// has the `components` installed.
+fn print_rust_analyzer_settings(path: &Path, components: &[String]) {
+    let mut settings = serde_json::Map::new();
+    settings.insert(
+        "rust-analyzer.server.path".to_owned(),
+        path.join("bin")
+            .join(format!("rust-analyzer{}", EXE_SUFFIX))
+            .display()
+            .to_string()
+            .into(),
+    );
+    settings.insert(
+        "rust-analyzer.cargo.sysroot".to_owned(),
+        path.display().to_string().into(),
+    );
+    // the sources of the compiler crates are part of rustc-dev.
+    if components.iter().any(|c| c == "rustc-dev") {
+        settings.insert("rust-analyzer.rustc.source".to_owned(), "discover".into());
+    }
+    eprintln!("add these settings of rust-analyzer to your editor to use the toolchain:");
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&settings).unwrap_or_default()
+    );
+    if !components.iter().any(|c| c == "rustc-dev") {
+        eprintln!("add `-c rustc-dev` to analyze crates using `rustc_private` as well");
+    }
+}
+
+/// Returns the Windows host with the other ABI than `host`, e.g.
+/// `x86_64-pc-windows-gnu` for `x86_64-pc-windows-msvc`.
+fn other_windows_abi(host: &str) -> Option<String> {
+    if let Some(arch) = host.strip_suffix("-pc-windows-msvc") {
+        Some(format!("{}-pc-windows-gnu", arch))
+    } else {
+        let arch = host.strip_suffix("-pc-windows-gnu")?;
+        Some(format!("{}-pc-windows-msvc", arch))
+    }
+}
+
+/// Appends the `items` missing from `list`.
+fn extend_unique(list: &mut Vec<String>, items: impl IntoIterator<Item = String>) {
+    for item in items {
+        if !list.contains(&item) {
+            list.push(item);
+        }
+    }
+}
+
+/// Fails if the operation needs the network, which `--offline` forbids.
+fn check_offline(args: &Args, command: Option<&Subcommand>) -> Result<(), Error> {
+    let needs_network = if args.watch {
+        "polling the master branch with `--watch`"
+    } else if args.nightly.is_some() {
+        "downloading the release manifest of `--nightly`"
+    } else if args.expires {
+        "looking up when the artifacts were uploaded with `--expires`"
+    } else {
+        match command {
+            Some(Subcommand::Bisect { .. }) => "listing the merge commits to bisect",
+            Some(Subcommand::Prefetch { .. }) => "listing the merge commits to prefetch",
+            Some(Subcommand::Pick { .. }) => "listing the merge commits to pick from",
+            Some(Subcommand::ListAvailable { .. }) => "listing the latest merge commits",
+            Some(Subcommand::WhichNightly { .. }) => "looking up the nightly releases",
+            Some(Subcommand::Run { commit, .. }) if commit == "master" => {
+                "looking up the latest master commit (pass the commit to run with)"
+            }
+            None if args.commits.is_empty() => {
+                "looking up the latest master commit (pass the commits to install)"
+            }
+            _ => return Ok(()),
+        }
+    };
+    bail!(
+        "{} needs the network, which `--offline` forbids",
+        needs_network
+    )
+}
+
+fn fetch_master_commit(
+    client: &Client,
+    github_token: Option<&str>,
+    wait_for_rate_limit: bool,
+    use_git: bool,
+    progress: &TerminalProgress,
+) -> Result<String, Error> {
+    eprintln!("fetching master commit hash... ");
+    if use_git {
+        match fetch_master_commit_via_git() {
+            Ok(master_commit) => return Ok(master_commit),
+            Err(err) => report_warn(
+                &Error::from(err)
+                    .context("unable to fetch master commit via git, falling back to HTTP"),
+            ),
+        }
+    }
+    let github_token = github_token.map(String::from).or_else(find_github_token);
+    let master_commit = fetch_master_commit_via_http(
+        client,
+        github_token.as_deref(),
+        wait_for_rate_limit,
+        progress,
+    )?;
+    println!("{}", master_commit);
+    Ok(master_commit)
+}
+
+/// Looks for a GitHub token in the `GITHUB_TOKEN` and `GH_TOKEN` environment
+/// variables, and then from the credentials of the GitHub CLI.
+fn find_github_token() -> Option<String> {
+    let from_env = ["GITHUB_TOKEN", "GH_TOKEN"]
+        .iter()
+        .filter_map(|name| var(name).ok())
+        .find(|token| !token.is_empty());
+    if from_env.is_some() {
+        return from_env;
+    }
+
+    let output = Command::new("gh").args(["auth", "token"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let token = String::from_utf8(output.stdout).ok()?;
+    let token = token.trim();
+    if token.is_empty() {
+        None
+    } else {
+        Some(token.to_owned())
+    }
+}
+
+impl Args {
+    /// Returns the GitHub token given explicitly or loaded from the keyring.
+    fn github_token(&self) -> Result<Option<String>, Error> {
+        Ok(match (&self.github_token, self.github_token_from) {
+            (Some(token), _) => Some(token.clone()),
+            (None, Some(TokenSource::Keyring)) => {
+                Some(load_github_token()?.context("no GitHub token is stored in the keyring")?)
+            }
+            (None, _) => None,
+        })
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let mut args = Args::from_matches(&app().get_matches());
+    // the config file and the profile are chosen before any config is read.
+    if args.config.is_none() {
+        args.config = var_os(CONFIG_VAR)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+    }
+    if args.profile_name.is_none() {
+        args.profile_name = var(PROFILE_VAR).ok().filter(|name| !name.is_empty());
+    }
+    let command = match args.command.take() {
+        Some(Subcommand::Config(command)) => {
+            return run_config_command(args.config.as_deref(), command)
+        }
+        Some(Subcommand::Keyring(command)) => return run_keyring_command(command),
+        Some(Subcommand::Mangen) => {
+            print!("{}", man_page(app())?);
+            return Ok(());
+        }
+        command => command,
+    };
+    if !args.no_config {
+        let mut configs = vec![Config::from_env()?];
+        configs.extend(Config::load_all(
+            args.config.as_deref(),
+            args.profile_name.as_deref(),
+        )?);
+        args.merge_configs(configs);
+    }
+    if args.from_toolchain_file {
+        let (path, toolchain) = load_toolchain_file()?;
+        eprintln!(
+            "installing the components and targets of `{}`",
+            path.display()
+        );
+        // rustc and rust-std are always installed.
+        let components = toolchain
+            .components
+            .into_iter()
+            .filter(|component| component != "rustc" && component != "rust-std");
+        extend_unique(&mut args.components, components);
+        extend_unique(&mut args.targets, toolchain.targets);
+    }
+    if args.wasm {
+        extend_unique(&mut args.targets, vec!["wasm".to_owned()]);
+    }
+    if args.embedded {
+        extend_unique(&mut args.targets, vec!["embedded".to_owned()]);
+    }
+    if args.rust_analyzer {
+        extend_unique(
+            &mut args.components,
+            vec!["rust-analyzer".to_owned(), "rust-src".to_owned()],
+        );
+    }
+
+    // `git ls-remote` and the update check bypass the fixtures, so they are
+    // skipped to keep the recorded runs reproducible.
+    let fixtures = match (&args.record, &args.replay) {
+        (Some(dir), _) => Some(HttpFixtures::Record(current_dir()?.join(dir))),
+        (None, Some(dir)) => Some(HttpFixtures::Replay(current_dir()?.join(dir))),
+        (None, None) => None,
+    };
+    if fixtures.is_some() {
+        args.no_git = true;
+        args.no_update_check = true;
+    }
+    set_http_fixtures(fixtures);
+
+    let session = Session::new(args, command.as_ref())?;
+    let args = &session.args;
+    let command = match command {
+        Some(Subcommand::History {
+            commit,
+            since,
+            limit,
+            json,
+        }) => {
+            return print_history(
+                &session.cache,
+                commit.as_deref(),
+                since.as_deref(),
+                limit,
+                json,
+            )
+        }
+        command => command,
+    };
+
+    let tmp_path = session.rustup_home.join("tmp");
+    let dry_run = args.dry_run || matches!(command, Some(Subcommand::Gc { dry_run: true }));
+    if let Err(err) =
+        remove_stale_temp_dirs(&tmp_path, STALE_TEMP_DIR_AGE, dry_run, &session.progress)
+    {
+        report_warn(&Error::from(err).context("failed to remove the stale temporary directories"));
+    }
+    let policy = args.retention_policy()?;
+    let cache_limits = args.cache_limits()?;
+    let command = match command {
+        Some(Subcommand::Cache(CacheCommand::Prune { dry_run })) => {
+            return run_cache_prune_command(&session, &cache_limits, dry_run)
+        }
+        Some(Subcommand::Gc { dry_run }) => return run_gc_command(&session, &policy, dry_run),
+        Some(Subcommand::Containerize {
+            toolchain,
+            output,
+            layer,
+            base_image,
+        }) => return run_containerize_command(&session, &toolchain, &output, layer, &base_image),
+        Some(Subcommand::Audit { toolchain }) => return run_audit_command(&session, &toolchain),
+        Some(Subcommand::ExportForBisect { dry_run }) => {
+            export_for_bisect(
+                &session.toolchains_path,
+                dry_run || args.dry_run,
+                &session.progress,
+            )?;
+            return Ok(());
+        }
+        command => command,
+    };
+
+    let mut commits = args.commits.clone();
+    let installer = Installer::new(&session, command.as_ref(), &mut commits)?;
+    match command {
+        Some(Subcommand::Bisect {
+            start,
+            end,
+            test_command,
+        }) => return run_bisect_command(&installer, start, end, &test_command),
+        Some(Subcommand::Compare { a, b }) => return run_compare_command(&installer, &a, &b),
+        Some(Subcommand::Run {
+            commit,
+            command: program,
+        }) => return run_run_command(&installer, &commit, &program),
+        Some(Subcommand::Bench {
+            crate_path,
+            time_passes,
+            format,
+            commits,
+        }) => return run_bench_command(&installer, &crate_path, time_passes, format, &commits),
+        Some(Subcommand::AddTarget { toolchain, targets }) => {
+            return run_add_target_command(&installer, &toolchain, &targets)
+        }
+        Some(Subcommand::AddComponent {
+            toolchain,
+            components,
+        }) => return run_add_component_command(&installer, &toolchain, &components),
+        Some(Subcommand::RemoveTarget { toolchain, targets }) => {
+            return run_remove_target_command(&installer, &toolchain, &targets)
+        }
+        Some(Subcommand::RemoveComponent {
+            toolchain,
+            components,
+        }) => return run_remove_component_command(&installer, &toolchain, &components),
+        Some(Subcommand::WhichNightly { commit }) => {
+            return run_which_nightly_command(&session, &commit)
+        }
+        Some(Subcommand::ListAvailable { count }) => {
+            return run_list_available_command(&installer, count)
+        }
+        Some(Subcommand::Prefetch { count }) => return run_prefetch_command(&installer, count),
+        Some(Subcommand::Pick { count }) => match run_pick_command(&installer, count, &commits)? {
+            Some(commit) => commits.push(commit),
+            None => return Ok(()),
+        },
+        _ => {}
+    }
+
+    installer.complete_commits(&mut commits)?;
+    if args.show_pr && args.offline {
+        report_warn(&anyhow!(
+            "not looking up the pull requests, since `--show-pr` needs the network, which \
+             `--offline` forbids"
+        ));
+    }
+    if args.show_commit && args.offline {
+        report_warn(&anyhow!(
+            "not showing the commits, since `--show-commit` needs the network, which `--offline` \
+             forbids"
+        ));
+    } else if args.show_commit {
+        print_commit_infos(&session, &commits)?;
+    }
+    if args.expires {
+        return installer.print_expiry(&commits);
+    }
+    if args.sizes {
+        return installer.print_sizes(&commits);
+    }
+    installer.install_all(commits, &policy, &cache_limits)
+}
+
+/// The options and the connections shared by every subcommand once the
+/// configs are merged.
+struct Session {
+    args: Args,
+    client: Client,
+    cache: Cache,
+    progress: TerminalProgress,
+    /// The shared directory of `--system`.
+    system_dir: Option<PathBuf>,
+    rustup_home: PathBuf,
+    toolchains_path: PathBuf,
+    jobs: usize,
+}
+
+impl Session {
+    fn new(args: Args, command: Option<&Subcommand>) -> Result<Self, Error> {
+        let client = build_client(&args)?;
+
+        ensure!(
+            args.system.is_none() || args.rustup_home.is_none(),
+            "`--system` cannot be combined with `--rustup-home`"
+        );
+        ensure!(
+            !args.group_writable || args.system.is_some(),
+            "`--group-writable` requires `--system`"
+        );
+        // the shared directory is laid out like a rustup home, without relying
+        // on the home directory of the user.
+        let system_dir = match &args.system {
+            Some(path) => Some(current_dir()?.join(path)),
+            None => None,
+        };
+        let rustup_home = match (&system_dir, &args.rustup_home) {
+            (Some(path), _) => path.clone(),
+            (None, Some(path)) => current_dir()?.join(path),
+            (None, None) => home::rustup_home().expect("$RUSTUP_HOME is undefined?"),
+        };
+        if let (Some(dir), false) = (&system_dir, args.dry_run) {
+            prepare_system_dir(dir, args.group_writable)?;
+        }
+        let toolchains_path = toolchains_path(&rustup_home)?;
+        let default_cache_dir = match &system_dir {
+            Some(_) => None,
+            None => Cache::default_dir(),
+        };
+        let cache = Cache::new(
+            args.cache_dir
+                .clone()
+                .or(default_cache_dir)
+                .unwrap_or_else(|| rustup_home.join("tmp").join("rtim-cache")),
+            // prefetching is pointless unless the downloads are kept.
+            args.cache_downloads || matches!(command, Some(Subcommand::Prefetch { .. })),
+        )
+        .offline(args.offline);
+        if args.offline {
+            check_offline(&args, command)?;
+        } else if !args.no_update_check {
+            check_for_update(&client, &cache.update_check_path());
+        }
+
+        let jobs = args.jobs.unwrap_or(1);
+        ensure!(jobs > 0, "the number of jobs must be positive");
+        let progress = TerminalProgress::new(jobs == 1).trace_http(args.debug_http);
+        let cancelled = progress.cancel_flag();
+        ctrlc::set_handler(move || {
+            // exit immediately if the user presses Ctrl-C again
+            if cancelled.swap(true, Ordering::SeqCst) {
+                exit(130);
+            }
+            eprintln!("cancelling...");
+        })?;
+
+        Ok(Session {
+            args,
+            client,
+            cache,
+            progress,
+            system_dir,
+            rustup_home,
+            toolchains_path,
+            jobs,
+        })
+    }
+
+    /// Returns the client to download with, or `None` in dry run mode.
+    fn dry_run_client(&self) -> Option<&Client> {
+        if self.args.dry_run {
+            None
+        } else {
+            Some(&self.client)
+        }
+    }
+}
+
+/// Builds the HTTP client with the proxy and the connection limits of `args`.
+fn build_client(args: &Args) -> Result<Client, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_static("rustup-toolchain-install-master"),
+    );
+
+    let mut client_builder =
+        ClientBuilder::new()
+            .default_headers(headers)
+            .redirect(redirect_policy(
+                args.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS),
+                args.verbose,
+            ));
+    if let Some(proxy) = &args.proxy {
+        client_builder = client_builder.proxy(Proxy::all(proxy)?);
+    }
+    if let Some(timeout) = &args.pool_idle_timeout {
+        let timeout = humantime::parse_duration(timeout)
+            .with_context(|| format!("invalid pool idle timeout `{}`", timeout))?;
+        client_builder = client_builder.pool_idle_timeout(timeout);
+    }
+    if let Some(max) = args.pool_max_idle {
+        client_builder = client_builder.pool_max_idle_per_host(max);
+    }
+    Ok(client_builder.build()?)
+}
+
+fn run_cache_prune_command(
+    session: &Session,
+    cache_limits: &CacheLimits,
+    dry_run: bool,
+) -> Result<(), Error> {
+    ensure!(
+        !cache_limits.is_empty(),
+        "no cache limit is configured, set `cache-max-size` or `cache-max-age` first"
+    );
+    let dry_run = dry_run || session.args.dry_run;
+    let freed = session
+        .cache
+        .prune(cache_limits, dry_run, &session.progress)?;
+    eprintln!(
+        "{} {} of downloads",
+        if dry_run { "would free" } else { "freed" },
+        format_size(freed)
+    );
+    Ok(())
+}
+
+fn run_gc_command(session: &Session, policy: &RetentionPolicy, dry_run: bool) -> Result<(), Error> {
+    ensure!(
+        !policy.is_empty(),
+        "no retention policy is configured, set `retention` or `max-toolchains` first"
+    );
+    let dry_run = dry_run || session.args.dry_run;
+    let removed = prune(&session.toolchains_path, policy, dry_run, &session.progress)?;
+    if !dry_run {
+        record_removals(
+            &session.cache,
+            &session.toolchains_path,
+            &session.args.hooks,
+            removed,
+        )?;
+    }
+    Ok(())
+}
+
+fn run_containerize_command(
+    session: &Session,
+    toolchain: &str,
+    output: &Path,
+    layer: bool,
+    base_image: &str,
+) -> Result<(), Error> {
+    let toolchain = &resolve_toolchain(&session.toolchains_path, toolchain)?;
+    let path = session.toolchains_path.join(toolchain);
+    if layer {
+        write_layer(&path, output)?;
+    } else {
+        write_context(&path, output, base_image)?;
+    }
+    eprintln!(
+        "toolchain `{}` is packed into `{}`",
+        toolchain,
+        output.display()
+    );
+    Ok(())
+}
+
+fn run_audit_command(session: &Session, toolchain: &str) -> Result<(), Error> {
+    let toolchain = &resolve_toolchain(&session.toolchains_path, toolchain)?;
+    let audit = audit_toolchain(&session.toolchains_path, toolchain, &session.progress)?;
+    for file in &audit.missing {
+        println!("missing\t{}", file);
+    }
+    for file in &audit.modified {
+        println!("modified\t{}", file);
+    }
+    if audit.unhashed > 0 {
+        report_warn(&anyhow!(
+            "{} files cannot be checked, since their hashes were not recorded when they were \
+             installed; reinstall the toolchain to record them",
+            audit.unhashed
+        ));
+    }
+    ensure!(
+        audit.is_intact(),
+        "toolchain `{}` has {} missing and {} modified files",
+        toolchain,
+        audit.missing.len(),
+        audit.modified.len()
+    );
+    eprintln!(
+        "all {} checked files of toolchain `{}` are intact",
+        audit.checked, toolchain
+    );
+    Ok(())
+}
+
+/// The toolchains requested by the options, which the subcommands installing
+/// toolchains share.
+struct Installer<'a> {
+    session: &'a Session,
+    hosts: Vec<String>,
+    /// The requested targets, without the excluded ones.
+    targets: Vec<String>,
+    /// The requested components, without the excluded ones.
+    components: Vec<String>,
+    component_commits: BTreeMap<String, String>,
+    component_urls: BTreeMap<String, String>,
+    /// Whether the commits are installed, rather than used by a subcommand.
+    installs_commits: bool,
+    outdated_rustup: bool,
+    /// Where the toolchains are installed, which is the staging directory with
+    /// `--destdir`.
+    toolchains_path: PathBuf,
+    /// Where the toolchains are extracted before being moved into place.
+    toolchains_dir: TempDir,
+    // a staged toolchain is installed next to the destdir, without touching
+    // the rustup home.
+    _staging_dir: Option<TempDir>,
+    /// The release manifest of `--nightly`.
+    manifest: Option<Manifest>,
+    max_download_size: Option<u64>,
+    /// The lock file of `--locked`.
+    lockfile: Option<Lockfile>,
+    /// Whether the metadata of the toolchains is kept for `--sbom` and
+    /// `--lock`.
+    record_installed: bool,
+    installed_toolchains: Mutex<Vec<(String, InstallMetadata)>>,
+    pr_github_token: Option<String>,
+    check_results: Mutex<Vec<(usize, String, bool)>>,
+}
+
+impl<'a> Installer<'a> {
+    /// Checks the options of installing toolchains for `command`, adding the
+    /// commit of `--nightly` to `commits`.
+    fn new(
+        session: &'a Session,
+        command: Option<&Subcommand>,
+        commits: &mut Vec<String>,
+    ) -> Result<Self, Error> {
+        let args = &session.args;
+        if args.commits.len() > 1 && args.name.is_some() {
+            return Err(Error::msg(
+                "name argument can only be provided with a single commit",
+            ));
+        }
+
+        let mut hosts = if args.hosts.is_empty() {
+            vec![detect_host()]
+        } else {
+            args.hosts.clone()
+        };
+        if args.both_abis {
+            let others = hosts
+                .iter()
+                .filter_map(|host| other_windows_abi(host))
+                .collect::<Vec<_>>();
+            ensure!(
+                !others.is_empty(),
+                "`--both-abis` needs a `-pc-windows-msvc` or `-pc-windows-gnu` host"
+            );
+            extend_unique(&mut hosts, others);
+        }
+        // the subcommands other than installing use the first host.
+        let host: &str = &hosts[0];
+
+        let mut targets = expand_targets(&args.targets, &session.toolchains_path)?;
+        // without `--detect-targets` the cargo config only gives a hint, so a
+        // broken one is not an error.
+        let detected = if args.detect_targets {
+            cargo_config_targets()?
+        } else {
+            cargo_config_targets().unwrap_or_default()
+        };
+        let missing = detected
+            .into_iter()
+            .filter(|target| target != host && !targets.contains(target))
+            .collect::<Vec<_>>();
+        if args.detect_targets {
+            targets.extend(missing);
+        } else if !missing.is_empty()
+            && !args.no_std
+            && !matches!(
+                command,
+                Some(
+                    Subcommand::AddTarget { .. }
+                        | Subcommand::AddComponent { .. }
+                        | Subcommand::RemoveTarget { .. }
+                        | Subcommand::RemoveComponent { .. }
+                )
+            )
+        {
+            report_warn(&anyhow!(
+                "rust-std is not installed for `{}`, which the cargo config of the project \
+                 builds for; pass `--detect-targets` to install it as well",
+                missing.join("`, `")
+            ));
+        }
+        let mut components = args.components.clone();
+        for item in &args.exclude {
+            if item == host {
+                report_warn(&anyhow!(
+                    "rust-std for the host `{}` is always installed, pass `--no-std` to leave it \
+                     out",
+                    item
+                ));
+            } else if !components.contains(item) && !targets.contains(item) {
+                report_warn(&anyhow!(
+                    "`{}` passed to `--exclude` is neither a requested component nor a target",
+                    item
+                ));
+            }
+        }
+        components.retain(|component| !args.exclude.contains(component));
+        targets.retain(|target| !args.exclude.contains(target));
+
+        // `pick` installs the commit it asks for like the commits given
+        // directly.
+        let installs_commits = matches!(command, None | Some(Subcommand::Pick { .. }));
+        ensure!(
+            hosts.len() == 1
+                || (installs_commits
+                    && args.name.is_none()
+                    && args.destdir.is_none()
+                    && args.lock.is_none()
+                    && !args.sizes),
+            "several hosts can only be given when installing toolchains without `--name`, \
+             `--destdir`, `--lock` or `--sizes`"
+        );
+        // the shared directory and staged toolchains are not used by this
+        // rustup.
+        let outdated_rustup =
+            installs_commits && session.system_dir.is_none() && args.destdir.is_none() && {
+                match rustup_version() {
+                    Some(version) if is_outdated(version) => {
+                        report_warn(&anyhow!(
+                            "rustup {} is older than {}, which may not recognize the installed \
+                             toolchains; aliases are linked with `rustup toolchain link` \
+                             instead, but consider running `rustup self update`",
+                            format_version(version),
+                            format_version(MIN_RUSTUP_VERSION)
+                        ));
+                        true
+                    }
+                    _ => false,
+                }
+            };
+        ensure!(
+            args.destdir.is_none()
+                || (installs_commits
+                    && args.commits.len() <= 1
+                    && !args.both
+                    && !args.watch
+                    && args.aliases.is_empty()
+                    && args.run.is_none()
+                    && args.check_crate.is_none()),
+            "`--destdir` can only be used when installing a single toolchain without running it"
+        );
+        ensure!(
+            args.install_prefix
+                .components()
+                .all(|c| !matches!(c, Component::ParentDir)),
+            "the install prefix `{}` must not contain `..`",
+            args.install_prefix.display()
+        );
+        let staging_dir = match &args.destdir {
+            Some(destdir) => Some(create_temp_dir(destdir)?),
+            None => None,
+        };
+        let toolchains_path = match &staging_dir {
+            // the locks are kept next to the toolchains.
+            Some(dir) => {
+                let path = dir.path().join("toolchains");
+                create_dir_all(&path)?;
+                path
+            }
+            None => session.toolchains_path.clone(),
+        };
+        if let (Some(destdir), false) = (&args.destdir, args.force) {
+            let dest = staged_path(destdir, &args.install_prefix);
+            ensure!(!dest.exists(), "`{}` already exists", dest.display());
+        }
+
+        if !args.dry_run {
+            check_writable(&toolchains_path)?;
+        }
+        let tmp_path = session.rustup_home.join("tmp");
+        let toolchains_dir = if let Some(dir) = &staging_dir {
+            create_temp_dir(dir.path())?
+        } else if tmp_path.exists() && !tmp_path.is_dir() {
+            tempdir()?
+        } else {
+            create_temp_dir(&tmp_path)?
+        };
+
+        if let Some(template) = &args.url_template {
+            check_url_template(template)?;
+        }
+        let component_commits = args
+            .component_commits
+            .iter()
+            .cloned()
+            .chain(
+                args.std_commit
+                    .iter()
+                    .map(|c| ("rust-std".to_owned(), c.clone())),
+            )
+            .collect::<BTreeMap<_, _>>();
+        for commit in component_commits.values() {
+            ensure!(
+                is_full_commit(commit),
+                "`{}` is not a full 40-digit commit hash",
+                commit
+            );
+        }
+        let component_urls = args
+            .component_urls
+            .iter()
+            .map(|(component, url)| Ok((component.clone(), absolute_url(url, &current_dir()?)?)))
+            .collect::<Result<BTreeMap<_, _>, Error>>()?;
+        ensure!(
+            (component_commits.is_empty() && component_urls.is_empty()) || args.name.is_some(),
+            "`--name` is required for a toolchain mixed from several commits or URLs"
+        );
+        ensure!(
+            args.rust_dev_dest.is_none() || components.iter().any(|c| c == "rust-dev"),
+            "`--dest` requires `-c rust-dev`"
+        );
+        ensure!(
+            args.rust_dev_dest.is_none() || !args.both,
+            "`--dest` cannot be combined with `--both`"
+        );
+        ensure!(
+            !(args.prefix.is_some() && (args.alt || args.both)),
+            "`--prefix` cannot be combined with `--alt` or `--both`"
+        );
+
+        let manifest = match &args.nightly {
+            Some(date) => {
+                ensure!(
+                    command.is_none() && commits.is_empty(),
+                    "`--nightly` cannot be combined with commits or subcommands"
+                );
+                let date = if date == "latest" {
+                    None
+                } else {
+                    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                        .with_context(|| format!("`{}` is not a date like `2020-01-31`", date))?;
+                    Some(date.format("%Y-%m-%d").to_string())
+                };
+                let url = manifest_url(
+                    args.dist_server.as_deref().unwrap_or(DEFAULT_DIST_SERVER),
+                    "nightly",
+                    date.as_deref(),
+                );
+                eprintln!("fetching manifest <{}>...", url);
+                let manifest = Manifest::fetch(&session.client, &url, &session.progress)?;
+                match manifest.commit() {
+                    Some(commit) => commits.push(commit.to_owned()),
+                    None => bail!("the manifest does not list the commit of rustc"),
+                }
+                Some(manifest)
+            }
+            None => None,
+        };
+
+        let max_download_size = args
+            .max_download_size
+            .as_deref()
+            .map(parse_size)
+            .transpose()?;
+        ensure!(
+            max_download_size.is_none() || args.nightly.is_none(),
+            "`--max-download-size` cannot be combined with `--nightly`"
+        );
+        ensure!(
+            args.sbom.is_none() || (installs_commits && !args.watch),
+            "`--sbom` can only be used when installing toolchains"
+        );
+        ensure!(
+            args.lock.is_none() || (installs_commits && !args.watch && args.nightly.is_none()),
+            "`--lock` can only be used when installing toolchains built by CI"
+        );
+        let lockfile = match (&args.lock, args.locked) {
+            (Some(path), true) => Some(Lockfile::read(path)?),
+            _ => None,
+        };
+        let pr_github_token = if args.show_pr && !args.offline {
+            args.github_token()?.or_else(find_github_token)
+        } else {
+            None
+        };
+
+        let installer = Installer {
+            session,
+            hosts,
+            targets,
+            components,
+            component_commits,
+            component_urls,
+            installs_commits,
+            outdated_rustup,
+            toolchains_path,
+            toolchains_dir,
+            _staging_dir: staging_dir,
+            manifest,
+            max_download_size,
+            lockfile,
+            record_installed: args.sbom.is_some() || (args.lock.is_some() && !args.locked),
+            installed_toolchains: Mutex::new(Vec::new()),
+            pr_github_token,
+            check_results: Mutex::new(Vec::new()),
+        };
+        // fail before installing anything, with a name like the real ones.
+        let name = installer.toolchain_name(&"0".repeat(40), args.alt, installer.host());
+        if let Err(err) = check_toolchain_name(&name) {
+            return Err(match (&args.name, &args.name_template) {
+                (None, Some(template)) => Error::from(err).context(format!(
+                    "`--name-template {}` makes invalid names",
+                    template
+                )),
+                _ => err.into(),
+            });
+        }
+        Ok(installer)
+    }
+
+    /// Returns the host used by the subcommands other than installing.
+    fn host(&self) -> &str {
+        &self.hosts[0]
+    }
+
+    /// Returns the URL of the builds of the normal or `alt` toolchains.
+    fn prefix_of(&self, alt: bool) -> String {
+        let args = &self.session.args;
+        let server = args.server.as_deref().unwrap_or(DEFAULT_SERVER);
+        let builds_dir = if args.try_build {
+            Some(TRY_BUILDS_DIR)
+        } else {
+            args.prefix.as_deref()
+        };
+        let prefix = match builds_dir {
+            Some(dir) => format!("{}/{}", server, dir),
+            None => builds_url(server, alt),
+        };
+        match &args.url_template {
+            Some(template) => templated_builds_url(template, server, &prefix),
+            None => prefix,
+        }
+    }
+
+    /// Returns the targets to install rust-std for with the toolchain of
+    /// `host`.
+    fn std_targets_of(&self, host: &str) -> Vec<String> {
+        if self.session.args.no_std {
+            Vec::new()
+        } else {
+            self.targets
+                .iter()
+                .filter(|target| *target != host)
+                .cloned()
+                .chain(once(host.to_owned()))
+                .collect()
+        }
+    }
+
+    fn toolchain_name(&self, commit: &str, alt: bool, host: &str) -> String {
+        let args = &self.session.args;
+        if let Some(name) = &args.name {
+            return name.clone();
+        }
+        let name_template = args
+            .name_template
+            .as_deref()
+            .unwrap_or(DEFAULT_NAME_TEMPLATE);
+        let name = name_template
+            .replace("{commit}", commit)
+            .replace("{alt}", if alt { "-alt" } else { "" })
+            .replace("{host}", host);
+        // the toolchains of several hosts need different names.
+        if self.hosts.len() > 1 && !name_template.contains("{host}") {
+            format!("{}-{}", name, host)
+        } else {
+            name
+        }
+    }
+
+    /// Installs the toolchain of `commit` unless it is installed, returning
+    /// its name and channel, and whether it has been installed just now.
+    fn install(
+        &self,
+        commit: &str,
+        alt: bool,
+        host: &str,
+        channel: Option<&str>,
+    ) -> Result<(String, Option<String>, bool), Error> {
+        let session = self.session;
+        let args = &session.args;
+        let name = self.toolchain_name(commit, alt, host);
+        let rust_std_targets = self.std_targets_of(host);
+        let locked = match &self.lockfile {
+            Some(lockfile) => {
+                let locked = lockfile.find(commit, alt).ok_or_else(|| {
+                    anyhow!(
+                        "the {} build of commit `{}` is not locked, install without `--locked` \
+                         to update the lock file",
+                        if alt { "alt" } else { "normal" },
+                        commit
+                    )
+                })?;
+                let targets = if args.targets.is_empty() {
+                    &[][..]
+                } else {
+                    &rust_std_targets[..]
+                };
+                locked.check_request(host, &self.components, targets)?;
+                ensure!(
+                    (locked.component_commits.is_empty() && locked.component_urls.is_empty())
+                        || args.name.is_some(),
+                    "`--name` is required for a toolchain mixed from several commits or URLs"
+                );
+                Some(locked)
+            }
+            None => None,
+        };
+
+        let toolchain = match locked {
+            Some(locked) => Toolchain {
+                commit: commit.to_owned(),
+                alt,
+                host_target: locked.host.clone(),
+                rust_std_targets: locked.targets.clone(),
+                components: locked.components.clone(),
+                component_commits: locked.component_commits.clone(),
+                component_urls: locked.component_urls.clone(),
+                artifact_hashes: locked.artifact_hashes(),
+                file_modes: args.chmod.unwrap_or_default(),
+                dest: self.toolchains_dir.path().join(&name),
+                name: name.clone(),
+            },
+            None => Toolchain {
+                commit: commit.to_owned(),
+                alt,
+                host_target: host.to_owned(),
+                rust_std_targets,
+                components: self.components.clone(),
+                component_commits: self.component_commits.clone(),
+                component_urls: self.component_urls.clone(),
+                artifact_hashes: BTreeMap::new(),
+                file_modes: args.chmod.unwrap_or_default(),
+                dest: self.toolchains_dir.path().join(&name),
+                name: name.clone(),
+            },
+        };
+        let channel = locked.map(|locked| locked.channel.as_str()).or(channel);
+        let toolchain_path = self.toolchains_path.join(&name);
+        if let (Some(max), false) = (self.max_download_size, args.dry_run) {
+            if args.force || !toolchain_path.is_dir() {
+                let sizes = toolchain_sizes(
+                    &session.client,
+                    &session.cache,
+                    &self.prefix_of(alt),
+                    &toolchain,
+                    channel.or(args.channel.as_deref()),
+                    &session.progress,
+                )?;
+                check_download_size(&name, &sizes, max)?;
+            }
+        }
+        if let (Some(script), false) = (&args.hooks.pre_install, args.dry_run) {
+            if args.force || !toolchain_path.is_dir() {
+                run_hook(
+                    "pre-install",
+                    script,
+                    &HookContext {
+                        name: &name,
+                        path: &toolchain_path,
+                        commit,
+                        host: &toolchain.host_target,
+                        components: &toolchain.components,
+                        targets: &toolchain.rust_std_targets,
+                    },
+                )?;
+            }
+        }
+        let start = Instant::now();
+        let result = match &self.manifest {
+            Some(manifest) => install_from_manifest(
+                session.dry_run_client(),
+                &session.cache,
+                manifest,
+                "nightly",
+                &self.toolchains_path,
+                &toolchain,
+                args.force,
+                &session.progress,
+            ),
+            None => {
+                // only what differs from the request is downloaded again.
+                let updated = if args.force {
+                    update_toolchain(
+                        &session.client,
+                        session.dry_run_client(),
+                        &session.cache,
+                        &self.prefix_of(alt),
+                        &self.toolchains_path,
+                        &toolchain,
+                        &session.progress,
+                    )
+                } else {
+                    Ok(None)
+                };
+                updated.transpose().unwrap_or_else(|| {
+                    install_single_toolchain(
+                        &session.client,
+                        session.dry_run_client(),
+                        &session.cache,
+                        &self.prefix_of(alt),
+                        &self.toolchains_path,
+                        &toolchain,
+                        channel.or(args.channel.as_deref()),
+                        args.force,
+                        &session.progress,
+                    )
+                })
+            }
+        };
+        if !args.dry_run {
+            let (outcome, error) = match &result {
+                Ok(_) => (Outcome::Success, None),
+                Err(InstallError::AlreadyInstalled { .. }) => (Outcome::AlreadyInstalled, None),
+                Err(err) => (Outcome::Failure, Some(err.to_string())),
+            };
+            record_history(
+                &session.cache,
+                HistoryEntry {
+                    time: Utc::now(),
+                    action: Action::Install,
+                    name: name.clone(),
+                    commit: Some(commit.to_owned()),
+                    components: toolchain.components.clone(),
+                    targets: toolchain.rust_std_targets.clone(),
+                    outcome,
+                    error,
+                    duration: start.elapsed(),
+                },
+            );
+        }
+
+        match result {
+            Err(InstallError::AlreadyInstalled { name }) => {
+                eprintln!("toolchain `{}` is already installed", name);
+                let metadata = InstallMetadata::read(&self.toolchains_path.join(&name))?;
+                if let Some(locked) = locked {
+                    match &metadata {
+                        Some(metadata) => locked.check_installed(&name, metadata)?,
+                        None => bail!(
+                            "toolchain `{}` was not installed by this tool, reinstall it with \
+                             `--force`",
+                            name
+                        ),
+                    }
+                }
+                let channel = metadata.as_ref().map(|m| m.channel.clone());
+                if let (Some(metadata), true) = (metadata, self.record_installed) {
+                    self.installed_toolchains
+                        .lock()
+                        .unwrap()
+                        .push((name.clone(), metadata));
+                }
+                Ok((name, channel, false))
+            }
+            result => {
+                let installed = result?;
+                if !args.dry_run {
+                    for problem in check_host(&installed.path, &toolchain.host_target) {
+                        report_warn(&problem);
+                    }
+                }
+                if args.show_pr && !args.offline && !args.dry_run {
+                    let pull_request = record_pull_request(
+                        &session.client,
+                        self.pr_github_token.as_deref(),
+                        args.wait_for_rate_limit,
+                        commit,
+                        &installed.path,
+                        &session.progress,
+                    );
+                    match pull_request {
+                        Ok(Some(pr)) => {
+                            eprintln!("commit `{}` merged #{}: {}", commit, pr.number, pr.title)
+                        }
+                        Ok(None) => eprintln!("commit `{}` did not merge a pull request", commit),
+                        // the toolchain is installed anyway.
+                        Err(err) => report_warn(&err.context(format!(
+                            "failed to look up the pull request of commit `{}`",
+                            commit
+                        ))),
+                    }
+                }
+                if let (Some(dest), false) = (&args.rust_dev_dest, args.dry_run) {
+                    move_rust_dev(&installed.path, dest, args.force)?;
+                }
+                if let (Some(dir), false) = (&session.system_dir, args.dry_run) {
+                    share_toolchain(&installed.path, args.group_writable)?;
+                    print_link_instructions(dir, &installed.name);
+                }
+                // read before `--destdir` removes the metadata.
+                if self.record_installed && !args.dry_run {
+                    if let Some(metadata) = InstallMetadata::read(&installed.path)? {
+                        self.installed_toolchains
+                            .lock()
+                            .unwrap()
+                            .push((installed.name.clone(), metadata));
+                    }
+                }
+                let mut installed_path = installed.path.clone();
+                if let (Some(destdir), false) = (&args.destdir, args.dry_run) {
+                    move_to_destdir(&installed.path, destdir, &args.install_prefix, args.force)?;
+                    installed_path = staged_path(destdir, &args.install_prefix);
+                }
+                if let (Some(script), false) = (&args.hooks.post_install, args.dry_run) {
+                    run_hook(
+                        "post-install",
+                        script,
+                        &HookContext {
+                            name: &installed.name,
+                            path: &installed_path,
+                            commit,
+                            host: &toolchain.host_target,
+                            components: &toolchain.components,
+                            targets: &toolchain.rust_std_targets,
+                        },
+                    )?;
+                }
+                Ok((installed.name, Some(installed.channel), true))
+            }
+        }
+    }
+
+    /// Adds the locked commits, or the latest master commit if none is given,
+    /// unless `--watch` polls for them.
+    fn complete_commits(&self, commits: &mut Vec<String>) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        ensure!(
+            !args.watch || commits.is_empty(),
+            "commits cannot be provided with `--watch`"
+        );
+        if let Some(lockfile) = &self.lockfile {
+            let locked = lockfile.commits();
+            if commits.is_empty() {
+                *commits = locked;
+            } else if let Some(commit) = commits.iter().find(|commit| !locked.contains(commit)) {
+                bail!(
+                    "commit `{}` is not locked, install without `--locked` to update the lock \
+                     file",
+                    commit
+                );
+            }
+        }
+        if commits.is_empty() && !args.watch {
+            commits.push(fetch_master_commit(
+                &session.client,
+                args.github_token()?.as_deref(),
+                args.wait_for_rate_limit,
+                !args.no_git,
+                &session.progress,
+            )?);
+        }
+        Ok(())
+    }
+
+    /// Prints when the artifacts of `commits` are expected to be deleted.
+    fn print_expiry(&self, commits: &[String]) -> Result<(), Error> {
+        let session = self.session;
+        let prefix = self.prefix_of(session.args.alt);
+        for commit in commits {
+            let channel = match &session.args.channel {
+                Some(channel) => Some(channel.as_str()),
+                None => match session.cache.channel(commit) {
+                    Some(channel) => Some(channel),
+                    None => probe_channel(&session.client, &prefix, commit, &session.progress)?,
+                },
+            };
+            let uploaded = match channel {
+                Some(channel) => {
+                    upload_time(&session.client, &prefix, commit, channel, &session.progress)?
+                }
+                None => None,
+            };
+            let Some(uploaded) = uploaded else {
+                println!(
+                    "{}: the artifacts have been deleted or never existed",
+                    commit
+                );
+                continue;
+            };
+            let expires = uploaded + chrono::Duration::days(ARTIFACT_RETENTION_DAYS);
+            let days_left = (expires - Utc::now()).num_days();
+            println!(
+                "{}: uploaded on {}, expected to be deleted around {} ({})",
+                commit,
+                uploaded.format("%Y-%m-%d %H:%M UTC"),
+                expires.format("%Y-%m-%d"),
+                if days_left > 0 {
+                    format!("in {} days", days_left)
+                } else {
+                    "any time now".to_owned()
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the sizes of the archives of the toolchains of `commits`.
+    fn print_sizes(&self, commits: &[String]) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        ensure!(
+            self.installs_commits && args.nightly.is_none() && !args.both,
+            "`--sizes` can only be used when installing the normal or alt builds of commits"
+        );
+        let prefix = self.prefix_of(args.alt);
+        for commit in commits {
+            let toolchain = Toolchain {
+                commit: commit.clone(),
+                alt: args.alt,
+                host_target: self.host().to_owned(),
+                rust_std_targets: self.std_targets_of(self.host()),
+                components: self.components.clone(),
+                component_commits: self.component_commits.clone(),
+                component_urls: self.component_urls.clone(),
+                artifact_hashes: BTreeMap::new(),
+                file_modes: FileModes::Archive,
+                name: String::new(),
+                dest: PathBuf::new(),
+            };
+            let sizes = toolchain_sizes(
+                &session.client,
+                &session.cache,
+                &prefix,
+                &toolchain,
+                args.channel.as_deref(),
+                &session.progress,
+            )?;
+            println!("{}:\n{}", commit, size_table(&sizes));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the normal or the alt builds are installed.
+    fn variants(&self) -> &'static [bool] {
+        if self.session.args.both {
+            &[false, true]
+        } else if self.session.args.alt {
+            &[true]
+        } else {
+            &[false]
+        }
+    }
+
+    /// Runs the command and checks the crate with the installed toolchain
+    /// `name`.
+    fn run_with(
+        &self,
+        index: usize,
+        commit: &str,
+        name: String,
+        installed: bool,
+    ) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        let mut result = match &args.run {
+            Some(script) => run_with_toolchain(script, &self.toolchains_path, &name),
+            None => Ok(()),
+        };
+        if let (Ok(()), Some(path)) = (&result, &args.check_crate) {
+            match check_crate(path, &args.check_command, &self.toolchains_path, &name) {
+                Ok(passed) => {
+                    self.check_results
+                        .lock()
+                        .unwrap()
+                        .push((index, name.clone(), passed))
+                }
+                Err(err) => result = Err(err),
+            }
+        }
+        // only remove the toolchains installed just for this run.
+        if args.ephemeral && installed {
+            eprintln!("removing toolchain `{}`", name);
+            let start = Instant::now();
+            let path = self.toolchains_path.join(&name);
+            let metadata = InstallMetadata::read(&path)?;
+            remove_dir_all(&path)?;
+            record_history(
+                &session.cache,
+                HistoryEntry {
+                    time: Utc::now(),
+                    action: Action::Uninstall,
+                    name: name.clone(),
+                    commit: Some(commit.to_owned()),
+                    components: Vec::new(),
+                    targets: Vec::new(),
+                    outcome: Outcome::Success,
+                    error: None,
+                    duration: start.elapsed(),
+                },
+            );
+            if let (Some(script), Some(metadata)) = (&args.hooks.post_uninstall, &metadata) {
+                run_hook(
+                    "post-uninstall",
+                    script,
+                    &HookContext {
+                        name: &name,
+                        path: &path,
+                        commit,
+                        host: &metadata.host,
+                        components: &metadata.components,
+                        targets: &metadata.targets,
+                    },
+                )?;
+            }
+        }
+        result
+    }
+
+    /// Installs every variant of the toolchain of `commit`, the `index`-th
+    /// commit given, and runs the commands with it.
+    fn install_and_run(&self, index: usize, commit: &str) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        // the alt build has the same channel, so it is only detected once.
+        let mut channel = None;
+        for (&alt, host) in self
+            .variants()
+            .iter()
+            .flat_map(|alt| self.hosts.iter().map(move |host| (alt, host)))
+        {
+            let (name, detected_channel, installed) =
+                self.install(commit, alt, host, channel.as_deref())?;
+            channel = channel.or(detected_channel);
+            if is_github_actions() {
+                set_output("toolchain", &name)?;
+            }
+            if args.rust_analyzer {
+                // a staged toolchain is used from where the package installs it.
+                let path = match &args.destdir {
+                    Some(_) => args.install_prefix.clone(),
+                    None => self.toolchains_path.join(&name),
+                };
+                print_rust_analyzer_settings(&path, &self.components);
+            }
+            if let Some(shell) = args.print_env {
+                // a staged toolchain is used from where the package installs it.
+                let path = match &args.destdir {
+                    Some(_) => args.install_prefix.clone(),
+                    None => self.toolchains_path.join(&name),
+                };
+                print_env(&path, &self.components, shell.unwrap_or_else(Shell::detect));
+            }
+            for alias in &args.aliases {
+                if self.outdated_rustup {
+                    if !args.dry_run {
+                        link_toolchain(
+                            &session.rustup_home,
+                            alias,
+                            &self.toolchains_path.join(&name),
+                        )?;
+                    }
+                    continue;
+                }
+                alias_toolchain(
+                    &self.toolchains_path,
+                    &name,
+                    alias,
+                    args.force,
+                    args.dry_run,
+                    &session.progress,
+                )?;
+            }
+            if !args.dry_run {
+                self.run_with(index, commit, name, installed)?;
+            }
+        }
+        Ok(())
+    }
 
-        let result = install_single_toolchain(
-            &client,
-            dry_run_client,
-            &prefix,
-            &toolchains_path,
-            &Toolchain {
-                commit: &commit,
-                host_target: host,
-                rust_std_targets: &rust_std_targets,
-                components: &components,
-                dest,
-            },
-            args.channel.as_deref(),
-            args.force,
+    /// Installs the toolchains of `commits` in parallel, or of every new master
+    /// commit with `--watch`, and runs the commands with them.
+    fn install_all(
+        self,
+        commits: Vec<String>,
+        policy: &RetentionPolicy,
+        cache_limits: &CacheLimits,
+    ) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        ensure!(
+            args.aliases.is_empty()
+                || (commits.len() == 1 && !args.both && self.hosts.len() == 1 && !args.watch),
+            "`--alias` can only be used when installing a single toolchain"
+        );
+        ensure!(
+            !args.ephemeral || args.run.is_some() || args.check_crate.is_some(),
+            "`--ephemeral` requires `--run` or `--check-crate`"
+        );
+        ensure!(
+            !args.both
+                || args
+                    .name_template
+                    .as_deref()
+                    .unwrap_or(DEFAULT_NAME_TEMPLATE)
+                    .contains("{alt}"),
+            "the name template must contain `{{alt}}` to install both the normal and alt builds"
         );
+        if args.watch {
+            return self.watch(policy);
+        }
 
-        if args.keep_going {
-            if let Err(err) = result {
-                report_warn(
-                    &err.context(format!("skipping toolchain `{}` due to a failure", commit)),
-                );
-                failed = true;
+        // every toolchain is extracted into its own directory, so they can be
+        // installed in parallel.
+        let queue = Mutex::new(commits.into_iter().enumerate());
+        let failed = AtomicBool::new(false);
+        let first_error = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..session.jobs {
+                scope.spawn(|| {
+                    while first_error.lock().unwrap().is_none() {
+                        let (index, commit) = match queue.lock().unwrap().next() {
+                            Some(item) => item,
+                            None => break,
+                        };
+                        let result = self.install_and_run(index, &commit);
+                        if args.keep_going {
+                            if let Err(err) = result {
+                                report_warn(&err.context(format!(
+                                    "skipping toolchain `{}` due to a failure",
+                                    commit
+                                )));
+                                failed.store(true, Ordering::SeqCst);
+                            }
+                        } else if let Err(err) = result {
+                            first_error.lock().unwrap().get_or_insert(err);
+                        }
+                    }
+                });
             }
+        });
+
+        print_check_results(
+            &args.check_command,
+            self.check_results.into_inner().unwrap(),
+        );
+
+        if let Some(err) = first_error.into_inner().unwrap() {
+            return Err(err);
+        }
+
+        let mut toolchains = self.installed_toolchains.into_inner().unwrap();
+        toolchains.sort_by(|a, b| a.0.cmp(&b.0));
+        if let (Some(path), false) = (&args.sbom, args.dry_run) {
+            write_sbom(path, &toolchains)?;
+        }
+        if let (Some(path), false, false) = (&args.lock, args.locked, args.dry_run) {
+            let toolchains = toolchains
+                .into_iter()
+                .map(|(name, metadata)| LockedToolchain::from_metadata(&name, metadata))
+                .collect::<Result<_, _>>()?;
+            Lockfile { toolchains }.write(path)?;
+            eprintln!("the toolchains are locked in `{}`", path.display());
+        }
+
+        if !policy.is_empty() && !args.dry_run {
+            let removed = prune(&self.toolchains_path, policy, false, &session.progress)?;
+            record_removals(&session.cache, &self.toolchains_path, &args.hooks, removed)?;
+        }
+        if !cache_limits.is_empty() && !args.dry_run {
+            session
+                .cache
+                .prune(cache_limits, false, &session.progress)?;
+        }
+
+        // Return the error only after downloading the toolchains that didn't fail
+        if failed.into_inner() {
+            Err(Error::msg("failed to download some toolchains"))
         } else {
-            result?;
+            Ok(())
+        }
+    }
+
+    /// Installs the toolchain of every new master commit until cancelled.
+    fn watch(&self, policy: &RetentionPolicy) -> Result<(), Error> {
+        let session = self.session;
+        let args = &session.args;
+        let github_token = args.github_token()?;
+        let mut last_commit = None;
+        loop {
+            match fetch_master_commit(
+                &session.client,
+                github_token.as_deref(),
+                args.wait_for_rate_limit,
+                !args.no_git,
+                &session.progress,
+            ) {
+                Ok(commit) if last_commit.as_ref() != Some(&commit) => {
+                    let result = self.install_and_run(0, &commit);
+                    // every commit is checked on its own, so its results are
+                    // reported before waiting for the next one.
+                    print_check_results(
+                        &args.check_command,
+                        take(&mut *self.check_results.lock().unwrap()),
+                    );
+                    match result {
+                        Ok(()) => {
+                            last_commit = Some(commit);
+                            if !policy.is_empty() && !args.dry_run {
+                                let removed =
+                                    prune(&self.toolchains_path, policy, false, &session.progress)?;
+                                record_removals(
+                                    &session.cache,
+                                    &self.toolchains_path,
+                                    &args.hooks,
+                                    removed,
+                                )?;
+                            }
+                        }
+                        // the artifacts may not be uploaded yet, so try again
+                        // at the next poll.
+                        Err(err) if !session.progress.is_cancelled() => report_warn(&err.context(
+                            format!("failed to install toolchain `{}`, retrying later", commit),
+                        )),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if !session.progress.is_cancelled() => {
+                    report_warn(&err.context("failed to fetch the master commit"))
+                }
+                Err(err) => return Err(err),
+            }
+
+            eprintln!(
+                "waiting {} for the next commit...",
+                humantime::format_duration(args.interval)
+            );
+            let deadline = Instant::now() + args.interval;
+            while Instant::now() < deadline {
+                if session.progress.is_cancelled() {
+                    return Err(InstallError::Cancelled.into());
+                }
+                thread::sleep(
+                    Duration::from_secs(1).min(deadline.saturating_duration_since(Instant::now())),
+                );
+            }
         }
     }
+}
+
+fn run_bisect_command(
+    installer: &Installer<'_>,
+    start: String,
+    end: String,
+    test_command: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    ensure!(
+        args.name.is_none(),
+        "name argument cannot be provided when bisecting"
+    );
+    ensure!(!args.dry_run, "cannot bisect in dry run mode");
+    ensure!(!args.both, "cannot bisect both the normal and alt builds");
+    for commit in &[&start, &end] {
+        ensure!(
+            is_full_commit(commit),
+            "`{}` is not a full 40-digit commit hash",
+            commit
+        );
+    }
+    let github_token = args.github_token()?.or_else(find_github_token);
+    let mut candidates = fetch_bors_commits(
+        &session.client,
+        github_token.as_deref(),
+        &start,
+        &end,
+        args.wait_for_rate_limit,
+        &session.progress,
+    )?;
+    candidates.insert(0, start);
+    let first_bad = bisect(
+        &candidates,
+        test_command,
+        &installer.toolchains_path,
+        |commit| {
+            installer
+                .install(commit, args.alt, installer.host(), None)
+                .map(|(name, ..)| name)
+        },
+    )?;
+    eprintln!("the first bad commit is:");
+    println!("{}", first_bad);
+    Ok(())
+}
+
+fn run_compare_command(installer: &Installer<'_>, a: &str, b: &str) -> Result<(), Error> {
+    let args = &installer.session.args;
+    ensure!(
+        args.name.is_none(),
+        "name argument cannot be provided when comparing"
+    );
+    ensure!(!args.dry_run, "cannot compare in dry run mode");
+    ensure!(!args.both, "cannot compare both the normal and alt builds");
+    let a = resolve_commit(&installer.toolchains_path, a)?;
+    let b = resolve_commit(&installer.toolchains_path, b)?;
+    let (name_a, channel, _) = installer.install(&a, args.alt, installer.host(), None)?;
+    let (name_b, ..) = installer.install(&b, args.alt, installer.host(), channel.as_deref())?;
+    compare(&installer.toolchains_path, &name_a, &name_b)
+}
 
-    // Return the error only after downloading the toolchains that didn't fail
-    if failed {
-        Err(Error::msg("failed to download some toolchains"))
+fn run_run_command(
+    installer: &Installer<'_>,
+    commit: &str,
+    program: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    ensure!(!args.dry_run, "cannot run a command in dry run mode");
+    ensure!(!args.both, "cannot run with both the normal and alt builds");
+    let commit = if commit == "master" {
+        fetch_master_commit(
+            &session.client,
+            args.github_token()?.as_deref(),
+            args.wait_for_rate_limit,
+            !args.no_git,
+            &session.progress,
+        )?
     } else {
-        Ok(())
+        resolve_commit(&installer.toolchains_path, commit)?
+    };
+    let (name, ..) = installer.install(&commit, args.alt, installer.host(), None)?;
+    eprintln!(
+        "running `{}` with toolchain `{}`...",
+        program.join(" "),
+        name
+    );
+    exec_with_toolchain(program, &installer.toolchains_path, &name)
+}
+
+fn run_bench_command(
+    installer: &Installer<'_>,
+    crate_path: &Path,
+    time_passes: bool,
+    format: BenchFormat,
+    commits: &[String],
+) -> Result<(), Error> {
+    let args = &installer.session.args;
+    ensure!(
+        args.name.is_none(),
+        "name argument cannot be provided when benchmarking"
+    );
+    ensure!(!args.dry_run, "cannot benchmark in dry run mode");
+    ensure!(
+        !args.both,
+        "cannot benchmark both the normal and alt builds"
+    );
+    for commit in commits {
+        ensure!(
+            is_full_commit(commit),
+            "`{}` is not a full 40-digit commit hash",
+            commit
+        );
+    }
+    let mut results = Vec::new();
+    for commit in commits {
+        let (name, ..) = installer.install(commit, args.alt, installer.host(), None)?;
+        results.push(bench_crate(
+            crate_path,
+            time_passes,
+            &installer.toolchains_path,
+            commit,
+            &name,
+        )?);
+    }
+    print!("{}", format_results(&results, format)?);
+    Ok(())
+}
+
+fn run_add_target_command(
+    installer: &Installer<'_>,
+    toolchain: &str,
+    targets: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    let toolchains_path = &installer.toolchains_path;
+    let toolchain = &resolve_toolchain(toolchains_path, toolchain)?;
+    let targets = expand_targets(targets, toolchains_path)?;
+    let added = add_targets(
+        &session.client,
+        session.dry_run_client(),
+        &session.cache,
+        &installer.prefix_of(args.alt),
+        toolchains_path,
+        toolchain,
+        &targets,
+        &session.progress,
+    )?;
+    if added.is_empty() {
+        eprintln!(
+            "toolchain `{}` already has rust-std for every given target",
+            toolchain
+        );
+    } else if session.system_dir.is_some() && !args.dry_run {
+        share_toolchain(&toolchains_path.join(toolchain), args.group_writable)?;
+    }
+    Ok(())
+}
+
+fn run_add_component_command(
+    installer: &Installer<'_>,
+    toolchain: &str,
+    components: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    let toolchains_path = &installer.toolchains_path;
+    let toolchain = &resolve_toolchain(toolchains_path, toolchain)?;
+    let added = add_components(
+        &session.client,
+        session.dry_run_client(),
+        &session.cache,
+        &installer.prefix_of(args.alt),
+        toolchains_path,
+        toolchain,
+        components,
+        &session.progress,
+    )?;
+    if added.is_empty() {
+        eprintln!(
+            "toolchain `{}` already has every given component",
+            toolchain
+        );
+    } else if session.system_dir.is_some() && !args.dry_run {
+        share_toolchain(&toolchains_path.join(toolchain), args.group_writable)?;
+    }
+    Ok(())
+}
+
+fn run_remove_target_command(
+    installer: &Installer<'_>,
+    toolchain: &str,
+    targets: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let toolchains_path = &installer.toolchains_path;
+    let toolchain = &resolve_toolchain(toolchains_path, toolchain)?;
+    let targets = expand_targets(targets, toolchains_path)?;
+    let removed = remove_targets(
+        toolchains_path,
+        toolchain,
+        &targets,
+        session.args.dry_run,
+        &session.progress,
+    )?;
+    if removed.is_empty() {
+        eprintln!(
+            "toolchain `{}` has no rust-std for any given target",
+            toolchain
+        );
+    }
+    Ok(())
+}
+
+fn run_remove_component_command(
+    installer: &Installer<'_>,
+    toolchain: &str,
+    components: &[String],
+) -> Result<(), Error> {
+    let session = installer.session;
+    let toolchains_path = &installer.toolchains_path;
+    let toolchain = &resolve_toolchain(toolchains_path, toolchain)?;
+    let removed = remove_components(
+        toolchains_path,
+        toolchain,
+        components,
+        session.args.dry_run,
+        &session.progress,
+    )?;
+    if removed.is_empty() {
+        eprintln!("toolchain `{}` has none of the given components", toolchain);
+    }
+    Ok(())
+}
+
+fn run_which_nightly_command(session: &Session, commit: &str) -> Result<(), Error> {
+    let args = &session.args;
+    let github_token = args.github_token()?.or_else(find_github_token);
+    let info = fetch_commit_info(
+        &session.client,
+        github_token.as_deref(),
+        commit,
+        args.wait_for_rate_limit,
+        &session.progress,
+    )?;
+    let Some(date) = info.date else {
+        bail!("the date of commit `{}` is unknown", commit);
+    };
+    let dist_server = args.dist_server.as_deref().unwrap_or(DEFAULT_DIST_SERVER);
+    // no nightly before the commit can contain it.
+    let mut date = date.date_naive();
+    let mut last_nightly_commit = None;
+    while date <= Utc::now().date_naive() {
+        let day = date.format("%Y-%m-%d").to_string();
+        date = date.succ_opt().context("the date is out of range")?;
+        let Some(nightly_commit) =
+            fetch_nightly_commit(&session.client, dist_server, &day, &session.progress)?
+        else {
+            continue;
+        };
+        // a nightly often has the same commit as the day before.
+        if last_nightly_commit.as_ref() == Some(&nightly_commit) {
+            continue;
+        }
+        eprintln!("checking nightly-{} ({})...", day, nightly_commit);
+        let contains = contains_commit(
+            &session.client,
+            github_token.as_deref(),
+            &nightly_commit,
+            &info.sha,
+            args.wait_for_rate_limit,
+            &session.progress,
+        )?;
+        if contains {
+            println!("nightly-{}", day);
+            return Ok(());
+        }
+        last_nightly_commit = Some(nightly_commit);
+    }
+    bail!("no nightly release contains commit `{}` yet", commit);
+}
+
+fn run_list_available_command(installer: &Installer<'_>, count: usize) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    ensure!(!args.both, "cannot list both the normal and alt builds");
+    let commits = fetch_recent_bors_commits(
+        &session.client,
+        args.github_token()?.or_else(find_github_token).as_deref(),
+        count,
+        args.wait_for_rate_limit,
+        &session.progress,
+    )?;
+    let prefix = installer.prefix_of(args.alt);
+    for commit in commits {
+        let channel = probe_channel(&session.client, &prefix, &commit.sha, &session.progress)?;
+        if let Some(channel) = channel {
+            session.cache.store_channel(&commit.sha, channel);
+        }
+        println!(
+            "{} {:<9} {}",
+            commit.sha,
+            channel.unwrap_or("expired"),
+            commit.subject
+        );
+    }
+    Ok(())
+}
+
+fn run_prefetch_command(installer: &Installer<'_>, count: usize) -> Result<(), Error> {
+    let session = installer.session;
+    let args = &session.args;
+    ensure!(!args.dry_run, "cannot prefetch in dry run mode");
+    ensure!(!args.both, "cannot prefetch both the normal and alt builds");
+    let commits = fetch_recent_bors_commits(
+        &session.client,
+        args.github_token()?.or_else(find_github_token).as_deref(),
+        count,
+        args.wait_for_rate_limit,
+        &session.progress,
+    )?;
+    let prefix = installer.prefix_of(args.alt);
+    for commit in commits {
+        let toolchain = Toolchain {
+            commit: commit.sha,
+            alt: args.alt,
+            host_target: installer.host().to_owned(),
+            rust_std_targets: installer.std_targets_of(installer.host()),
+            components: installer.components.clone(),
+            component_commits: BTreeMap::new(),
+            component_urls: BTreeMap::new(),
+            artifact_hashes: BTreeMap::new(),
+            file_modes: FileModes::Archive,
+            name: String::new(),
+            dest: PathBuf::new(),
+        };
+        let result = prefetch_toolchain(
+            &session.client,
+            &session.cache,
+            &prefix,
+            &toolchain,
+            args.channel.as_deref(),
+            &session.progress,
+        );
+        match result {
+            Ok(_) => eprintln!("toolchain `{}` is cached", toolchain.commit),
+            // old commits may have expired, which should not stop the rest.
+            Err(err) if args.keep_going => report_warn(&Error::from(err).context(format!(
+                "skipping toolchain `{}` due to a failure",
+                toolchain.commit
+            ))),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Asks which of the latest merge commits with artifacts to install, returning
+/// `None` if the user picks none.
+fn run_pick_command(
+    installer: &Installer<'_>,
+    count: usize,
+    commits: &[String],
+) -> Result<Option<String>, Error> {
+    let session = installer.session;
+    let args = &session.args;
+    ensure!(!args.both, "cannot pick both the normal and alt builds");
+    ensure!(
+        commits.is_empty() && !args.watch,
+        "commits cannot be provided to `pick`"
+    );
+    let merge_commits = fetch_recent_bors_commits(
+        &session.client,
+        args.github_token()?.or_else(find_github_token).as_deref(),
+        count,
+        args.wait_for_rate_limit,
+        &session.progress,
+    )?;
+    let prefix = installer.prefix_of(args.alt);
+    eprintln!("checking which commits still have artifacts...");
+    let candidates = merge_commits
+        .into_iter()
+        .map(|commit| {
+            let channel = probe_channel(&session.client, &prefix, &commit.sha, &session.progress)?;
+            if let Some(channel) = channel {
+                session.cache.store_channel(&commit.sha, channel);
+            }
+            Ok(Candidate { commit, channel })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(pick_commit(&candidates)?.map(str::to_owned))
+}
+
+/// Prints the author, the date and the subject of `commits`.
+fn print_commit_infos(session: &Session, commits: &[String]) -> Result<(), Error> {
+    let args = &session.args;
+    let github_token = args.github_token()?.or_else(find_github_token);
+    for commit in commits {
+        let info = fetch_commit_info(
+            &session.client,
+            github_token.as_deref(),
+            commit,
+            args.wait_for_rate_limit,
+            &session.progress,
+        );
+        match info {
+            Ok(info) => eprintln!(
+                "commit `{}` by {} on {}: {}{}",
+                info.sha,
+                info.author.as_deref().unwrap_or("an unknown author"),
+                info.date.map_or_else(
+                    || "an unknown date".to_owned(),
+                    |date| date.format("%Y-%m-%d %H:%M UTC").to_string()
+                ),
+                info.subject,
+                if info.is_bors_merge {
+                    ""
+                } else {
+                    " (not a merge of bors, so CI has not built it)"
+                }
+            ),
+            // the lookup is informational and should not stop the install.
+            Err(err) => report_warn(
+                &Error::from(err).context(format!("failed to look up commit `{}`", commit)),
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Prints whether `cargo <check_command>` passed with each toolchain, in the
+/// order the commits were given.
+fn print_check_results(check_command: &str, mut check_results: Vec<(usize, String, bool)>) {
+    if check_results.is_empty() {
+        return;
+    }
+    check_results.sort();
+    eprintln!("summary of `cargo {}`:", check_command);
+    for (_, name, passed) in check_results {
+        println!("{}\t{}", if passed { "pass" } else { "FAIL" }, name);
+    }
+}
+
+/// Formats the archive `sizes` as a table with their total, marking the
+/// archives which would not be downloaded.
+fn size_table(sizes: &[ArtifactSize]) -> String {
+    let component_width = sizes.iter().map(|s| s.component.len()).max().unwrap_or(0);
+    let target_width = sizes.iter().map(|s| s.target.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for size in sizes {
+        table += &format!(
+            "  {:<cw$}  {:<tw$}  {:>10}{}\n",
+            size.component,
+            size.target,
+            format_size(size.size),
+            if size.download { "" } else { "  (cached)" },
+            cw = component_width,
+            tw = target_width
+        );
+    }
+    table += &format!(
+        "  {:<w$}  {:>10}",
+        "total",
+        format_size(sizes.iter().map(|s| s.size).sum()),
+        w = component_width + 2 + target_width
+    );
+    table
+}
+
+/// Fails if the archives of the toolchain `name` which would be downloaded
+/// are larger than `max` bytes in total, listing them from the largest.
+fn check_download_size(name: &str, sizes: &[ArtifactSize], max: u64) -> Result<(), Error> {
+    let mut downloads = sizes
+        .iter()
+        .filter(|size| size.download)
+        .cloned()
+        .collect::<Vec<_>>();
+    let total = downloads.iter().map(|size| size.size).sum::<u64>();
+    if total > max {
+        downloads.sort_by_key(|size| Reverse(size.size));
+        bail!(
+            "toolchain `{}` would download {}, more than the {} allowed by \
+             `--max-download-size`:\n{}",
+            name,
+            format_size(total),
+            format_size(max),
+            size_table(&downloads)
+        );
+    }
+    Ok(())
+}
+
+/// Finds the installed toolchain `query` refers to, telling which one it is if
+/// the query is abbreviated.
+fn resolve_toolchain(toolchains_path: &Path, query: &str) -> Result<String, Error> {
+    let name = find_toolchain(toolchains_path, query)?;
+    if name != query {
+        eprintln!("using toolchain `{}`", name);
+    }
+    Ok(name)
+}
+
+/// Returns `commit` if it is a full commit hash, or the commit of the
+/// installed toolchain it refers to otherwise.
+fn resolve_commit(toolchains_path: &Path, commit: &str) -> Result<String, Error> {
+    if is_full_commit(commit) {
+        return Ok(commit.to_owned());
+    }
+    let name = resolve_toolchain(toolchains_path, commit)?;
+    match InstallMetadata::read(&toolchains_path.join(&name))? {
+        Some(metadata) => Ok(metadata.commit),
+        None => bail!(
+            "`{}` is neither a full 40-digit commit hash nor a toolchain installed by this tool",
+            commit
+        ),
+    }
+}
+
+fn parse_component_commit(s: &str) -> Result<(String, String), Error> {
+    match s.split_once('=') {
+        Some((component, commit)) => Ok((component.to_owned(), commit.to_owned())),
+        None => bail!("expected `component=commit`, found `{}`", s),
+    }
+}
+
+fn parse_component_url(s: &str) -> Result<(String, String), Error> {
+    match s.split_once('=') {
+        Some((component, url)) => Ok((component.to_owned(), url.to_owned())),
+        None => bail!("expected `component=url`, found `{}`", s),
+    }
+}
+
+/// Turns a path into a `file://` URL, relative to `base`, and leaves other
+/// URLs alone.
+fn absolute_url(url: &str, base: &Path) -> Result<String, Error> {
+    if Url::parse(url).is_ok_and(|url| url.cannot_be_a_base() || url.has_host()) {
+        return Ok(url.to_owned());
+    }
+    let path = base.join(url.strip_prefix("file://").unwrap_or(url));
+    Url::from_file_path(&path)
+        .map(String::from)
+        .map_err(|()| anyhow!("`{}` is not a valid path", path.display()))
+}
+
+/// Moves the rust-dev component out of the toolchain at `toolchain_path`
+/// into `dest`.
+fn move_rust_dev(toolchain_path: &Path, dest: &Path, force: bool) -> Result<(), Error> {
+    if dest.exists() {
+        ensure!(force, "`{}` already exists", dest.display());
+        remove_dir_all(dest)?;
+    }
+    if let Some(parent) = dest.parent() {
+        create_dir_all(parent)?;
+    }
+    rename(toolchain_path.join(RUST_DEV_DIR), dest)
+        .with_context(|| format!("failed to move rust-dev to `{}`", dest.display()))?;
+    eprintln!("moved rust-dev to `{}`", dest.display());
+    Ok(())
+}
+
+/// Returns `<destdir>/<prefix>`, even if `prefix` is absolute.
+fn staged_path(destdir: &Path, prefix: &Path) -> PathBuf {
+    prefix
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .fold(destdir.to_owned(), |dest, c| dest.join(c))
+}
+
+/// Moves the toolchain at `toolchain_path` to `<destdir>/<prefix>`, the layout a
+/// package installing it into `prefix` is built from.
+fn move_to_destdir(
+    toolchain_path: &Path,
+    destdir: &Path,
+    prefix: &Path,
+    force: bool,
+) -> Result<(), Error> {
+    // the metadata only describes toolchains in a rustup home.
+    remove_file(toolchain_path.join(METADATA_FILE_NAME))?;
+    let dest = staged_path(destdir, prefix);
+    if dest.exists() {
+        ensure!(force, "`{}` already exists", dest.display());
+        remove_dir_all(&dest)?;
+    }
+    if let Some(parent) = dest.parent() {
+        create_dir_all(parent)?;
+    }
+    rename(toolchain_path, &dest)
+        .with_context(|| format!("failed to move the toolchain to `{}`", dest.display()))?;
+    eprintln!("staged the toolchain in `{}`", dest.display());
+    Ok(())
+}
+
+fn record_history(cache: &Cache, entry: HistoryEntry) {
+    if let Err(err) = history::record(cache, &entry) {
+        report_warn(&Error::from(err).context("failed to record the history"));
+    }
+}
+
+/// Looks up the pull request merged by `commit` and records it in the
+/// metadata of the toolchain at `path`.
+fn record_pull_request(
+    client: &Client,
+    github_token: Option<&str>,
+    wait_for_rate_limit: bool,
+    commit: &str,
+    path: &Path,
+    progress: &dyn ProgressObserver,
+) -> Result<Option<PullRequest>, Error> {
+    let info = fetch_commit_info(client, github_token, commit, wait_for_rate_limit, progress)?;
+    let Some(pull_request) = info.pull_request else {
+        return Ok(None);
+    };
+    if let Some(mut metadata) = InstallMetadata::read(path)? {
+        metadata.pull_request = Some(pull_request.clone());
+        metadata.write(path)?;
+    }
+    Ok(Some(pull_request))
+}
+
+/// Records the toolchains `removed` from `toolchains_path` in the history and
+/// runs the post-uninstall hook for each of them.
+fn record_removals(
+    cache: &Cache,
+    toolchains_path: &Path,
+    hooks: &Hooks,
+    removed: Vec<(String, InstallMetadata)>,
+) -> Result<(), Error> {
+    for (name, metadata) in &removed {
+        record_history(
+            cache,
+            HistoryEntry {
+                time: Utc::now(),
+                action: Action::Uninstall,
+                name: name.clone(),
+                commit: Some(metadata.commit.clone()),
+                components: metadata.components.clone(),
+                targets: metadata.targets.clone(),
+                outcome: Outcome::Success,
+                error: None,
+                duration: Duration::default(),
+            },
+        );
+    }
+    if let Some(script) = &hooks.post_uninstall {
+        for (name, metadata) in &removed {
+            run_hook(
+                "post-uninstall",
+                script,
+                &HookContext {
+                    name,
+                    path: &toolchains_path.join(name),
+                    commit: &metadata.commit,
+                    host: &metadata.host,
+                    components: &metadata.components,
+                    targets: &metadata.targets,
+                },
+            )?;
+        }
     }
+    Ok(())
+}
+
+fn print_history(
+    cache: &Cache,
+    commit: Option<&str>,
+    since: Option<&str>,
+    limit: Option<usize>,
+    json: bool,
+) -> Result<(), Error> {
+    let since = since.map(parse_since).transpose()?;
+    let entries = history::read(cache)?
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| {
+            commit.is_none_or(|prefix| {
+                entry
+                    .commit
+                    .as_deref()
+                    .is_some_and(|c| c.starts_with(prefix))
+            })
+        })
+        .collect::<Vec<_>>();
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries[skip..])?);
+        return Ok(());
+    }
+    for entry in &entries[skip..] {
+        let action = match entry.action {
+            Action::Install => "install",
+            Action::Uninstall => "uninstall",
+        };
+        let outcome = match entry.outcome {
+            Outcome::Success => "success",
+            Outcome::AlreadyInstalled => "already installed",
+            Outcome::Failure => "failure",
+        };
+        print!(
+            "{}  {:<9}  {:<17}  {:>7.1}s  {}",
+            entry.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            action,
+            outcome,
+            entry.duration.as_secs_f64(),
+            entry.name
+        );
+        if !entry.components.is_empty() {
+            print!(" [{}]", entry.components.join(", "));
+        }
+        if !entry.targets.is_empty() {
+            print!(" (targets: {})", entry.targets.join(", "));
+        }
+        println!();
+        if let Some(error) = &entry.error {
+            println!("    {}", error);
+        }
+    }
+    Ok(())
+}
+
+/// Parses the `--since` of `history`, either a duration before now, a date
+/// in the local time zone, or an RFC 3339 time.
+fn parse_since(since: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(duration) = humantime::parse_duration(since) {
+        return Ok(Utc::now() - chrono::Duration::from_std(duration)?);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(since, "%Y-%m-%d").with_context(|| {
+        format!(
+            "invalid `--since` value `{}`, expected a duration like `2d` or a date like \
+             `2024-05-01`",
+            since
+        )
+    })?;
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let time = Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .ok_or_else(|| anyhow!("`{}` does not exist in the local time zone", since))?;
+    Ok(time.with_timezone(&Utc))
+}
+
+fn run_config_command(path: Option<&Path>, command: ConfigCommand) -> Result<(), Error> {
+    let mut file = ConfigFile::open(path)?;
+    match command {
+        ConfigCommand::List => {
+            for (key, value) in file.list() {
+                println!("{} = {}", key, value);
+            }
+        }
+        ConfigCommand::Get { key } => match file.get(&key)? {
+            Some(value) => println!("{}", value),
+            None => bail!("`{}` is not set in the config file", key),
+        },
+        ConfigCommand::Set { key, value } => {
+            file.set(&key, &value)?;
+            file.save()?;
+        }
+        ConfigCommand::Unset { key } => {
+            if file.unset(&key)? {
+                file.save()?;
+            } else {
+                eprintln!("`{}` is not set in the config file", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_keyring_command(command: KeyringCommand) -> Result<(), Error> {
+    match command {
+        KeyringCommand::Store => {
+            eprint!("GitHub token: ");
+            let mut token = String::new();
+            stdin().read_line(&mut token)?;
+            let token = token.trim();
+            ensure!(!token.is_empty(), "no GitHub token was given");
+            store_github_token(token)?;
+            eprintln!("the GitHub token is stored in the keyring");
+        }
+        KeyringCommand::Delete => {
+            if delete_github_token()? {
+                eprintln!("the GitHub token is deleted from the keyring");
+            } else {
+                eprintln!("no GitHub token is stored in the keyring");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Suggests the options of this tool which avoid `err`, since the errors of
+/// the library do not know about them.
+fn hint(err: &Error) -> Option<&'static str> {
+    err.chain().find_map(|cause| match cause.downcast_ref()? {
+        InstallError::NotWritable { .. } => Some(
+            "pass `--rustup-home` to install into a rustup home of your own, \
+             or ask the owner of the rustup home to install the toolchain",
+        ),
+        InstallError::RateLimitExceeded {
+            authenticated: false,
+            ..
+        } => Some(
+            "pass a GitHub token with `--github-token`, set `GITHUB_TOKEN`, \
+             or log in with `gh auth login` to raise the rate limit",
+        ),
+        InstallError::RateLimitExceeded {
+            authenticated: true,
+            ..
+        } => Some("pass `--wait-for-rate-limit` to wait until the rate limit is reset"),
+        _ => None,
+    })
 }
 
 fn report_error(err: &Error) {
-    eprintln!("{} {}", Red.bold().paint("error:"), err);
-    for cause in err.chain().skip(1) {
-        eprintln!("{} {}", Red.bold().paint("caused by:"), cause);
+    if is_github_actions() {
+        annotate("error", err);
+    } else {
+        eprintln!("{} {}", Red.bold().paint("error:"), err);
+        for cause in err.chain().skip(1) {
+            eprintln!("{} {}", Red.bold().paint("caused by:"), cause);
+        }
+    }
+    if let Some(hint) = hint(err) {
+        eprintln!("{} {}", Cyan.bold().paint("help:"), hint);
     }
     exit(1);
 }
 
 fn report_warn(warn: &Error) {
+    if is_github_actions() {
+        annotate("warning", warn);
+        if let Some(hint) = hint(warn) {
+            eprintln!("{} {}", Cyan.bold().paint("help:"), hint);
+        }
+        return;
+    }
     eprintln!("{} {}", Yellow.bold().paint("warn:"), warn);
     for cause in warn.chain().skip(1) {
         eprintln!("{} {}", Yellow.bold().paint("caused by:"), cause);
     }
+    if let Some(hint) = hint(warn) {
+        eprintln!("{} {}", Cyan.bold().paint("help:"), hint);
+    }
     eprintln!();
 }
 
 fn main() {
     if let Err(err) = run() {
+        // the command of the `run` subcommand has reported its own failure.
+        if let Some(CommandFailed { code }) = err.downcast_ref() {
+            exit(*code);
+        }
+        // `run` has removed the partial downloads already.
+        let cancelled = err
+            .chain()
+            .any(|cause| matches!(cause.downcast_ref(), Some(InstallError::Cancelled)));
+        if cancelled {
+            eprintln!("cancelled");
+            exit(130);
+        }
         report_error(&err);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ValueKind;
+    use structopt::clap::ErrorKind;
+
+    #[test]
+    fn windows_abis() {
+        assert_eq!(
+            other_windows_abi("x86_64-pc-windows-msvc").as_deref(),
+            Some("x86_64-pc-windows-gnu")
+        );
+        assert_eq!(
+            other_windows_abi("i686-pc-windows-gnu").as_deref(),
+            Some("i686-pc-windows-msvc")
+        );
+        assert_eq!(other_windows_abi("aarch64-pc-windows-gnullvm"), None);
+        assert_eq!(other_windows_abi("x86_64-unknown-linux-gnu"), None);
+    }
+
+    #[test]
+    fn negated_switches() {
+        let parse = |argv: &[&str]| {
+            let mut args = Args::from_matches(&app().get_matches_from(argv));
+            args.merge_configs(vec![Config {
+                force: Some(true),
+                no_git: Some(true),
+                ..Config::default()
+            }]);
+            args
+        };
+        let args = parse(&["rtim"]);
+        assert!(args.force && args.no_git);
+        let args = parse(&["rtim", "--no-force", "--git"]);
+        assert!(!args.force && !args.no_git);
+        assert!(!parse(&["rtim", "-f", "--no-force"]).force);
+        assert!(parse(&["rtim", "--no-force", "-f"]).force);
+    }
+
+    #[test]
+    fn config_keys_are_options() {
+        for (key, kind) in KEYS {
+            let flag = format!("--{}", key);
+            let mut argv = vec!["rtim", &flag];
+            if !matches!(kind, ValueKind::Flag) {
+                argv.push("1");
+            }
+            if let Err(err) = app().get_matches_from_safe(&argv) {
+                assert_ne!(err.kind, ErrorKind::UnknownArgument, "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn hints() {
+        let err = Error::new(InstallError::NotWritable {
+            path: PathBuf::from("/opt/rustup"),
+        })
+        .context("failed to install");
+        assert!(hint(&err).unwrap().contains("--rustup-home"));
+        let err = Error::new(InstallError::RateLimitExceeded {
+            reset: None,
+            authenticated: false,
+        });
+        assert!(hint(&err).unwrap().contains("--github-token"));
+        assert_eq!(hint(&anyhow!("failed")), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Renders a man page from the help of the CLI for distro packaging. clap 2
+//! cannot enumerate the arguments, so the generated help is parsed instead.
+
+use anyhow::{bail, Error};
+use structopt::clap::{App, ErrorKind};
+
+/// A parsed help message.
+struct Help {
+    about: Vec<String>,
+    usage: String,
+    /// The `FLAGS:`, `OPTIONS:`, `ARGS:` and `SUBCOMMANDS:` sections.
+    sections: Vec<(String, Vec<Entry>)>,
+}
+
+/// An argument or subcommand with its help.
+struct Entry {
+    spec: String,
+    help: String,
+}
+
+/// Renders the man page in roff of the CLI defined by `app`, including every
+/// subcommand.
+pub fn man_page(app: App<'_, '_>) -> Result<String, Error> {
+    let name = app.get_name().to_owned();
+    // a zero width disables wrapping the help.
+    let app = app.set_term_width(0);
+    let mut buf = Vec::new();
+    app.clone().write_long_help(&mut buf)?;
+    let help = parse_help(&String::from_utf8_lossy(&buf));
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{} {}\"\n.SH NAME\n{} \\- {}\n.SH SYNOPSIS\n{}\n",
+        escape(&name.to_uppercase()),
+        escape(&name),
+        env!("CARGO_PKG_VERSION"),
+        escape(&name),
+        escape(env!("CARGO_PKG_DESCRIPTION")),
+        escape(&help.usage),
+    );
+    for (title, entries) in &help.sections {
+        if title == "SUBCOMMANDS" {
+            continue;
+        }
+        page.push_str(&format!(".SH {}\n", title));
+        render_entries(&mut page, entries);
+    }
+
+    let subcommands = help
+        .sections
+        .iter()
+        .filter(|(title, _)| title == "SUBCOMMANDS")
+        .flat_map(|(_, entries)| entries)
+        .filter(|entry| entry.spec != "help");
+    let mut has_subcommands = false;
+    for subcommand in subcommands {
+        if !has_subcommands {
+            page.push_str(".SH SUBCOMMANDS\n");
+            has_subcommands = true;
+        }
+        let message = match app
+            .clone()
+            .get_matches_from_safe([&*name, &subcommand.spec, "--help"])
+        {
+            Err(e) if e.kind == ErrorKind::HelpDisplayed => e.message,
+            _ => bail!("failed to render the help of `{}`", subcommand.spec),
+        };
+        let help = parse_help(&message);
+        page.push_str(&format!(".SS {}\n", escape(&subcommand.spec)));
+        for line in &help.about {
+            page.push_str(&format!("{}\n", escape(line)));
+        }
+        page.push_str(&format!(".PP\n{}\n", escape(&help.usage)));
+        for (_, entries) in &help.sections {
+            render_entries(&mut page, entries);
+        }
+    }
+    Ok(page)
+}
+
+/// Renders each entry as a tagged paragraph, except `--help`, `--version` and
+/// the `help` subcommand which every man reader knows.
+fn render_entries(page: &mut String, entries: &[Entry]) {
+    for entry in entries {
+        if matches!(&*entry.spec, "-h, --help" | "-V, --version" | "help") {
+            continue;
+        }
+        page.push_str(&format!(
+            ".TP\n\\fB{}\\fR\n{}\n",
+            escape(&entry.spec),
+            escape(&entry.help)
+        ));
+    }
+}
+
+fn parse_help(text: &str) -> Help {
+    let mut help = Help {
+        about: Vec::new(),
+        usage: String::new(),
+        sections: Vec::new(),
+    };
+    // the first line is the name and the version.
+    let mut lines = text.lines().skip(1);
+    for line in lines.by_ref() {
+        if line == "USAGE:" {
+            break;
+        }
+        if !line.trim().is_empty() {
+            help.about.push(line.trim().to_owned());
+        }
+    }
+    if let Some(usage) = lines.by_ref().find(|line| !line.trim().is_empty()) {
+        help.usage = usage.trim().to_owned();
+    }
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !line.starts_with(' ') {
+            let title = trimmed.trim_end_matches(':').to_owned();
+            help.sections.push((title, Vec::new()));
+            continue;
+        }
+        let Some((title, entries)) = help.sections.last_mut() else {
+            continue;
+        };
+        let indent = line.len() - line.trim_start().len();
+        let is_entry = if title == "SUBCOMMANDS" || title == "ENVIRONMENT" {
+            indent == 4
+        } else {
+            trimmed.starts_with('-') || trimmed.starts_with('<')
+        };
+        match entries.last_mut() {
+            // the long help starts on the next line and is indented further.
+            Some(entry) if !is_entry => {
+                if !entry.help.is_empty() {
+                    entry.help.push(' ');
+                }
+                entry.help.push_str(&strip_env_values(trimmed));
+            }
+            _ => {
+                let (spec, text) = trimmed.split_once("  ").unwrap_or((trimmed, ""));
+                entries.push(Entry {
+                    spec: spec.to_owned(),
+                    help: strip_env_values(text.trim()),
+                });
+            }
+        }
+    }
+    help
+}
+
+/// Removes the values clap shows in `[env: NAME=value]`, which are those of
+/// the environment generating the page.
+fn strip_env_values(help: &str) -> String {
+    let mut result = String::new();
+    let mut rest = help;
+    while let Some(start) = rest.find("[env: ") {
+        let (before, env) = rest.split_at(start);
+        result.push_str(before);
+        match (env.find('='), env.find(']')) {
+            (Some(eq), Some(end)) if eq < end => {
+                result.push_str(&env[..eq]);
+                result.push(']');
+                rest = &env[end + 1..];
+            }
+            _ => {
+                result.push_str(env);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Escapes `text` for roff.
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
@@ -0,0 +1,131 @@
+//! Reads the release manifests of static.rust-lang.org, which list the URL and
+//! hash of every component of a nightly, beta or stable release.
+
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::download::response_url;
+use crate::error::Error;
+use crate::http::send;
+use crate::progress::{check_cancelled, ProgressObserver};
+
+/// The server storing the releases of Rust.
+pub static DEFAULT_DIST_SERVER: &str = "https://static.rust-lang.org";
+
+/// Returns the URL of the manifest of the `channel` release made on `date`
+/// (as `YYYY-MM-DD`), or of the latest release if `date` is `None`.
+pub fn manifest_url(dist_server: &str, channel: &str, date: Option<&str>) -> String {
+    match date {
+        Some(date) => format!(
+            "{}/dist/{}/channel-rust-{}.toml",
+            dist_server, date, channel
+        ),
+        None => format!("{}/dist/channel-rust-{}.toml", dist_server, channel),
+    }
+}
+
+/// Fetches the commit rustc of the nightly release made on `date` (as
+/// `YYYY-MM-DD`) was built from, or `None` if there is no nightly of that date,
+/// without downloading the whole manifest.
+pub fn fetch_nightly_commit(
+    client: &Client,
+    dist_server: &str,
+    date: &str,
+    observer: &dyn ProgressObserver,
+) -> Result<Option<String>, Error> {
+    check_cancelled(observer)?;
+    let url = format!(
+        "{}/dist/{}/channel-rust-nightly-git-commit-hash.txt",
+        dist_server, date
+    );
+    let response = send(client, client.get(&url), observer)?;
+    match response.status() {
+        StatusCode::OK => Ok(Some(response.text()?.trim().to_owned())),
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Ok(None),
+        status => Err(Error::UnexpectedStatus {
+            status,
+            url: response_url(&url, &response),
+        }),
+    }
+}
+
+/// A `channel-rust-*.toml` release manifest.
+#[derive(Deserialize, Debug)]
+pub struct Manifest {
+    /// The date of the release as `YYYY-MM-DD`.
+    pub date: String,
+    pub pkg: BTreeMap<String, Package>,
+    /// Maps the old names of components to their current names.
+    #[serde(default)]
+    pub renames: BTreeMap<String, Rename>,
+}
+
+/// A component listed in the manifest.
+#[derive(Deserialize, Debug)]
+pub struct Package {
+    pub version: String,
+    pub git_commit_hash: Option<String>,
+    /// The artifacts of the component by target, where the target-independent
+    /// components like `rust-src` use `*`.
+    pub target: BTreeMap<String, PackageTarget>,
+}
+
+/// The artifact of a component for a single target.
+#[derive(Deserialize, Debug)]
+pub struct PackageTarget {
+    pub available: bool,
+    pub xz_url: Option<String>,
+    /// The SHA-256 hash of the archive at `xz_url`.
+    pub xz_hash: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Rename {
+    pub to: String,
+}
+
+impl Manifest {
+    /// Downloads and parses the manifest at `url`, as returned by
+    /// [`manifest_url`].
+    pub fn fetch(
+        client: &Client,
+        url: &str,
+        observer: &dyn ProgressObserver,
+    ) -> Result<Self, Error> {
+        check_cancelled(observer)?;
+        let response = send(client, client.get(url), observer)?;
+        let status = response.status();
+        if status != StatusCode::OK {
+            return Err(Error::UnexpectedStatus {
+                status,
+                url: response_url(url, &response),
+            });
+        }
+        let text = response.text()?;
+        let manifest = toml::from_str(&text)
+            .with_context(|| format!("failed to parse the manifest {}", url))?;
+        Ok(manifest)
+    }
+
+    /// Returns the commit rustc of this release was built from.
+    pub fn commit(&self) -> Option<&str> {
+        self.pkg.get("rustc")?.git_commit_hash.as_deref()
+    }
+
+    /// Returns the URL and the SHA-256 hash of the `.tar.xz` archive of
+    /// `component` for `target`, if it is available.
+    pub fn artifact(&self, component: &str, target: &str) -> Option<(&str, &str)> {
+        let component = self.renames.get(component).map_or(component, |r| &r.to);
+        let package = self.pkg.get(component)?;
+        let artifact = package
+            .target
+            .get(target)
+            .or_else(|| package.target.get("*"))
+            .filter(|artifact| artifact.available)?;
+        Some((artifact.xz_url.as_deref()?, artifact.xz_hash.as_deref()?))
+    }
+}
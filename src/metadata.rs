@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::files::{installed_size, FileModes};
+use crate::resolve::PullRequest;
+
+/// The file written into every toolchain installed by this tool.
+pub static METADATA_FILE_NAME: &str = "rtim-install.toml";
+
+/// The JSON summary of the toolchain written next to [`METADATA_FILE_NAME`].
+pub static SUMMARY_FILE_NAME: &str = "install-summary.json";
+
+/// Describes how a toolchain was installed. Its presence also marks the
+/// toolchain as managed by this tool, so only such toolchains are pruned.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstallMetadata {
+    pub commit: String,
+    pub channel: String,
+    pub host: String,
+    pub alt: bool,
+    pub components: Vec<String>,
+    pub targets: Vec<String>,
+    pub installed_at: DateTime<Utc>,
+    /// How the permissions of the extracted files were set.
+    #[serde(default, skip_serializing_if = "FileModes::is_archive")]
+    pub file_modes: FileModes,
+    /// The components taken from other commits than `commit`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub component_commits: BTreeMap<String, String>,
+    /// The components downloaded from other URLs than the server.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub component_urls: BTreeMap<String, String>,
+    /// The pull request merged by `commit`, if it was looked up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_request: Option<PullRequest>,
+    /// The archives extracted into the toolchain, empty for toolchains
+    /// installed by older versions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<InstalledArtifact>,
+}
+
+/// Where an archive extracted into a toolchain was downloaded from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstalledArtifact {
+    pub component: String,
+    pub target: String,
+    pub commit: String,
+    pub channel: String,
+    pub url: String,
+    pub sha256: String,
+    /// The content of the `version` file of the archive, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// The summary of the toolchain written as JSON next to the metadata, for
+/// other tools to inspect the toolchain without the network.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Summary<'a> {
+    commit: &'a str,
+    channel: &'a str,
+    host: &'a str,
+    alt: bool,
+    installed_at: DateTime<Utc>,
+    pull_request: Option<&'a PullRequest>,
+    components: Vec<ComponentSummary<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ComponentSummary<'a> {
+    component: &'a str,
+    target: &'a str,
+    commit: &'a str,
+    version: Option<&'a str>,
+    url: &'a str,
+    sha256: &'a str,
+    /// The total size of the extracted files in bytes.
+    installed_size: Option<u64>,
+}
+
+impl InstallMetadata {
+    /// Writes the metadata into the toolchain directory, together with its
+    /// JSON summary in [`SUMMARY_FILE_NAME`].
+    pub fn write(&self, toolchain_path: &Path) -> Result<(), Error> {
+        let path = toolchain_path.join(METADATA_FILE_NAME);
+        write(&path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
+        self.write_summary(toolchain_path)
+    }
+
+    fn write_summary(&self, toolchain_path: &Path) -> Result<(), Error> {
+        let components = self
+            .artifacts
+            .iter()
+            .map(|artifact| ComponentSummary {
+                component: &artifact.component,
+                target: &artifact.target,
+                commit: &artifact.commit,
+                version: artifact.version.as_deref(),
+                url: &artifact.url,
+                sha256: &artifact.sha256,
+                installed_size: installed_size(
+                    toolchain_path,
+                    &artifact.component,
+                    &artifact.target,
+                ),
+            })
+            .collect();
+        let summary = Summary {
+            commit: &self.commit,
+            channel: &self.channel,
+            host: &self.host,
+            alt: self.alt,
+            installed_at: self.installed_at,
+            pull_request: self.pull_request.as_ref(),
+            components,
+        };
+        let path = toolchain_path.join(SUMMARY_FILE_NAME);
+        let content = serde_json::to_string_pretty(&summary)? + "\n";
+        write(&path, content).with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Reads the metadata from the toolchain directory, returning `None` if
+    /// the toolchain was not installed by this tool.
+    pub fn read(toolchain_path: &Path) -> Result<Option<Self>, Error> {
+        let path = toolchain_path.join(METADATA_FILE_NAME);
+        let content = match read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(Error::new(e).context(format!("failed to read `{}`", path.display())))
+            }
+        };
+        toml::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+}
@@ -0,0 +1,102 @@
+//! Asynchronous variants of the library functions, for applications running
+//! on their own tokio runtime.
+//!
+//! Downloading and extracting artifacts is mostly CPU- and disk-bound, so
+//! these functions run the blocking implementation on tokio's blocking thread
+//! pool instead of stalling the async executor.
+//!
+//! The blocking [`Client`] must be created outside of the async context, or
+//! inside [`tokio::task::spawn_blocking`].
+
+use std::path::PathBuf;
+
+use reqwest::blocking::Client;
+use tokio::task::spawn_blocking;
+
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::install::{InstalledToolchain, Toolchain};
+use crate::installer::ToolchainInstaller;
+use crate::progress::ProgressObserver;
+
+/// Runs `f` on the blocking thread pool.
+async fn run_blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    spawn_blocking(f)
+        .await
+        .map_err(|err| Error::Other(err.into()))?
+}
+
+/// Asynchronous version of [`crate::install_single_toolchain`]. If `dry_run`
+/// is set, nothing is downloaded or installed.
+#[allow(clippy::too_many_arguments)]
+pub async fn install_single_toolchain(
+    client: Client,
+    dry_run: bool,
+    cache: Cache,
+    prefix: String,
+    toolchains_path: PathBuf,
+    toolchain: Toolchain,
+    override_channel: Option<String>,
+    force: bool,
+    observer: impl ProgressObserver + Send + 'static,
+) -> Result<InstalledToolchain, Error> {
+    run_blocking(move || {
+        crate::install_single_toolchain(
+            &client,
+            if dry_run { None } else { Some(&client) },
+            &cache,
+            &prefix,
+            &toolchains_path,
+            &toolchain,
+            override_channel.as_deref(),
+            force,
+            &observer,
+        )
+    })
+    .await
+}
+
+/// Asynchronous version of [`crate::ToolchainInstaller::install_with_progress`].
+pub async fn install(
+    installer: ToolchainInstaller,
+    observer: impl ProgressObserver + Send + 'static,
+) -> Result<InstalledToolchain, Error> {
+    run_blocking(move || installer.install_with_progress(&observer)).await
+}
+
+/// Asynchronous version of [`crate::detect_channel`].
+pub async fn detect_channel(
+    client: Client,
+    prefix: String,
+    commit: String,
+    observer: impl ProgressObserver + Send + 'static,
+) -> Result<&'static str, Error> {
+    run_blocking(move || crate::detect_channel(&client, &prefix, &commit, &observer)).await
+}
+
+/// Asynchronous version of [`crate::resolve::fetch_master_commit_via_git`].
+pub async fn fetch_master_commit_via_git() -> Result<String, Error> {
+    run_blocking(crate::resolve::fetch_master_commit_via_git).await
+}
+
+/// Asynchronous version of [`crate::resolve::fetch_master_commit_via_http`].
+pub async fn fetch_master_commit_via_http(
+    client: Client,
+    github_token: Option<String>,
+    wait_for_rate_limit: bool,
+    observer: impl ProgressObserver + Send + 'static,
+) -> Result<String, Error> {
+    run_blocking(move || {
+        crate::resolve::fetch_master_commit_via_http(
+            &client,
+            github_token.as_deref(),
+            wait_for_rate_limit,
+            &observer,
+        )
+    })
+    .await
+}
@@ -0,0 +1,71 @@
+//! Lets the user pick one of the latest merge commits to install, for
+//! exploring the compilers of the last few days.
+
+use std::io::{stderr, stdin, Write};
+
+use anyhow::Error;
+use chrono::Local;
+
+use rustup_toolchain_install_master::resolve::MergeCommit;
+
+/// A merge commit offered by `pick`.
+pub struct Candidate {
+    pub commit: MergeCommit,
+    /// The channel of its artifacts, or `None` if they have expired.
+    pub channel: Option<&'static str>,
+}
+
+/// Lists the `candidates` numbered on stderr and asks which one to install,
+/// until one whose artifacts still exist is chosen. Returns `None` if the user
+/// quits instead.
+pub fn pick_commit(candidates: &[Candidate]) -> Result<Option<&str>, Error> {
+    for (i, candidate) in candidates.iter().enumerate() {
+        let date = candidate.commit.date.map_or_else(
+            || "?".repeat(16),
+            |date| {
+                date.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            },
+        );
+        let description = match &candidate.commit.pull_request {
+            Some(pr) => format!("#{} {}", pr.number, pr.title),
+            None => candidate.commit.subject.clone(),
+        };
+        eprintln!(
+            "{:>3}  {}  {:<9}  {}  {}",
+            i + 1,
+            date,
+            candidate.channel.unwrap_or("expired"),
+            &candidate.commit.sha[..10],
+            description
+        );
+    }
+    loop {
+        eprint!("install which commit? [1-{}, q to quit] ", candidates.len());
+        stderr().flush()?;
+        let mut answer = String::new();
+        if stdin().read_line(&mut answer)? == 0 {
+            eprintln!();
+            return Ok(None);
+        }
+        let answer = answer.trim();
+        if answer == "q" {
+            return Ok(None);
+        }
+        match answer
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| candidates.get(i.checked_sub(1)?))
+        {
+            Some(Candidate {
+                channel: Some(_),
+                commit,
+            }) => return Ok(Some(&commit.sha)),
+            Some(Candidate { channel: None, .. }) => {
+                eprintln!("the artifacts of this commit have expired, pick another one")
+            }
+            None => eprintln!("`{}` is not one of the listed numbers", answer),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Error;
+use chrono::{DateTime, Local};
+
+use crate::http::HttpExchange;
+
+/// A step of the installation reported to a [`ProgressObserver`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Phase<'a> {
+    /// Probing the server for the channel of `commit`.
+    DetectingChannel { commit: &'a str },
+    /// Downloading the artifact from `url`.
+    Downloading { url: &'a str },
+    /// Extracting the artifact of `url` from the cache instead of downloading.
+    UsingCache { url: &'a str },
+    /// The cached artifact of `url` does not match its hash, so it is
+    /// discarded and downloaded again.
+    DiscardingCache { url: &'a str },
+    /// The GitHub API rate limit is exceeded, waiting until it resets.
+    WaitingForRateLimit { until: DateTime<Local> },
+    /// Another process holds the lock at `path`, waiting until it finishes.
+    WaitingForLock { path: &'a Path },
+    /// The toolchain is installed in `path`.
+    Installed { name: &'a str, path: &'a Path },
+    /// The toolchain would be installed in `path` if this were not a dry run.
+    WouldInstall { name: &'a str, path: &'a Path },
+    /// rust-std for `targets` is added to the installed toolchain.
+    AddingTargets {
+        name: &'a str,
+        targets: &'a [String],
+        dry_run: bool,
+    },
+    /// `components` are added to the installed toolchain.
+    AddingComponents {
+        name: &'a str,
+        components: &'a [String],
+        dry_run: bool,
+    },
+    /// rust-std for `targets` is removed from the installed toolchain.
+    RemovingTargets {
+        name: &'a str,
+        targets: &'a [String],
+        dry_run: bool,
+    },
+    /// `components` are removed from the installed toolchain.
+    RemovingComponents {
+        name: &'a str,
+        components: &'a [String],
+        dry_run: bool,
+    },
+    /// A toolchain is removed according to the retention policy.
+    Pruning {
+        name: &'a str,
+        reason: &'a str,
+        dry_run: bool,
+    },
+    /// A temporary directory left behind by a crashed run is removed.
+    RemovingTempDir { path: &'a Path, dry_run: bool },
+    /// A download is removed from the cache according to its limits.
+    EvictingDownload {
+        path: &'a Path,
+        reason: &'a str,
+        dry_run: bool,
+    },
+    /// A toolchain is linked as `link_name`, for cargo-bisect-rustc or as an
+    /// alias.
+    Exporting {
+        name: &'a str,
+        link_name: &'a str,
+        dry_run: bool,
+    },
+}
+
+/// Receives progress notifications from the long running operations, so the
+/// application can render them however it wants.
+///
+/// All methods do nothing by default. `()` can be used when no progress needs
+/// to be reported.
+pub trait ProgressObserver {
+    /// Called when the installation moves on to another step.
+    fn phase(&self, phase: Phase<'_>) {
+        let _ = phase;
+    }
+
+    /// Called before reading an artifact of `length` bytes, if known.
+    fn download_started(&self, length: Option<u64>) {
+        let _ = length;
+    }
+
+    /// Called whenever `amount` more bytes of the artifact have been read.
+    fn bytes_read(&self, amount: u64) {
+        let _ = amount;
+    }
+
+    /// Called after the artifact has been read completely.
+    fn download_finished(&self) {}
+
+    /// Called after a file has been extracted to `path`.
+    fn file_extracted(&self, path: &Path) {
+        let _ = path;
+    }
+
+    /// Called after every request sent to a server, with the head of its
+    /// response.
+    fn http_exchange(&self, exchange: &HttpExchange<'_>) {
+        let _ = exchange;
+    }
+
+    /// Called with the content of the `version` file of the artifact, e.g.
+    /// `1.80.0-nightly (8d67f576b 2024-05-01)`, which is not extracted.
+    fn version_read(&self, version: &str) {
+        let _ = version;
+    }
+
+    /// Called for recoverable problems which do not stop the operation.
+    fn warning(&self, warning: &Error) {
+        let _ = warning;
+    }
+
+    /// Polled during the long running operations, which stop with
+    /// [`crate::Error::Cancelled`] as soon as this returns `true`.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl ProgressObserver for () {}
+
+/// Reports nothing, but cancels the operations once the flag is set.
+impl ProgressObserver for AtomicBool {
+    fn is_cancelled(&self) -> bool {
+        self.load(Ordering::SeqCst)
+    }
+}
+
+/// Returns [`crate::Error::Cancelled`] if the observer asks to cancel.
+pub(crate) fn check_cancelled(observer: &dyn ProgressObserver) -> Result<(), crate::Error> {
+    if observer.is_cancelled() {
+        Err(crate::Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// A reader which reports the number of bytes read to an observer.
+pub(crate) struct ProgressReader<'a, R> {
+    inner: R,
+    observer: &'a dyn ProgressObserver,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub(crate) fn new(inner: R, length: Option<u64>, observer: &'a dyn ProgressObserver) -> Self {
+        observer.download_started(length);
+        Self { inner, observer }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.observer.is_cancelled() {
+            return Err(io::Error::other("cancelled"));
+        }
+        let amount = self.inner.read(buf)?;
+        self.observer.bytes_read(amount as u64);
+        Ok(amount)
+    }
+}
@@ -0,0 +1,450 @@
+//! Resolves which commit of rust-lang/rust to install.
+
+use std::io::{ErrorKind, Read};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::download::{parse_header, response_url};
+use crate::error::Error;
+use crate::http::send;
+use crate::progress::{check_cancelled, Phase, ProgressObserver};
+
+/// How long `git ls-remote` may take before [`fetch_master_commit_via_git`]
+/// gives up, e.g. when outbound git traffic is silently dropped.
+pub static GIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fetches the commit hash of rust-lang/rust's master branch using
+/// `git ls-remote`, failing after [`GIT_TIMEOUT`].
+pub fn fetch_master_commit_via_git() -> Result<String, Error> {
+    let mut child = match Command::new("git")
+        .args([
+            "ls-remote",
+            "https://github.com/rust-lang/rust.git",
+            "master",
+        ])
+        // fail instead of asking for credentials.
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Error::MasterCommitUnavailable(
+                "git is not installed".to_owned(),
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let deadline = Instant::now() + GIT_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::MasterCommitUnavailable(format!(
+                "git ls-remote timed out after {} seconds",
+                GIT_TIMEOUT.as_secs()
+            )));
+        }
+        sleep(Duration::from_millis(50));
+    };
+    if !status.success() {
+        return Err(Error::MasterCommitUnavailable(
+            "git ls-remote exited with error".to_owned(),
+        ));
+    }
+    // the single line of output fits in the pipe, so it is read only now.
+    let mut stdout = Vec::new();
+    if let Some(mut pipe) = child.stdout.take() {
+        pipe.read_to_end(&mut stdout)?;
+    }
+    match stdout.get(..40) {
+        Some(hash) if hash.iter().all(u8::is_ascii_hexdigit) => {
+            Ok(String::from_utf8_lossy(hash).into_owned())
+        }
+        _ => Err(Error::MasterCommitUnavailable(
+            "git ls-remote does not return a commit".to_owned(),
+        )),
+    }
+}
+
+/// Sends a GET request to the GitHub API, optionally authenticated with
+/// `github_token`.
+///
+/// If the rate limit is exceeded and `wait_for_rate_limit` is set, waits until
+/// the limit is reset and tries again.
+fn github_get(
+    client: &Client,
+    url: &str,
+    media_type: &str,
+    github_token: Option<&str>,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Response, Error> {
+    loop {
+        check_cancelled(observer)?;
+        let mut req = client.get(url).header(ACCEPT, media_type);
+        if let Some(token) = github_token {
+            req = req.header(AUTHORIZATION, format!("token {}", token));
+        }
+        let response = send(client, req, observer)?;
+        match response.status() {
+            StatusCode::OK => return Ok(response),
+            status @ StatusCode::FORBIDDEN => {
+                let rate_limit =
+                    parse_header::<u32>(&response, "X-RateLimit-Remaining").unwrap_or(0);
+                if rate_limit != 0 {
+                    return Err(Error::UnexpectedStatus {
+                        status,
+                        url: response_url(url, &response),
+                    });
+                }
+                let reset = parse_header::<i64>(&response, "X-RateLimit-Reset")
+                    .and_then(|r| Local.timestamp_opt(r, 0).single());
+                if let (true, Some(reset)) = (wait_for_rate_limit, reset) {
+                    observer.phase(Phase::WaitingForRateLimit { until: reset });
+                    // add one extra second to make sure the limit has really been reset.
+                    while Local::now() <= reset + chrono::Duration::seconds(1) {
+                        check_cancelled(observer)?;
+                        sleep(Duration::from_secs(1));
+                    }
+                    continue;
+                }
+                return Err(Error::RateLimitExceeded {
+                    reset,
+                    authenticated: github_token.is_some(),
+                });
+            }
+            status => {
+                return Err(Error::UnexpectedStatus {
+                    status,
+                    url: response_url(url, &response),
+                })
+            }
+        }
+    }
+}
+
+/// Fetches the commit hash of rust-lang/rust's master branch from the GitHub
+/// API, optionally authenticated with `github_token`.
+///
+/// If the rate limit is exceeded and `wait_for_rate_limit` is set, waits until
+/// the limit is reset and tries again.
+pub fn fetch_master_commit_via_http(
+    client: &Client,
+    github_token: Option<&str>,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<String, Error> {
+    let master_commit = github_get(
+        client,
+        "https://api.github.com/repos/rust-lang/rust/commits/master",
+        "application/vnd.github.VERSION.sha",
+        github_token,
+        wait_for_rate_limit,
+        observer,
+    )?
+    .text()?;
+    if is_full_commit(&master_commit) {
+        Ok(master_commit)
+    } else {
+        Err(Error::MasterCommitUnavailable(format!(
+            "unable to parse `{}` as a commit",
+            master_commit
+        )))
+    }
+}
+
+/// The names bors commits the merges of rust-lang/rust as.
+static BORS_NAMES: &[&str] = &["bors", "rust-bors[bot]"];
+
+#[derive(Deserialize)]
+struct Comparison {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    commits: Vec<ListedCommit>,
+}
+
+#[derive(Deserialize)]
+struct ListedCommit {
+    sha: String,
+    commit: CommitDetails,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+    author: Option<CommitAuthor>,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct CommitAuthor {
+    name: String,
+    #[serde(default)]
+    date: Option<DateTime<Utc>>,
+}
+
+/// Fetches the merge commits of bors after `start` up to `end` from the GitHub
+/// API, oldest first. Only these commits have CI artifacts.
+pub fn fetch_bors_commits(
+    client: &Client,
+    github_token: Option<&str>,
+    start: &str,
+    end: &str,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<String>, Error> {
+    const PER_PAGE: usize = 100;
+    let mut bors_commits = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "https://api.github.com/repos/rust-lang/rust/compare/{}...{}?per_page={}&page={}",
+            start, end, PER_PAGE, page
+        );
+        let comparison = github_get(
+            client,
+            &url,
+            "application/vnd.github.v3+json",
+            github_token,
+            wait_for_rate_limit,
+            observer,
+        )?
+        .json::<Comparison>()?;
+        let count = comparison.commits.len();
+        bors_commits.extend(
+            comparison
+                .commits
+                .into_iter()
+                .filter(is_bors_commit)
+                .map(|c| c.sha),
+        );
+        if count < PER_PAGE {
+            break;
+        }
+    }
+    Ok(bors_commits)
+}
+
+/// The details of a commit, from [`fetch_commit_info`].
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author: Option<String>,
+    pub date: Option<DateTime<Utc>>,
+    /// The first line of the commit message.
+    pub subject: String,
+    /// Whether the commit is a merge of bors, since only these have CI
+    /// artifacts.
+    pub is_bors_merge: bool,
+    /// The pull request merged by bors in this commit.
+    pub pull_request: Option<PullRequest>,
+}
+
+/// A pull request of rust-lang/rust.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+}
+
+/// Fetches the author, date and subject of `commit` from the GitHub API,
+/// optionally authenticated with `github_token`.
+pub fn fetch_commit_info(
+    client: &Client,
+    github_token: Option<&str>,
+    commit: &str,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<CommitInfo, Error> {
+    let url = format!(
+        "https://api.github.com/repos/rust-lang/rust/commits/{}",
+        commit
+    );
+    let listed = github_get(
+        client,
+        &url,
+        "application/vnd.github.v3+json",
+        github_token,
+        wait_for_rate_limit,
+        observer,
+    )?
+    .json::<ListedCommit>()?;
+    let is_bors_merge = is_bors_commit(&listed);
+    let pull_request = if is_bors_merge {
+        parse_pull_request(&listed.commit.message)
+    } else {
+        None
+    };
+    let (author, date) = match listed.commit.author {
+        Some(author) => (Some(author.name), author.date),
+        None => (None, None),
+    };
+    Ok(CommitInfo {
+        subject: listed
+            .commit
+            .message
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_owned(),
+        sha: listed.sha,
+        author,
+        date,
+        is_bors_merge,
+        pull_request,
+    })
+}
+
+/// Parses the pull request from the message of a bors merge, which starts
+/// with `Auto merge of #<number> - <branch>, r=<reviewer>` followed by the
+/// title of the pull request.
+fn parse_pull_request(message: &str) -> Option<PullRequest> {
+    let mut lines = message.lines();
+    let rest = lines.next()?.strip_prefix("Auto merge of #")?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let number = rest[..end].parse().ok()?;
+    let title = lines
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .to_owned();
+    Some(PullRequest { number, title })
+}
+
+/// A merge commit of bors listed by [`fetch_recent_bors_commits`].
+#[derive(Debug, Clone)]
+pub struct MergeCommit {
+    pub sha: String,
+    /// The first line of the commit message.
+    pub subject: String,
+    /// When the commit was merged, if GitHub knows it.
+    pub date: Option<DateTime<Utc>>,
+    /// The pull request merged by the commit, parsed from its message.
+    pub pull_request: Option<PullRequest>,
+}
+
+/// Checks with the GitHub API whether `descendant` is `ancestor` or contains
+/// it in its history.
+pub fn contains_commit(
+    client: &Client,
+    github_token: Option<&str>,
+    descendant: &str,
+    ancestor: &str,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<bool, Error> {
+    let url = format!(
+        "https://api.github.com/repos/rust-lang/rust/compare/{}...{}?per_page=1",
+        ancestor, descendant
+    );
+    let comparison = github_get(
+        client,
+        &url,
+        "application/vnd.github.v3+json",
+        github_token,
+        wait_for_rate_limit,
+        observer,
+    )?
+    .json::<Comparison>()?;
+    Ok(comparison.status == "ahead" || comparison.status == "identical")
+}
+
+/// Fetches the latest `count` merge commits of bors on the master branch from
+/// the GitHub API, newest first.
+pub fn fetch_recent_bors_commits(
+    client: &Client,
+    github_token: Option<&str>,
+    count: usize,
+    wait_for_rate_limit: bool,
+    observer: &dyn ProgressObserver,
+) -> Result<Vec<MergeCommit>, Error> {
+    const PER_PAGE: usize = 100;
+    let mut bors_commits = Vec::new();
+    for page in 1.. {
+        if bors_commits.len() >= count {
+            break;
+        }
+        let url = format!(
+            "https://api.github.com/repos/rust-lang/rust/commits?sha=master&per_page={}&page={}",
+            PER_PAGE, page
+        );
+        let commits = github_get(
+            client,
+            &url,
+            "application/vnd.github.v3+json",
+            github_token,
+            wait_for_rate_limit,
+            observer,
+        )?
+        .json::<Vec<ListedCommit>>()?;
+        let fetched = commits.len();
+        bors_commits.extend(
+            commits
+                .into_iter()
+                .filter(is_bors_commit)
+                .map(|c| MergeCommit {
+                    subject: c.commit.message.lines().next().unwrap_or("").to_owned(),
+                    date: c.commit.author.and_then(|author| author.date),
+                    pull_request: parse_pull_request(&c.commit.message),
+                    sha: c.sha,
+                }),
+        );
+        if fetched < PER_PAGE {
+            break;
+        }
+    }
+    bors_commits.truncate(count);
+    Ok(bors_commits)
+}
+
+fn is_bors_commit(commit: &ListedCommit) -> bool {
+    commit
+        .commit
+        .author
+        .as_ref()
+        .is_some_and(|author| BORS_NAMES.contains(&&*author.name))
+}
+
+/// Checks whether `commit` is a full 40-digit commit hash.
+pub fn is_full_commit(commit: &str) -> bool {
+    commit.len() == 40 && commit.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bors_merges() {
+        let message = "Auto merge of #12345 - user:branch, r=reviewer\n\nFix the thing\n\nDetails";
+        let pr = parse_pull_request(message).unwrap();
+        assert_eq!(pr.number, 12345);
+        assert_eq!(pr.title, "Fix the thing");
+
+        let pr = parse_pull_request("Auto merge of #7").unwrap();
+        assert_eq!(pr.number, 7);
+        assert_eq!(pr.title, "");
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        assert!(parse_pull_request("Rollup merge of #12345 - user:branch").is_none());
+        assert!(parse_pull_request("Auto merge of #abc - user:branch").is_none());
+        assert!(parse_pull_request("").is_none());
+    }
+}
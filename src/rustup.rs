@@ -0,0 +1,57 @@
+//! Checks the version of the installed rustup, since very old releases lay out
+//! the rustup home differently and mishandle toolchains put into it by other
+//! tools.
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{ensure, Context, Error};
+
+/// The oldest rustup which picks up every toolchain in the `toolchains`
+/// directory, including relative symlinks to other toolchains.
+pub static MIN_RUSTUP_VERSION: [u64; 3] = [1, 20, 0];
+
+/// Returns the version of `rustup --version`, or `None` if rustup is not
+/// installed.
+pub fn rustup_version() -> Option<[u64; 3]> {
+    let output = Command::new("rustup").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // e.g. `rustup 1.27.1 (54dd3d00f 2024-04-24)`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next()?.strip_prefix("rustup ")?;
+    let mut parts = version
+        .split(['.', ' ', '-'])
+        .map(|part| part.parse::<u64>().ok());
+    Some([parts.next()??, parts.next()??, parts.next()??])
+}
+
+/// Returns whether `version` is older than [`MIN_RUSTUP_VERSION`].
+pub fn is_outdated(version: [u64; 3]) -> bool {
+    version < MIN_RUSTUP_VERSION
+}
+
+/// Formats a version like `1.20.0`.
+pub fn format_version(version: [u64; 3]) -> String {
+    format!("{}.{}.{}", version[0], version[1], version[2])
+}
+
+/// Links the toolchain at `path` as `name` with `rustup toolchain link`, which
+/// old rustups need instead of a symlink in the `toolchains` directory.
+pub fn link_toolchain(rustup_home: &Path, name: &str, path: &Path) -> Result<(), Error> {
+    let status = Command::new("rustup")
+        .args(["toolchain", "link", name])
+        .arg(path)
+        .env("RUSTUP_HOME", rustup_home)
+        .status()
+        .context("failed to run `rustup toolchain link`")?;
+    ensure!(
+        status.success(),
+        "`rustup toolchain link {} {}` exited with {}",
+        name,
+        path.display(),
+        status
+    );
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! Writes a CycloneDX document listing the archives the installed toolchains
+//! were assembled from, for compliance tooling.
+
+use std::fs::write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Error};
+use chrono::{SecondsFormat, Utc};
+use rustup_toolchain_install_master::InstallMetadata;
+use serde_json::{json, Value};
+
+/// The version of the CycloneDX specification the document follows.
+static SPEC_VERSION: &str = "1.5";
+
+/// Writes the document of the `toolchains`, given by their names and metadata,
+/// into `path`. Every toolchain is a component containing its archives, with
+/// their URLs, commits and SHA-256 hashes.
+///
+/// Fails if a toolchain was installed by an older version, which did not
+/// record its archives.
+pub fn write_sbom(path: &Path, toolchains: &[(String, InstallMetadata)]) -> Result<(), Error> {
+    let mut components = Vec::new();
+    for (name, metadata) in toolchains {
+        if metadata.artifacts.is_empty() {
+            return Err(anyhow!(
+                "the archives of toolchain `{}` were not recorded when it was installed, \
+                 reinstall it with `--force` to list them",
+                name
+            ));
+        }
+        components.push(toolchain_component(name, metadata));
+    }
+    let document = json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": SPEC_VERSION,
+        "version": 1,
+        "metadata": {
+            "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+        },
+        "components": components,
+    });
+    let content = serde_json::to_string_pretty(&document)?;
+    write(path, content + "\n").with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+fn toolchain_component(name: &str, metadata: &InstallMetadata) -> Value {
+    let artifacts = metadata
+        .artifacts
+        .iter()
+        .map(|artifact| {
+            json!({
+                "type": "application",
+                "bom-ref": format!("{}/{}-{}", name, artifact.component, artifact.target),
+                "name": artifact.component,
+                "version": artifact.commit,
+                "hashes": [{ "alg": "SHA-256", "content": artifact.sha256 }],
+                "externalReferences": [{ "type": "distribution", "url": artifact.url }],
+                "properties": [
+                    { "name": "rust:target", "value": artifact.target },
+                    { "name": "rust:channel", "value": artifact.channel },
+                ],
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "type": "application",
+        "bom-ref": name,
+        "name": name,
+        "version": metadata.commit,
+        "properties": [
+            { "name": "rust:host", "value": metadata.host },
+            { "name": "rust:channel", "value": metadata.channel },
+        ],
+        "components": artifacts,
+    })
+}
@@ -0,0 +1,85 @@
+//! Prints the environment pointing build systems like Bazel, Buck or Meson at
+//! an installed toolchain, since they run rustc directly instead of through
+//! the rustup proxies.
+
+use std::env::consts::EXE_SUFFIX;
+use std::env::var_os;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+/// The syntax of the lines printed by `--print-env`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Sh,
+    Fish,
+    Powershell,
+    Cmd,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "sh" => Ok(Self::Sh),
+            "fish" => Ok(Self::Fish),
+            "powershell" => Ok(Self::Powershell),
+            "cmd" => Ok(Self::Cmd),
+            _ => bail!(
+                "unknown shell `{}`, expected `sh`, `fish`, `powershell` or `cmd`",
+                s
+            ),
+        }
+    }
+}
+
+impl Shell {
+    /// Guesses the shell of the user from `$SHELL`, assuming PowerShell on
+    /// Windows where it is usually unset.
+    pub fn detect() -> Self {
+        match var_os("SHELL") {
+            Some(shell) if Path::new(&shell).file_stem().is_some_and(|s| s == "fish") => Self::Fish,
+            Some(_) => Self::Sh,
+            None if cfg!(windows) => Self::Powershell,
+            None => Self::Sh,
+        }
+    }
+
+    /// Formats a line setting the variable `name` to `value`.
+    fn export(self, name: &str, value: &str) -> String {
+        match self {
+            Self::Sh => format!("export {}='{}'", name, value.replace('\'', r"'\''")),
+            Self::Fish => format!(
+                "set -gx {} '{}'",
+                name,
+                value.replace('\\', r"\\").replace('\'', r"\'")
+            ),
+            Self::Powershell => format!("$env:{} = '{}'", name, value.replace('\'', "''")),
+            // cmd has no quoting inside `set "..."`, except for doubling `%`.
+            Self::Cmd => format!("set \"{}={}\"", name, value.replace('%', "%%")),
+        }
+    }
+}
+
+/// Prints the lines setting `RUSTC`, `RUSTDOC`, `CARGO` (if installed) and
+/// `RUST_SYSROOT` to the toolchain at `path`, which has installed the
+/// `components`.
+pub fn print_env(path: &Path, components: &[String], shell: Shell) {
+    let bin = path.join("bin");
+    let tool = |name: &str| {
+        bin.join(format!("{}{}", name, EXE_SUFFIX))
+            .display()
+            .to_string()
+    };
+    println!("{}", shell.export("RUSTC", &tool("rustc")));
+    println!("{}", shell.export("RUSTDOC", &tool("rustdoc")));
+    if components.iter().any(|c| c == "cargo") {
+        println!("{}", shell.export("CARGO", &tool("cargo")));
+    }
+    println!(
+        "{}",
+        shell.export("RUST_SYSROOT", &path.display().to_string())
+    );
+}
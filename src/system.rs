@@ -0,0 +1,82 @@
+//! Installs toolchains into a directory shared by every user of a machine,
+//! e.g. `/opt/rust-ci` on a build server, for `--system`.
+
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+
+/// Creates the shared directory `dir` with the `toolchains` and `tmp`
+/// directories inside, which every user can read, and the group can write to
+/// if `group_writable`.
+pub fn prepare_system_dir(dir: &Path, group_writable: bool) -> Result<(), Error> {
+    for path in [dir.to_owned(), dir.join("toolchains"), dir.join("tmp")] {
+        create_dir_all(&path).with_context(|| format!("failed to create `{}`", path.display()))?;
+        share(&path, group_writable)?;
+    }
+    Ok(())
+}
+
+/// Makes everything in the toolchain at `path` readable by every user, and
+/// writable by the group if `group_writable`, regardless of the umask of the
+/// user installing it.
+pub fn share_toolchain(path: &Path, group_writable: bool) -> Result<(), Error> {
+    share(path, group_writable)?;
+    if path.symlink_metadata()?.is_dir() {
+        for entry in path
+            .read_dir()
+            .with_context(|| format!("failed to list `{}`", path.display()))?
+        {
+            share_toolchain(&entry?.path(), group_writable)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints how each user links the toolchain `name` installed into `dir` into
+/// their own rustup.
+pub fn print_link_instructions(dir: &Path, name: &str) {
+    eprintln!(
+        "users can link the toolchain into their rustup with:\n    \
+         rustup toolchain link {} {}",
+        name,
+        dir.join("toolchains").join(name).display()
+    );
+}
+
+/// Adds the permissions to read `path`, and to search it if it is a directory
+/// or executable by the owner, for every user. With `group_writable`, the group
+/// may write too, and new entries of directories inherit their group.
+#[cfg(unix)]
+fn share(path: &Path, group_writable: bool) -> Result<(), Error> {
+    use std::fs::{set_permissions, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = path.symlink_metadata()?;
+    // the permissions of symlinks are never checked.
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+    let mode = metadata.permissions().mode();
+    let mut shared = mode | 0o444;
+    if metadata.is_dir() || mode & 0o100 != 0 {
+        shared |= 0o111;
+    }
+    if group_writable {
+        shared |= 0o020;
+        if metadata.is_dir() {
+            shared |= 0o2000;
+        }
+    }
+    if shared != mode {
+        set_permissions(path, Permissions::from_mode(shared))
+            .with_context(|| format!("failed to change the permissions of `{}`", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Files can be read by every user by default elsewhere.
+#[cfg(not(unix))]
+fn share(_: &Path, _: bool) -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,227 @@
+//! Named groups of targets which `--targets` accepts besides triples.
+
+use std::collections::BTreeSet;
+use std::env::{current_dir, var};
+use std::fs::{read_dir, read_to_string};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use toml::Value;
+
+/// The target groups and their triples, with `installed` handled separately.
+static TARGET_GROUPS: &[(&str, &[&str])] = &[
+    (
+        "tier1",
+        &[
+            "aarch64-apple-darwin",
+            "aarch64-pc-windows-msvc",
+            "aarch64-unknown-linux-gnu",
+            "i686-pc-windows-msvc",
+            "i686-unknown-linux-gnu",
+            "x86_64-pc-windows-gnu",
+            "x86_64-pc-windows-msvc",
+            "x86_64-unknown-linux-gnu",
+        ],
+    ),
+    (
+        "windows",
+        &[
+            "aarch64-pc-windows-msvc",
+            "i686-pc-windows-gnu",
+            "i686-pc-windows-msvc",
+            "x86_64-pc-windows-gnu",
+            "x86_64-pc-windows-msvc",
+        ],
+    ),
+    (
+        "wasm",
+        &["wasm32-unknown-unknown", "wasm32-wasip1", "wasm32-wasip2"],
+    ),
+    (
+        "embedded",
+        &[
+            "thumbv6m-none-eabi",
+            "thumbv7em-none-eabi",
+            "thumbv7em-none-eabihf",
+            "thumbv7m-none-eabi",
+            "thumbv8m.base-none-eabi",
+            "thumbv8m.main-none-eabi",
+            "thumbv8m.main-none-eabihf",
+        ],
+    ),
+];
+
+/// Replaces the groups in `targets` by their triples, and removes duplicates.
+///
+/// `installed` expands to every target which rust-std is installed for in any
+/// toolchain under `toolchains_path`.
+pub fn expand_targets(targets: &[String], toolchains_path: &Path) -> Result<Vec<String>, Error> {
+    let mut expanded = Vec::new();
+    for target in targets {
+        if target == "installed" {
+            expanded.extend(installed_targets(toolchains_path)?);
+        } else if let Some((_, triples)) = TARGET_GROUPS.iter().find(|(name, _)| name == target) {
+            expanded.extend(triples.iter().map(|triple| (*triple).to_owned()));
+        } else {
+            expanded.push(target.clone());
+        }
+    }
+    let mut seen = BTreeSet::new();
+    expanded.retain(|target| seen.insert(target.clone()));
+    Ok(expanded)
+}
+
+/// Lists the targets with a `lib/rustlib/<target>/lib` directory in any
+/// installed toolchain, which rust-std is installed for.
+fn installed_targets(toolchains_path: &Path) -> Result<Vec<String>, Error> {
+    let mut targets = Vec::new();
+    let toolchains = match read_dir(toolchains_path) {
+        Ok(toolchains) => toolchains,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(targets),
+        Err(e) => {
+            return Err(Error::new(e).context(format!(
+                "failed to list the toolchains in `{}`",
+                toolchains_path.display()
+            )))
+        }
+    };
+    for toolchain in toolchains {
+        let rustlib = toolchain?.path().join("lib").join("rustlib");
+        // toolchains under construction or linked toolchains may lack it.
+        let entries = match read_dir(&rustlib) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to list `{}`", rustlib.display()))?;
+            if entry.path().join("lib").is_dir() {
+                if let Some(target) = entry.file_name().to_str() {
+                    targets.push(target.to_owned());
+                }
+            }
+        }
+    }
+    targets.sort();
+    Ok(targets)
+}
+
+/// Returns the targets the project in the current directory is built for
+/// according to its cargo configs: `CARGO_BUILD_TARGET`, `build.target`, and
+/// the targets configured in a `[target.<triple>]` table.
+///
+/// Custom targets given as paths to JSON specs are skipped, since no rust-std
+/// is built for them.
+pub fn cargo_config_targets() -> Result<Vec<String>, Error> {
+    let mut targets = var("CARGO_BUILD_TARGET").into_iter().collect::<Vec<_>>();
+    let cwd = current_dir()?;
+    for dir in cwd.ancestors() {
+        // cargo reads the first of these in each directory.
+        for name in ["config", "config.toml"] {
+            let path = dir.join(".cargo").join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let content = read_to_string(&path)
+                .with_context(|| format!("failed to read `{}`", path.display()))?;
+            let config: Value = toml::from_str(&content)
+                .with_context(|| format!("failed to parse `{}`", path.display()))?;
+            targets.extend(configured_targets(&config));
+            break;
+        }
+    }
+    targets.retain(|target| !target.ends_with(".json"));
+    let mut seen = BTreeSet::new();
+    targets.retain(|target| seen.insert(target.clone()));
+    Ok(targets)
+}
+
+fn configured_targets(config: &Value) -> Vec<String> {
+    let mut targets = Vec::new();
+    match config.get("build").and_then(|build| build.get("target")) {
+        Some(Value::String(target)) => targets.push(target.clone()),
+        Some(Value::Array(list)) => {
+            targets.extend(list.iter().filter_map(Value::as_str).map(String::from))
+        }
+        _ => {}
+    }
+    if let Some(Value::Table(table)) = config.get("target") {
+        // `[target.'cfg(unix)']` applies to many targets and names none.
+        targets.extend(table.keys().filter(|key| !key.starts_with("cfg(")).cloned());
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn expands_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let targets = strings(&[
+            "x86_64-pc-windows-gnu",
+            "windows",
+            "thumbv8m.main-none-eabihf",
+        ]);
+        let expanded = expand_targets(&targets, dir.path()).unwrap();
+        assert_eq!(
+            expanded,
+            strings(&[
+                "x86_64-pc-windows-gnu",
+                "aarch64-pc-windows-msvc",
+                "i686-pc-windows-gnu",
+                "i686-pc-windows-msvc",
+                "x86_64-pc-windows-msvc",
+                "thumbv8m.main-none-eabihf",
+            ])
+        );
+        let expanded = expand_targets(&strings(&["embedded"]), dir.path()).unwrap();
+        assert!(expanded.contains(&"thumbv8m.main-none-eabihf".to_owned()));
+    }
+
+    #[test]
+    fn expands_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let rustlib = dir.path().join("master/lib/rustlib");
+        std::fs::create_dir_all(rustlib.join("wasm32-unknown-unknown/lib")).unwrap();
+        std::fs::create_dir_all(rustlib.join("etc")).unwrap();
+        let expanded = expand_targets(&strings(&["installed"]), dir.path()).unwrap();
+        assert_eq!(expanded, strings(&["wasm32-unknown-unknown"]));
+        let missing = dir.path().join("missing");
+        assert!(expand_targets(&strings(&["installed"]), &missing)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn cargo_config() {
+        let config: Value = toml::from_str(
+            r#"
+            build.target = ["wasm32-unknown-unknown", "thumbv7em-none-eabihf"]
+            [target.aarch64-unknown-linux-gnu]
+            linker = "cc"
+            [target.'cfg(unix)']
+            rustflags = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            configured_targets(&config),
+            strings(&[
+                "wasm32-unknown-unknown",
+                "thumbv7em-none-eabihf",
+                "aarch64-unknown-linux-gnu",
+            ])
+        );
+        let config: Value = toml::from_str("build.target = 'x86_64-unknown-linux-musl'").unwrap();
+        assert_eq!(
+            configured_targets(&config),
+            strings(&["x86_64-unknown-linux-musl"])
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{stderr, Stderr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use pbr::{ProgressBar, Units};
+use reqwest::header::{HeaderMap, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE};
+use rustup_toolchain_install_master::{HttpExchange, Phase, ProgressObserver};
+
+use crate::github_actions::{end_group, is_github_actions, start_group};
+use crate::report_warn;
+
+/// Reports progress on the terminal, drawing a progress bar for downloads.
+pub struct TerminalProgress {
+    show_progress_bar: bool,
+    /// Whether each download is folded into a group of the GitHub Actions log.
+    group_downloads: bool,
+    progress_bar: Mutex<Option<ProgressBar<Stderr>>>,
+    cancelled: Arc<AtomicBool>,
+    trace_http: bool,
+}
+
+impl TerminalProgress {
+    /// Creates the reporter. Progress bars should be disabled when several
+    /// toolchains are downloaded at the same time, as they would overwrite
+    /// each other, and so are the groups in GitHub Actions.
+    pub fn new(show_progress_bar: bool) -> Self {
+        Self {
+            show_progress_bar,
+            group_downloads: show_progress_bar && is_github_actions(),
+            progress_bar: Mutex::default(),
+            cancelled: Arc::default(),
+            trace_http: false,
+        }
+    }
+
+    /// Prints every HTTP request and the head of its response, except the
+    /// headers carrying credentials.
+    pub fn trace_http(mut self, trace_http: bool) -> Self {
+        self.trace_http = trace_http;
+        self
+    }
+
+    /// Returns the flag which cancels the operations once set, e.g. by the
+    /// Ctrl-C handler.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+}
+
+impl ProgressObserver for TerminalProgress {
+    fn phase(&self, phase: Phase<'_>) {
+        if self.group_downloads {
+            match phase {
+                Phase::Downloading { url } => {
+                    return start_group(&format!("downloading <{}>", url))
+                }
+                Phase::UsingCache { url } => {
+                    return start_group(&format!("using cached <{}>", url))
+                }
+                _ => end_group(),
+            }
+        }
+        match phase {
+            Phase::DetectingChannel { commit } => {
+                eprintln!("detecting the channel of the `{}` toolchain...", commit);
+            }
+            Phase::Downloading { url } => eprintln!("downloading <{}>...", url),
+            Phase::UsingCache { url } => eprintln!("using cached <{}>...", url),
+            Phase::DiscardingCache { url } => {
+                eprintln!("the cached <{}> is corrupted, downloading it again...", url)
+            }
+            Phase::WaitingForRateLimit { until } => eprintln!(
+                "GitHub API rate limit exceeded, waiting until {}...",
+                until.format("%Y-%m-%d %H:%M:%S %:z")
+            ),
+            Phase::WaitingForLock { path } => eprintln!(
+                "waiting for another process holding the lock `{}`...",
+                path.display()
+            ),
+            Phase::Installed { name, .. } => {
+                eprintln!("toolchain `{}` is successfully installed!", name);
+            }
+            Phase::WouldInstall { name, path } => eprintln!(
+                "toolchain `{}` will be installed to `{}` on real run",
+                name,
+                path.display()
+            ),
+            Phase::AddingTargets {
+                name,
+                targets,
+                dry_run: true,
+            } => eprintln!(
+                "rust-std for `{}` would be added to toolchain `{}`",
+                targets.join("`, `"),
+                name
+            ),
+            Phase::AddingTargets { name, targets, .. } => eprintln!(
+                "added rust-std for `{}` to toolchain `{}`",
+                targets.join("`, `"),
+                name
+            ),
+            Phase::AddingComponents {
+                name,
+                components,
+                dry_run: true,
+            } => eprintln!(
+                "`{}` would be added to toolchain `{}`",
+                components.join("`, `"),
+                name
+            ),
+            Phase::AddingComponents {
+                name, components, ..
+            } => eprintln!(
+                "added `{}` to toolchain `{}`",
+                components.join("`, `"),
+                name
+            ),
+            Phase::RemovingTargets {
+                name,
+                targets,
+                dry_run: true,
+            } => eprintln!(
+                "rust-std for `{}` would be removed from toolchain `{}`",
+                targets.join("`, `"),
+                name
+            ),
+            Phase::RemovingTargets { name, targets, .. } => eprintln!(
+                "removed rust-std for `{}` from toolchain `{}`",
+                targets.join("`, `"),
+                name
+            ),
+            Phase::RemovingComponents {
+                name,
+                components,
+                dry_run: true,
+            } => eprintln!(
+                "`{}` would be removed from toolchain `{}`",
+                components.join("`, `"),
+                name
+            ),
+            Phase::RemovingComponents {
+                name, components, ..
+            } => eprintln!(
+                "removed `{}` from toolchain `{}`",
+                components.join("`, `"),
+                name
+            ),
+            Phase::Pruning {
+                name,
+                reason,
+                dry_run: true,
+            } => eprintln!("toolchain `{}` would be removed ({})", name, reason),
+            Phase::Pruning { name, reason, .. } => {
+                eprintln!("removing toolchain `{}` ({})", name, reason);
+            }
+            Phase::RemovingTempDir {
+                path,
+                dry_run: true,
+            } => eprintln!("stale directory `{}` would be removed", path.display()),
+            Phase::RemovingTempDir { path, .. } => {
+                eprintln!("removing stale directory `{}`", path.display());
+            }
+            Phase::EvictingDownload {
+                path,
+                reason,
+                dry_run: true,
+            } => eprintln!("cached `{}` would be removed ({})", path.display(), reason),
+            Phase::EvictingDownload { path, reason, .. } => {
+                eprintln!("removing cached `{}` ({})", path.display(), reason);
+            }
+            Phase::Exporting {
+                name,
+                link_name,
+                dry_run: true,
+            } => eprintln!("toolchain `{}` would be linked as `{}`", name, link_name),
+            Phase::Exporting {
+                name, link_name, ..
+            } => eprintln!("linking toolchain `{}` as `{}`", name, link_name),
+            _ => {}
+        }
+    }
+
+    fn download_started(&self, length: Option<u64>) {
+        if !self.show_progress_bar {
+            return;
+        }
+        let mut progress_bar = ProgressBar::on(stderr(), length.unwrap_or(0));
+        progress_bar.set_units(Units::Bytes);
+        progress_bar.set_max_refresh_rate(Some(Duration::from_secs(1)));
+        *self.progress_bar.lock().unwrap() = Some(progress_bar);
+    }
+
+    fn bytes_read(&self, amount: u64) {
+        if let Some(progress_bar) = &mut *self.progress_bar.lock().unwrap() {
+            progress_bar.add(amount);
+        }
+    }
+
+    fn download_finished(&self) {
+        if let Some(mut progress_bar) = self.progress_bar.lock().unwrap().take() {
+            progress_bar.finish();
+            eprintln!();
+        }
+        if self.group_downloads {
+            end_group();
+        }
+    }
+
+    fn http_exchange(&self, exchange: &HttpExchange<'_>) {
+        if !self.trace_http {
+            return;
+        }
+        let mut trace = format!("> {} {}\n", exchange.method, exchange.url);
+        write_headers(&mut trace, "> ", exchange.request_headers);
+        let elapsed = exchange.elapsed.as_millis();
+        match &exchange.result {
+            Ok(response) => {
+                trace += &format!("< {} in {} ms", response.status, elapsed);
+                if response.url != exchange.url {
+                    trace += &format!(" from {}", response.url);
+                }
+                trace.push('\n');
+                write_headers(&mut trace, "< ", response.headers);
+            }
+            Err(err) => trace += &format!("< failed after {} ms: {}\n", elapsed, err),
+        }
+        eprint!("{}", trace);
+    }
+
+    fn warning(&self, warning: &Error) {
+        report_warn(warning);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Appends the `headers` to `trace` a line each, with the values of those
+/// carrying credentials hidden.
+fn write_headers(trace: &mut String, prefix: &str, headers: &HeaderMap) {
+    for (name, value) in headers {
+        let value = if [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE].contains(name) {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        trace.push_str(&format!("{}{}: {}\n", prefix, name, value));
+    }
+}
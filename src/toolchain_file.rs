@@ -0,0 +1,55 @@
+//! Reads the components and targets a project declares in its
+//! `rust-toolchain.toml`, for `--from-toolchain-file`.
+
+use std::env::current_dir;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Error};
+use serde::Deserialize;
+
+/// The file names rustup looks for in each directory, in its order.
+static TOOLCHAIN_FILE_NAMES: &[&str] = &["rust-toolchain", "rust-toolchain.toml"];
+
+#[derive(Deserialize, Debug)]
+struct ToolchainFileContent {
+    toolchain: ToolchainSection,
+}
+
+/// The `[toolchain]` section of a toolchain file. The channel, profile and
+/// path are ignored, since the commit to install is given separately.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct ToolchainSection {
+    pub components: Vec<String>,
+    pub targets: Vec<String>,
+}
+
+/// Finds the toolchain file of the project in the current directory or its
+/// ancestors as rustup does, and returns its path and `[toolchain]` section.
+///
+/// A legacy `rust-toolchain` file only naming the channel declares no
+/// components or targets.
+pub fn load_toolchain_file() -> Result<(PathBuf, ToolchainSection), Error> {
+    let cwd = current_dir()?;
+    for dir in cwd.ancestors() {
+        for name in TOOLCHAIN_FILE_NAMES {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let content = read_to_string(&path)
+                .with_context(|| format!("failed to read `{}`", path.display()))?;
+            if *name == "rust-toolchain" && !content.contains('[') {
+                return Ok((path, ToolchainSection::default()));
+            }
+            let file: ToolchainFileContent = toml::from_str(&content)
+                .with_context(|| format!("failed to parse `{}`", path.display()))?;
+            return Ok((path, file.toolchain));
+        }
+    }
+    bail!(
+        "no rust-toolchain.toml found in `{}` or its parent directories",
+        cwd.display()
+    );
+}
@@ -0,0 +1,93 @@
+//! Checks crates.io for a newer release of this tool once a day, since old
+//! releases stop working when the layout of the artifacts changes upstream.
+
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+
+static CRATE_URL: &str = "https://crates.io/api/v1/crates/rustup-toolchain-install-master";
+
+/// The result of the last check, stored in the cache.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct UpdateCheck {
+    checked_at: DateTime<Utc>,
+    latest: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CrateResponse {
+    #[serde(rename = "crate")]
+    krate: CrateInfo,
+}
+
+#[derive(Deserialize)]
+struct CrateInfo {
+    max_stable_version: Option<String>,
+}
+
+/// Prints a note if a newer release than this one is published, checking
+/// crates.io unless the result stored in `path` is less than a day old.
+///
+/// Any failure is ignored, the check must never get in the way of installing.
+pub fn check_for_update(client: &Client, path: &Path) {
+    let stored = read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<UpdateCheck>(&content).ok());
+    let latest = match stored {
+        Some(check) if Utc::now() - check.checked_at < chrono::Duration::days(1) => check.latest,
+        stored => {
+            // a failed check is not retried until tomorrow either, so an
+            // unreachable crates.io does not slow down every run.
+            let latest = fetch_latest(client).or_else(|| stored.and_then(|check| check.latest));
+            let check = UpdateCheck {
+                checked_at: Utc::now(),
+                latest,
+            };
+            if let (Some(parent), Ok(content)) = (path.parent(), serde_json::to_string(&check)) {
+                let _ = create_dir_all(parent).and_then(|()| write(path, content));
+            }
+            check.latest
+        }
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    if let Some(latest) = latest {
+        if is_newer(&latest, current) {
+            eprintln!(
+                "note: rustup-toolchain-install-master {} is available (this is {}), update with \
+                 `cargo install rustup-toolchain-install-master`",
+                latest, current
+            );
+        }
+    }
+}
+
+fn fetch_latest(client: &Client) -> Option<String> {
+    let response = client
+        .get(CRATE_URL)
+        .timeout(Duration::from_secs(3))
+        .send()
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    response
+        .json::<CrateResponse>()
+        .ok()?
+        .krate
+        .max_stable_version
+}
+
+/// Compares the numeric parts of two versions like `1.8.0`.
+fn is_newer(version: &str, than: &str) -> bool {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    parse(version) > parse(than)
+}
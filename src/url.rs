@@ -0,0 +1,161 @@
+/// The extensions of the archives probed for every artifact, in order of
+/// preference.
+pub static ARCHIVE_EXTENSIONS: &[&str] = &["tar.zst", "tar.xz", "tar.gz"];
+
+/// Returns the URL under which the artifacts of all CI builds are stored.
+pub fn builds_url(server: &str, alt: bool) -> String {
+    format!("{}/rustc-builds{}", server, if alt { "-alt" } else { "" })
+}
+
+/// Returns the prefix to use in place of [`builds_url`] for a server storing
+/// the artifacts at the URLs given by `template`.
+///
+/// `{server}` is replaced by `server`, and `{prefix}` by `builds_url`, usually
+/// the result of [`builds_url`]. `{commit}`, `{component}`, `{channel}`,
+/// `{target}` and `{ext}` are replaced for every artifact, where `{ext}` is
+/// each of [`ARCHIVE_EXTENSIONS`] in turn until the archive is found, and
+/// `{target}` is empty for `rust-src`.
+pub fn templated_builds_url(template: &str, server: &str, builds_url: &str) -> String {
+    template
+        .replace("{server}", server)
+        .replace("{prefix}", builds_url)
+}
+
+/// Returns the URL of the `.tar.xz` archive of `component` built from
+/// `commit`, exactly as requested when installing the toolchain.
+///
+/// `rust-src` is the only target-independent component, so `target` is
+/// ignored for it.
+pub fn artifact_url(
+    server: &str,
+    alt: bool,
+    commit: &str,
+    channel: &str,
+    component: &str,
+    target: &str,
+) -> String {
+    component_url(&builds_url(server, alt), commit, channel, component, target)
+}
+
+/// Same as [`artifact_url`], taking the result of [`builds_url`] or
+/// [`templated_builds_url`] as `prefix`.
+pub(crate) fn component_url(
+    prefix: &str,
+    commit: &str,
+    channel: &str,
+    component: &str,
+    target: &str,
+) -> String {
+    archive_url(prefix, commit, channel, component, target, "tar.xz")
+}
+
+/// Returns the URLs of the archive of the component for each of the
+/// [`ARCHIVE_EXTENSIONS`], without the duplicates of a template lacking
+/// `{ext}`.
+pub(crate) fn component_urls(
+    prefix: &str,
+    commit: &str,
+    channel: &str,
+    component: &str,
+    target: &str,
+) -> Vec<String> {
+    let mut urls = Vec::<String>::new();
+    for ext in ARCHIVE_EXTENSIONS {
+        let url = archive_url(prefix, commit, channel, component, target, ext);
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+fn archive_url(
+    prefix: &str,
+    commit: &str,
+    channel: &str,
+    component: &str,
+    target: &str,
+    ext: &str,
+) -> String {
+    // braces are never left unescaped in a plain URL.
+    if prefix.contains('{') {
+        prefix
+            .replace("{commit}", commit)
+            .replace("{component}", component)
+            .replace("{channel}", channel)
+            .replace(
+                "{target}",
+                if component == "rust-src" { "" } else { target },
+            )
+            .replace("{ext}", ext)
+    } else if component == "rust-src" {
+        format!("{}/{}/{}-{}.{}", prefix, commit, component, channel, ext)
+    } else {
+        format!(
+            "{}/{}/{}-{}-{}.{}",
+            prefix, commit, component, channel, target, ext
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_urls_prefer_zstd() {
+        let urls = component_urls(
+            "https://ci/rustc-builds",
+            "abc",
+            "nightly",
+            "rustc",
+            "x86_64",
+        );
+        assert_eq!(
+            urls,
+            [
+                "https://ci/rustc-builds/abc/rustc-nightly-x86_64.tar.zst",
+                "https://ci/rustc-builds/abc/rustc-nightly-x86_64.tar.xz",
+                "https://ci/rustc-builds/abc/rustc-nightly-x86_64.tar.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_src_has_no_target() {
+        assert_eq!(
+            archive_url(
+                "https://ci",
+                "abc",
+                "nightly",
+                "rust-src",
+                "x86_64",
+                "tar.xz"
+            ),
+            "https://ci/abc/rust-src-nightly.tar.xz"
+        );
+        assert_eq!(
+            archive_url(
+                "https://mirror/{commit}/{component}-{target}.{ext}",
+                "abc",
+                "nightly",
+                "rust-src",
+                "x86_64",
+                "tar.xz"
+            ),
+            "https://mirror/abc/rust-src-.tar.xz"
+        );
+    }
+
+    #[test]
+    fn templates_without_ext_are_probed_once() {
+        let urls = component_urls(
+            "https://mirror/{commit}/{component}",
+            "abc",
+            "nightly",
+            "rustc",
+            "x86_64",
+        );
+        assert_eq!(urls, ["https://mirror/abc/rustc"]);
+    }
+}
@@ -0,0 +1,89 @@
+//! Decompresses `.xz` streams with the multi-threaded decoder of liblzma,
+//! which decodes the blocks of a stream in parallel on the worker threads.
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread::available_parallelism;
+
+use liblzma::bufread::XzDecoder;
+use liblzma::stream::{Error, MtStreamBuilder, Stream, CONCATENATED};
+
+/// The memory the worker threads may use together before liblzma decodes
+/// with fewer of them.
+const MEMLIMIT_THREADING: u64 = 1 << 30;
+
+/// Returns a reader of the decompressed content of `reader`, decoding every
+/// concatenated stream.
+pub(crate) fn decoder<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let threads = available_parallelism().map_or(1, |n| n.get());
+    if threads == 1 {
+        Ok(Box::new(liblzma::read::XzDecoder::new_multi_decoder(
+            reader,
+        )))
+    } else {
+        Ok(Box::new(MultiStreamDecoder::new(reader, threads)?))
+    }
+}
+
+/// Creates the multi-threaded decoder of a single stream, falling back to the
+/// single-threaded one (of all the streams) if liblzma cannot start it.
+fn stream_decoder(threads: usize) -> Result<Stream, Error> {
+    MtStreamBuilder::new()
+        .threads(u32::try_from(threads).unwrap_or(u32::MAX))
+        .memlimit_threading(MEMLIMIT_THREADING)
+        .memlimit_stop(u64::MAX)
+        .decoder()
+        .or_else(|_| Stream::new_stream_decoder(u64::MAX, CONCATENATED))
+}
+
+/// Restarts the multi-threaded decoder after each stream, since it cannot
+/// decode concatenated streams by itself.
+struct MultiStreamDecoder<R> {
+    /// `None` after the last stream has been decoded.
+    decoder: Option<XzDecoder<BufReader<R>>>,
+    threads: usize,
+}
+
+impl<R: Read> MultiStreamDecoder<R> {
+    fn new(reader: R, threads: usize) -> Result<Self, Error> {
+        let stream = stream_decoder(threads)?;
+        Ok(Self {
+            decoder: Some(XzDecoder::new_stream(BufReader::new(reader), stream)),
+            threads,
+        })
+    }
+}
+
+impl<R: Read> Read for MultiStreamDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(decoder) = &mut self.decoder {
+            let len = decoder.read(buf)?;
+            if len > 0 || buf.is_empty() {
+                return Ok(len);
+            }
+            let mut reader = self.decoder.take().unwrap().into_inner();
+            if skip_padding(&mut reader)? {
+                let stream = stream_decoder(self.threads)?;
+                self.decoder = Some(XzDecoder::new_stream(reader, stream));
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Skips the null bytes padding the end of a stream, returning whether
+/// another stream follows.
+fn skip_padding(reader: &mut impl BufRead) -> io::Result<bool> {
+    loop {
+        let input = reader.fill_buf()?;
+        if input.is_empty() {
+            return Ok(false);
+        }
+        let padding = input.iter().take_while(|b| **b == 0).count();
+        let more = padding < input.len();
+        reader.consume(padding);
+        if more {
+            return Ok(true);
+        }
+    }
+}